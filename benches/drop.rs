@@ -0,0 +1,62 @@
+//! Measures the cost of dropping guards and groups, compared to calling the closure directly.
+//!
+//! Run with `cargo bench`, the overhead of the no-panic path over the baseline should stay within a few nanoseconds.
+//!
+//! The debug switch and hooks checks (a relaxed and an acquire atomic load) are part of every measured drop, they're
+//! within the noise of the measurement: removing them from `DeferGroup`'s drop saves ~1.4ns out of ~50ns, while dropping
+//! a `Defer` is as fast as the direct call either way. The last cases measure the slow path, taken while hooks are installed.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use defer_rs::{hooks, Defer, DeferExit, DeferGroup};
+
+const ITERATIONS: u32 = 10_000_000;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let nanos = start.elapsed().as_secs_f64() * 1e9 / f64::from(ITERATIONS);
+    println!("{name:<32} {nanos:>8.2}ns");
+}
+
+fn main() {
+    let counter = std::cell::Cell::new(0u64);
+    let increment = || counter.set(black_box(counter.get() + 1));
+
+    bench("baseline (direct call)", increment);
+    bench("Defer", || {
+        let _guard = Defer::new(increment);
+    });
+    bench("DeferExit", || {
+        let _guard = DeferExit::new(|_| increment());
+    });
+    bench("DeferGroup (1 closure)", || {
+        let mut group = DeferGroup::new();
+        group.add(Box::new(increment));
+    });
+    bench("DeferGroup (empty)", || {
+        let _group = black_box(DeferGroup::new());
+    });
+    hooks::on_execute(|event| {
+        black_box(event);
+    });
+    bench("Defer (hook installed)", || {
+        let _guard = Defer::new(increment);
+    });
+    bench("DeferGroup (hook installed)", || {
+        let mut group = DeferGroup::new();
+        group.add(Box::new(increment));
+    });
+    hooks::clear();
+    bench("Defer (hooks cleared)", || {
+        let _guard = Defer::new(increment);
+    });
+    black_box(counter.get());
+}
@@ -20,11 +20,80 @@ pub fn call_indexed(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     .into()
 }
 
+// Forwarded by `defer!(priority = N; ...)`, to register the closure on the group initialized by `defer_scope_init!`:
+// `macro_rules` hygiene is bypassed by resolving the group using the span of the (caller's) deferred code.
+// The group is reached through the macro imported by `defer_scope_init!` (see `scope_group_accessor`), so that a missing
+// initialization is reported by that macro's name, instead of as an unresolved `___deferred_code_group`
+#[doc(hidden)]
+#[proc_macro]
+pub fn defer_priority(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let mut tokens = input.into_iter();
+    let priority = match tokens.next() {
+        Some(proc_macro::TokenTree::Group(priority)) => priority.stream(),
+        _ => unreachable!("`defer!` always passes the priority in parentheses"),
+    };
+    let priority: syn::Expr = match syn::parse(priority) {
+        Ok(priority) => priority,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let deferred: proc_macro::TokenStream = tokens.collect();
+    let span = deferred
+        .clone()
+        .into_iter()
+        .next()
+        .map_or_else(proc_macro::Span::call_site, |tt| tt.span());
+    let accessor = syn::Ident::new(
+        "defer_scope_init_is_required_before_prioritized_defer",
+        span.into(),
+    );
+    let group = syn::Ident::new("___deferred_code_group", span.into());
+    let group = quote::quote!(#accessor!(#group));
+    let DeferStmtExpr { move_kw, deferred } = match syn::parse(deferred) {
+        Ok(stmt) => stmt,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    if cfg!(feature = "noop") {
+        return quote::quote! {
+            {
+                let _ = &mut #group;
+                let _ = #priority;
+                let _ = #move_kw || {
+                    #(#deferred)*;
+                };
+            }
+        }
+        .into();
+    }
+    quote::quote! {
+        {
+            #group.add_with_priority(#priority, ::std::boxed::Box::new(#move_kw || {
+                #(#deferred)*;
+            }));
+        }
+    }
+    .into()
+}
+
 struct DeferStmtExpr {
     move_kw: Option<syn::token::Move>,
     deferred: Vec<Stmt>,
 }
 
+// The `let name` argument of `defer_scope_init!`
+struct InitHandle {
+    name: syn::Ident,
+}
+
+impl Parse for InitHandle {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<syn::Token![let]>()?;
+        Ok(Self {
+            name: input.parse()?,
+        })
+    }
+}
+
 impl Parse for DeferStmtExpr {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         Ok(Self {
@@ -34,7 +103,6 @@ impl Parse for DeferStmtExpr {
     }
 }
 
-
 /// A macro for deferring execution of code until the closest scope containing a previously invoked [`defer_scope_init!`] macro ends.
 ///
 /// Use `defer_scope!` when you want to defer execution not to the end of the current active scope, but to the end of a larger parent scope. 
@@ -64,6 +132,116 @@ impl Parse for DeferStmtExpr {
 /// })));
 /// ```
 /// 
+/// ## Targeting an enclosing group:
+/// When groups are nested (using `defer_scope_init!(nested)` in the nested scopes), an enclosing group can be targeted by its depth,
+/// `1` being the innermost group (the default), `2` its parent, and so on.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// for i in 0..3 {
+///     defer_scope_init!(nested);
+///     defer_scope!(println!("Executed at the end of iteration #{i}."));
+///     defer_scope!(2: move println!("Executed once the loop is done, for iteration #{i}."));
+/// }
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// for i in 0..3 {
+///     let mut ___deferred_code_group = ::defer_rs::NestedDeferGroup::new(&mut ___deferred_code_group);
+///     ___deferred_code_group.add(Box::new(|| {
+///         println!("Executed at the end of iteration #{i}.");
+///     }));
+///     ___deferred_code_group.parent().add(Box::new(move || {
+///         println!("Executed once the loop is done, for iteration #{i}.");
+///     }));
+/// }
+/// ```
+///
+/// ## Appending to the group:
+/// By default, the deferred code is added to the start of the group's queue (using `add`), so it's executed before
+/// the code deferred earlier. Prefixing it with `push:` appends it to the end of the queue (using `push`) instead,
+/// so it's executed after the code deferred earlier. A depth can precede it, e.g. `defer_scope!(2: push: ...)`.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer_scope!(push: println!("This will be printed 1st."));
+/// defer_scope!(push: println!("This will be printed 2nd."));
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// ___deferred_code_group.push(Box::new(|| {
+///     println!("This will be printed 1st.");
+/// }));
+/// ___deferred_code_group.push(Box::new(|| {
+///     println!("This will be printed 2nd.");
+/// }));
+/// ```
+///
+/// ## Priorities:
+/// Prefixing the deferred code with `priority = N;` (`N` being an `i32` expression) queues it with that priority
+/// (using `add_with_priority`, or `push_with_priority` when followed by `push:`): code with a higher priority is executed first,
+/// regardless of the order of registration, and code deferred without a priority has a priority of `0`.
+/// This lets code deferred independently be ordered relative to each other. A depth can precede it, e.g. `defer_scope!(2: priority = 10; ...)`.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer_scope!(priority = -1; println!("This will be printed 3rd."));
+/// defer_scope!(println!("This will be printed 2nd."));
+/// defer_scope!(priority = 10; println!("This will be printed 1st."));
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// ___deferred_code_group.add_with_priority(-1, Box::new(|| {
+///     println!("This will be printed 3rd.");
+/// }));
+/// ___deferred_code_group.add(Box::new(|| {
+///     println!("This will be printed 2nd.");
+/// }));
+/// ___deferred_code_group.add_with_priority(10, Box::new(|| {
+///     println!("This will be printed 1st.");
+/// }));
+/// ```
+///
+/// ## Binding a handle to the deferred code:
+/// Prefixing the deferred code with `as name;` binds a [`RunOnce`](https://docs.rs/defer_rs/latest/defer_rs/struct.RunOnce.html) handle to the queued code to `name`,
+/// so it can be executed early (using `name.run()`), or cancelled (using `name.cancel()`), while it's still queued on the group.
+/// The prefix must come first, e.g. `defer_scope!(as name; 2: push: ...)`.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer_scope!(as drop_table; println!("Dropping the temporary table..."));
+/// defer_scope!(as unlock; println!("Releasing the lock..."));
+/// // The lock is released now, instead of when the scope exits
+/// unlock.run();
+/// // The table was kept, it must not be dropped
+/// drop_table.cancel();
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// let drop_table = ::defer_rs::RunOnce::new(|| {
+///     println!("Dropping the temporary table...");
+/// });
+/// ___deferred_code_group.add(Box::new(drop_table.callback()));
+/// let unlock = ::defer_rs::RunOnce::new(|| {
+///     println!("Releasing the lock...");
+/// });
+/// ___deferred_code_group.add(Box::new(unlock.callback()));
+/// unlock.run();
+/// drop_table.cancel();
+/// ```
+///
 /// Ignoring the ability to specify the scope and the need for invoking `defer_scope_init!` beforehand, 
 /// `defer_scope!` is otherwise identical to [`defer!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer.html).
 ///
@@ -76,39 +254,194 @@ impl Parse for DeferStmtExpr {
 // A proc_macro is used instead of `macro_rules` to bypass identifier hygiene
 #[proc_macro]
 pub fn defer_scope(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let (handle, input) = match split_as(input) {
+        Ok(split) => split,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let (depth, input) = match split_depth(input) {
+        Ok(split) => split,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let (priority, input) = match split_priority(input) {
+        Ok(split) => split,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let (push, input) = split_push(input);
+    let method = match (push, priority.is_some()) {
+        (false, false) => quote::quote!(add),
+        (true, false) => quote::quote!(push),
+        (false, true) => quote::quote!(add_with_priority),
+        (true, true) => quote::quote!(push_with_priority),
+    };
+    let priority = priority.map(|priority| quote::quote!(#priority,));
+    // Each level above the innermost group is reached through the `parent` of a `NestedDeferGroup`
+    let parents = (1..depth).map(|_| quote::quote!(.parent()));
+    let group = quote::quote!(___deferred_code_group #(#parents)*);
+
+    // With `defer-rs/noop`, the closure is only type checked, it's neither queued nor called
+    if cfg!(feature = "noop") {
+        let DeferStmtExpr { move_kw, deferred } = match syn::parse(input) {
+            Ok(stmt) => stmt,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        // The handle still executes the closure if it's explicitly run (unless skipped by `defer_rs::debug`)
+        return match handle {
+            Some(handle) => quote::quote! {
+                let _ = &mut #group;
+                let #handle = ::defer_rs::RunOnce::new(#move_kw || {
+                    #(#deferred)*;
+                });
+            },
+            None => quote::quote! {
+                {
+                    let _ = &mut #group;
+                    let _ = #move_kw || {
+                        #(#deferred)*;
+                    };
+                }
+            },
+        }
+        .into();
+    }
+
     let ast: syn::Result<syn::ExprCall> = syn::parse(input.clone());
-    if let Ok(call) = ast {
+    let (captured_args, closure) = if let Ok(call) = ast {
         let func = call.func;
         let args = call.args.iter();
         let i = (0..args.len()).map(syn::Index::from);
-        quote::quote! {
-
-            let ___deferred_code_captured_args = ( #( #args, )* );
-            {
-                ___deferred_code_group.add(::std::boxed::Box::new( move || {
+        (
+            Some(quote::quote! {
+                let ___deferred_code_captured_args = ( #( #args, )* );
+            }),
+            quote::quote! {
+                move || {
                     #func(#(___deferred_code_captured_args.#i, )*);
-                }));
-            }
-        }
-        .into()
+                }
+            },
+        )
     } else {
         let DeferStmtExpr { move_kw, deferred } = syn::parse(input).unwrap();
-        quote::quote! {
-            {
-                ___deferred_code_group.add(::std::boxed::Box::new(#move_kw || {
+        (
+            None,
+            quote::quote! {
+                #move_kw || {
                     #(#deferred)*;
-                }));
+                }
+            },
+        )
+    };
+    // The closure is queued directly, or through the `RunOnce` bound to the handle's name
+    match handle {
+        Some(handle) => quote::quote! {
+            #captured_args
+            let #handle = ::defer_rs::RunOnce::new(#closure);
+            {
+                #group.#method(#priority ::std::boxed::Box::new(#handle.callback()));
             }
+        },
+        None => quote::quote! {
+            #captured_args
+            {
+                #group.#method(#priority ::std::boxed::Box::new(#closure));
+            }
+        },
+    }
+    .into()
+}
+
+/// Splits the optional `as name;` prefix of a `defer_scope!` invocation from the rest of the input.
+fn split_as(
+    input: proc_macro::TokenStream,
+) -> syn::Result<(Option<syn::Ident>, proc_macro::TokenStream)> {
+    use proc_macro::TokenTree;
+
+    let mut tokens = input.clone().into_iter();
+    match (tokens.next(), tokens.next(), tokens.next()) {
+        (
+            Some(TokenTree::Ident(as_kw)),
+            Some(TokenTree::Ident(name)),
+            Some(TokenTree::Punct(semi)),
+        ) if as_kw.to_string() == "as" && semi.as_char() == ';' => Ok((
+            Some(syn::parse(TokenTree::Ident(name).into())?),
+            tokens.collect(),
+        )),
+        _ => Ok((None, input)),
+    }
+}
+
+/// Splits the optional `N:` depth prefix of a `defer_scope!` invocation from the rest of the input, the depth defaults to `1` (the innermost group).
+fn split_depth(input: proc_macro::TokenStream) -> syn::Result<(usize, proc_macro::TokenStream)> {
+    use proc_macro::{Spacing, TokenTree};
+
+    let mut tokens = input.clone().into_iter();
+    match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(lit)), Some(TokenTree::Punct(colon)))
+            if colon.as_char() == ':' && colon.spacing() == Spacing::Alone =>
+        {
+            let lit: syn::LitInt = syn::parse(TokenTree::Literal(lit).into())?;
+            let depth = lit.base10_parse::<usize>()?;
+            if depth == 0 {
+                return Err(syn::Error::new(
+                    lit.span(),
+                    "the depth of the targeted group starts at 1 (the innermost group)",
+                ));
+            }
+            Ok((depth, tokens.collect()))
+        }
+        _ => Ok((1, input)),
+    }
+}
+
+/// Splits the optional `priority = N;` prefix of a `defer_scope!` invocation from the rest of the input.
+///
+/// An input with nothing after the `;` isn't considered prefixed, it's an assignment to a `priority` variable.
+fn split_priority(
+    input: proc_macro::TokenStream,
+) -> syn::Result<(Option<syn::Expr>, proc_macro::TokenStream)> {
+    use proc_macro::{Spacing, TokenTree};
+
+    let mut tokens = input.clone().into_iter();
+    match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Ident(ident)), Some(TokenTree::Punct(eq)))
+            if ident.to_string() == "priority"
+                && eq.as_char() == '='
+                && eq.spacing() == Spacing::Alone =>
+        {
+            let priority: proc_macro::TokenStream = tokens
+                .by_ref()
+                .take_while(|tt| !matches!(tt, TokenTree::Punct(semi) if semi.as_char() == ';'))
+                .collect();
+            let rest: proc_macro::TokenStream = tokens.collect();
+            if rest.is_empty() {
+                return Ok((None, input));
+            }
+            Ok((Some(syn::parse(priority)?), rest))
         }
-        .into()
+        _ => Ok((None, input)),
     }
 }
 
+/// Splits the optional `push:` prefix of a `defer_scope!` invocation from the rest of the input.
+fn split_push(input: proc_macro::TokenStream) -> (bool, proc_macro::TokenStream) {
+    use proc_macro::{Spacing, TokenTree};
+
+    let mut tokens = input.clone().into_iter();
+    match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Ident(ident)), Some(TokenTree::Punct(colon)))
+            if ident.to_string() == "push"
+                && colon.as_char() == ':'
+                && colon.spacing() == Spacing::Alone =>
+        {
+            (true, tokens.collect())
+        }
+        _ => (false, input),
+    }
+}
 
 /// Initializes a [DeferGroup], which is an empty collection of closures to run at the end of the scope containing the invocation.
 /// It provides no functionality by itself and should be called before any [defer_scope!] invocation(s).
 /// 
-/// No arguments should be passed to the macro invocation.
+/// No arguments should be passed to the macro invocation, except for `nested` (see below).
 /// 
 /// # Usage
 /// 
@@ -120,6 +453,51 @@ pub fn defer_scope(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 /// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
 /// ```
 ///
+/// ## Invoking it more than once in a scope:
+/// Only one group can be initialized per scope, invoking `defer_scope_init!` again in the same scope is a compile error
+/// (rather than silently shadowing the first group, changing which group the following [defer_scope!] invocations target).
+/// To use another group, initialize it in a nested scope.
+///
+/// ```rust,compile_fail
+/// defer_rs::defer_scope_init!();
+/// defer_rs::defer_scope_init!(); // error[E0428]: the name `___DEFER_SCOPE_INIT_ALREADY_INVOKED_IN_THIS_SCOPE` is defined multiple times
+/// ```
+///
+/// ## Nested groups:
+/// A group initialized using `defer_scope_init!(nested)` (in a scope nested in the scope of another group) is a [NestedDeferGroup](https://docs.rs/defer_rs/latest/defer_rs/struct.NestedDeferGroup.html),
+/// borrowing the enclosing group, which can then be targeted by [defer_scope!] using its depth (e.g. `defer_scope!(2: ...)`).
+///
+/// ```rust
+/// defer_rs::defer_scope_init!();
+/// {
+///     defer_rs::defer_scope_init!(nested);
+/// }
+/// ```
+/// ## Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// {
+///     let mut ___deferred_code_group = ::defer_rs::NestedDeferGroup::new(&mut ___deferred_code_group);
+/// }
+/// ```
+///
+/// ## Binding a handle:
+/// `defer_scope_init!(let name)` also binds a [DeferScope](https://docs.rs/defer_rs/latest/defer_rs/struct.DeferScope.html) handle to `name`, sharing the group with [defer_scope!],
+/// so closures can be queued (or the group managed, e.g. rolled back to a savepoint) through direct method calls as well.
+///
+/// ```rust
+/// defer_rs::defer_scope_init!(let cleanup);
+/// defer_rs::defer_scope!(println!("Queued using the macro"));
+/// cleanup.add(Box::new(|| println!("Queued using the handle")));
+/// assert_eq!(cleanup.len(), 2);
+/// ```
+/// ## Expands to:
+/// ```rust
+/// let cleanup = ::defer_rs::DeferScope::new();
+/// #[allow(unused_mut)]
+/// let mut ___deferred_code_group = cleanup.clone();
+/// ```
+///
 /// For more detailed examples, refer to the documentation for [defer_scope!].
 ///
 /// See also: [`DeferGroup`](https://docs.rs/defer_rs/latest/defer_rs/struct.DeferGroup.html), [`defer_scope!`], and [`defer!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer.html).
@@ -128,10 +506,610 @@ pub fn defer_scope(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 // This is used to bypass `macro_rules` identifier hygiene
 #[proc_macro]
 pub fn defer_scope_init(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    if !input.is_empty() {
-        return quote::quote! {compile_error!("deferfn_init! doesn't take any arguments")}.into();
+    let init = if input.is_empty() {
+        quote::quote!(let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();)
+    } else if let Ok(InitHandle { name }) = syn::parse(input.clone()) {
+        quote::quote! {
+            let #name = ::defer_rs::DeferScope::new();
+            #[allow(unused_mut)]
+            let mut ___deferred_code_group = #name.clone();
+        }
+    } else {
+        match syn::parse::<syn::Ident>(input) {
+            Ok(ident) if ident == "nested" => quote::quote! {
+                let mut ___deferred_code_group = ::defer_rs::NestedDeferGroup::new(&mut ___deferred_code_group);
+            },
+            _ => return quote::quote! {compile_error!("defer_scope_init! only takes an optional `nested`, or `let name`, argument")}.into(),
+        }
+    };
+    let marker = scope_init_marker();
+    let accessor = scope_group_accessor();
+    quote::quote! {
+        #marker
+        #init
+        #accessor
+    }
+    .into()
+}
+
+/// Returns the item marking a scope whose group was already initialized, emitted along with every group binding
+/// (by `defer_scope_init!`, or injected by the other macros).
+///
+/// Items can't be defined twice in the same block (unlike `let` bindings, which are silently shadowed),
+/// so a second initialization in the same scope fails to compile, while initializations in nested scopes don't conflict.
+fn scope_init_marker() -> Stmt {
+    syn::parse_quote! {
+        #[allow(dead_code)]
+        const ___DEFER_SCOPE_INIT_ALREADY_INVOKED_IN_THIS_SCOPE: () = ();
+    }
+}
+
+/// Returns the import emitted along with every group binding, which `defer!(priority = N; ...)` reaches the group through.
+///
+/// When no group was initialized, the (only) error is that the macro, named after the requirement, can't be found.
+/// Unlike `macro_rules` definitions, the imports in nested scopes all refer to the same macro, so they don't conflict.
+fn scope_group_accessor() -> Stmt {
+    syn::parse_quote! {
+        #[allow(unused_imports)]
+        use ::defer_rs::__scope_group as defer_scope_init_is_required_before_prioritized_defer;
+    }
+}
+
+/// Turns a function into a test (like `#[test]`) whose body can register teardowns using [`defer_scope!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer_scope.html).
+///
+/// The test body is wrapped with a [`TeardownGroup`](https://docs.rs/defer_rs/latest/defer_rs/testing/struct.TeardownGroup.html) (taking the place of [`defer_scope_init!`]), which guarantees
+/// that every registered teardown is executed, even when an assertion fails (or a previous teardown panics),
+/// and reports which teardowns were executed (shown by the test harness for failed tests).
+///
+/// **Note: `#[test]` must not be added to the function, as `#[defer_test]` already adds it!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_test};
+///
+/// # fn create_temp_dir() -> std::path::PathBuf { std::env::temp_dir() }
+/// # fn remove_temp_dir(_: std::path::PathBuf) {}
+/// #[defer_test]
+/// fn creates_config_file() {
+///     let dir = create_temp_dir();
+///     // Arguments of a solitary function call are evaluated immediately, `dir` is cloned now
+///     defer_scope!(remove_temp_dir(dir.clone()));
+///
+///     assert!(dir.is_absolute());
+/// }
+/// ```
+///
+/// The closures registered using `defer_scope!` are stored in a group created before anything else in the test body,
+/// so (just like with `defer_scope_init!`), they can't borrow the test's local variables, and must use `move` (or the immediate
+/// evaluation of a function call's arguments) instead. As the group takes the place of `defer_scope_init!`, invoking it directly
+/// in the test body (rather than in a nested scope) is a compile error.
+///
+/// ```rust,ignore
+/// use defer_rs::{defer_scope_init, defer_test};
+///
+/// #[defer_test]
+/// fn registers_teardowns() {
+///     defer_scope_init!(); // error[E0428]: the name `___DEFER_SCOPE_INIT_ALREADY_INVOKED_IN_THIS_SCOPE` is defined multiple times
+/// }
+/// ```
+///
+/// A `defer!` invoked as the last statement of a block of the test body (executed right away, as if its code was written inline) is reported by a warning,
+/// see [`defer!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer.html).
+#[proc_macro_attribute]
+pub fn defer_test(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !args.is_empty() {
+        return quote::quote! {compile_error!("defer_test doesn't take any arguments");}.into();
+    }
+    let func = expand_defer_test(syn::parse_macro_input!(input as syn::ItemFn));
+    quote::quote!(#func).into()
+}
+
+fn expand_defer_test(mut func: syn::ItemFn) -> syn::ItemFn {
+    let name = func.sig.ident.to_string();
+    let stmts = &func.block.stmts;
+    let warnings = trailing_defers(stmts);
+    let marker = scope_init_marker();
+    let accessor = scope_group_accessor();
+    // The body's statements are spliced in the group's block, so invoking `defer_scope_init!` in the body is rejected
+    func.block = syn::parse_quote! {
+        {
+            #(#warnings)*
+            #marker
+            #accessor
+            let mut ___deferred_code_group = ::defer_rs::testing::TeardownGroup::new(#name);
+            #(#stmts)*
+        }
+    };
+    func.attrs.insert(0, syn::parse_quote!(#[test]));
+    func
+}
+
+/// Makes the `defer!` invocations of a function register their code into an implicit group per scope, instead of creating individual guards.
+///
+/// Every block of the function body invoking [`defer!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer.html) gets a [`DeferGroup`](https://docs.rs/defer_rs/latest/defer_rs/struct.DeferGroup.html),
+/// initialized right before its first `defer!`, and each `defer!` is turned into a [`defer_scope!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer_scope.html) queuing its code on that group.
+/// All the cleanups of a scope then share a single policy (e.g. the execution order, or the handling of panics in the executed closures)
+/// and instrumentation, and interoperate with `defer_scope!`, both queuing on the same group. If a block already invokes [`defer_scope_init!`]
+/// before its first `defer!`, that group is used instead of an implicit one, invoking it after the first `defer!` is a compile error.
+///
+/// ```rust,compile_fail
+/// use defer_rs::{defer, defer_scope_init, unified_defers};
+///
+/// #[unified_defers]
+/// fn handle_request() {
+///     defer!(println!("Request handled"));
+///     defer_scope_init!(); // error[E0428]: the name `___DEFER_SCOPE_INIT_ALREADY_INVOKED_IN_THIS_SCOPE` is defined multiple times
+/// }
+/// ```
+///
+/// The syntax of `defer!` is kept (including `move`, the immediate evaluation of a function call's arguments, and `priority = N;`),
+/// the only difference being that `defer!(as name; ...)` binds a [`RunOnce`](https://docs.rs/defer_rs/latest/defer_rs/struct.RunOnce.html) handle
+/// (like `defer_scope!`) rather than a `Defer` guard. As the code of every `defer!` of a block is executed when the group goes out of scope,
+/// it can't borrow local variables declared after the block's first `defer!`, and must use `move` instead.
+///
+/// Closures, `async` blocks, and nested items aren't rewritten, a `defer!` in their body keeps creating its own guard.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{defer, defer_scope, unified_defers};
+///
+/// #[unified_defers]
+/// fn handle_request(id: u32) {
+///     defer!(println!("Request #{id} handled, 3rd"));
+///     defer_scope!(push: println!("Flushing the logs, 4th"));
+///     defer!(priority = 10; println!("Closing the connection, 1st"));
+///     defer!(println!("Releasing the buffers, 2nd"));
+/// }
+///
+/// handle_request(1);
+/// ```
+/// ## Expands to:
+/// ```rust
+/// fn handle_request(id: u32) {
+///     let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+///     ::defer_rs::defer_scope!(println!("Request #{id} handled, 3rd"));
+///     ::defer_rs::defer_scope!(push: println!("Flushing the logs, 4th"));
+///     ::defer_rs::defer_scope!(priority = 10; println!("Closing the connection, 1st"));
+///     ::defer_rs::defer_scope!(println!("Releasing the buffers, 2nd"));
+/// }
+/// # handle_request(1);
+/// ```
+#[proc_macro_attribute]
+pub fn unified_defers(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !args.is_empty() {
+        return quote::quote! {compile_error!("unified_defers doesn't take any arguments");}.into();
+    }
+    let mut func = syn::parse_macro_input!(input as syn::ItemFn);
+    unify_block(&mut func.block.stmts);
+    quote::quote!(#func).into()
+}
+
+/// Lets a function returning a `Result` register error decorators using [`defer_err!`], which are only executed if it returns an `Err`.
+///
+/// An error decorator is a closure taking the outgoing error, and returning it wrapped (or annotated), e.g. with `anyhow`'s `context`.
+/// This adds "what we were doing" breadcrumbs to every error returned past the point a decorator is registered, without
+/// sprinkling `.context()` (or `.map_err()`) on every `?`. Decorators are executed in reverse order of registration (the most recently
+/// registered decorator wraps the error first), and are dropped without being executed if the function returns an `Ok`.
+///
+/// The function body is wrapped in a closure, whose (possibly early) return value is passed to an [`ErrorDecorators`](https://docs.rs/defer_rs/latest/defer_rs/struct.ErrorDecorators.html)
+/// stored before anything else in the function body, so the decorators can't borrow the function's local variables,
+/// and must use `move` instead. `async` functions aren't supported.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{defer_err, err_context};
+///
+/// #[err_context]
+/// fn load_config(path: &str) -> Result<String, String> {
+///     let path = path.to_owned();
+///     defer_err!(move |err| format!("failed to load the config from {path}: {err}"));
+///
+///     let contents = std::fs::read_to_string("/missing/config.toml").map_err(|err| err.to_string())?;
+///     defer_err!(|err| format!("failed to parse the config: {err}"));
+///     Ok(contents)
+/// }
+///
+/// let err = load_config("/missing/config.toml").unwrap_err();
+/// assert!(err.starts_with("failed to load the config from /missing/config.toml: "));
+/// ```
+///
+/// A `defer!` invoked as the last statement of a block of the function (executed right away, as if its code was written inline) is reported by a warning,
+/// see [`defer!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer.html).
+#[proc_macro_attribute]
+pub fn err_context(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !args.is_empty() {
+        return quote::quote! {compile_error!("err_context doesn't take any arguments");}.into();
+    }
+    let mut func = syn::parse_macro_input!(input as syn::ItemFn);
+    if let Some(asyncness) = func.sig.asyncness {
+        return syn::Error::new_spanned(asyncness, "err_context doesn't support `async` functions")
+            .to_compile_error()
+            .into();
+    }
+    let syn::ReturnType::Type(_, ret) = &func.sig.output else {
+        return syn::Error::new_spanned(
+            &func.sig,
+            "err_context can only be used on functions returning a `Result`",
+        )
+        .to_compile_error()
+        .into();
+    };
+    let block = &func.block;
+    let warnings = trailing_defers(&block.stmts);
+    func.block = syn::parse_quote! {
+        {
+            #(#warnings)*
+            let mut ___deferred_err_decorators = ::defer_rs::ErrorDecorators::new();
+            #[allow(clippy::redundant_closure_call)]
+            let ___deferred_code_result: #ret = (|| -> #ret #block)();
+            ___deferred_err_decorators.apply(___deferred_code_result)
+        }
+    };
+    quote::quote!(#func).into()
+}
+
+/// Registers an error decorator (a closure taking the outgoing error, and returning it wrapped) in a function marked with [`macro@err_context`].
+///
+/// See [`macro@err_context`] for more details.
+// This is used to bypass `macro_rules` identifier hygiene
+#[proc_macro]
+pub fn defer_err(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let decorator = syn::parse_macro_input!(input as syn::Expr);
+    quote::quote! {
+        ___deferred_err_decorators.add(#decorator);
+    }
+    .into()
+}
+
+/// Evaluates a block returning a `Result` with a [`TryDeferGroup`](https://docs.rs/defer_rs/latest/defer_rs/struct.TryDeferGroup.html) in scope,
+/// in which code can be deferred using [`defer_scope!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer_scope.html) (always executed),
+/// and [`errdefer!`] (only executed if the block doesn't complete with an `Ok`).
+///
+/// This brings the error-path semantics of [`macro@err_context`] to expression scopes: the block can exit early using `?`
+/// (or `return`), which exits the block only (not the enclosing function), and the code deferred so far is executed
+/// before the block's value is returned. Regular and error-only code is executed in reverse order of registration, interleaved.
+/// Error-only code is also executed if the block unwinds.
+///
+/// The block is wrapped in a closure, and the group is created outside of it, so (just like with [`macro@defer_test`]) the deferred code
+/// can't borrow the block's local variables, and must use `move` instead. The `Result` type may need to be annotated, e.g. on the binding of the value.
+/// As the group takes the place of `defer_scope_init!`, invoking it directly in the block (rather than in a nested scope) is a compile error.
+///
+/// ```rust,compile_fail
+/// use defer_rs::{defer_scope_init, try_defer_scope};
+///
+/// let res: Result<(), ()> = try_defer_scope! {
+///     defer_scope_init!(); // error[E0428]: the name `___DEFER_SCOPE_INIT_ALREADY_INVOKED_IN_THIS_SCOPE` is defined multiple times
+///     Ok(())
+/// };
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{defer_scope, errdefer, try_defer_scope};
+///
+/// let path = std::env::temp_dir().join("try_defer_scope_example.txt");
+/// let res: Result<(), std::io::Error> = try_defer_scope! {
+///     let mut file = std::fs::File::create(&path)?;
+///     defer_scope!(println!("Releasing the lock..."));
+///     errdefer!(std::fs::remove_file(&path).unwrap());
+///
+///     std::io::Write::write_all(&mut file, b"config")?;
+///     Err(std::io::Error::other("validation failed"))?;
+///     Ok(())
+/// };
+///
+/// assert!(res.is_err());
+/// assert!(!path.exists());
+/// ```
+/// ## Expands to:
+/// ```rust
+/// # let res: Result<(), ()> =
+/// {
+///     let mut ___deferred_code_group = ::defer_rs::TryDeferGroup::new();
+///     let ___deferred_code_result = (|| {
+///         // ... the block ...
+/// #       Ok(())
+///     })();
+///     ___deferred_code_group.finish(___deferred_code_result)
+/// }
+/// # ;
+/// ```
+///
+/// A `defer!` invoked as the last statement of a block of the block (executed right away, as if its code was written inline) is reported by a warning,
+/// see [`defer!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer.html).
+///
+/// See also: [`TryDeferGroup`](https://docs.rs/defer_rs/latest/defer_rs/struct.TryDeferGroup.html), [`errdefer!`], and [`macro@err_context`].
+#[proc_macro]
+pub fn try_defer_scope(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let body = match syn::parse::<BlockBody>(input) {
+        Ok(BlockBody(body)) => body,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let warnings = trailing_defers(&body);
+    let marker = scope_init_marker();
+    let accessor = scope_group_accessor();
+    quote::quote! {
+        {
+            #(#warnings)*
+            let mut ___deferred_code_group = ::defer_rs::TryDeferGroup::new();
+            #[allow(clippy::redundant_closure_call)]
+            let ___deferred_code_result = (|| {
+                // In the closure's body, so invoking `defer_scope_init!` in the block is rejected
+                #marker
+                #accessor
+                #(#body)*
+            })();
+            ___deferred_code_group.finish(___deferred_code_result)
+        }
+    }
+    .into()
+}
+
+/// Defers the execution of code until the closest enclosing [`try_defer_scope!`] block exits, only if it doesn't complete with an `Ok`.
+///
+/// Like [`defer_scope!`](https://docs.rs/defer_rs/latest/defer_rs/macro.defer_scope.html), the code can be prefixed with `move`,
+/// and with `push:` to append it to the end of the group's queue.
+///
+/// See [`try_defer_scope!`] for more details.
+// This is used to bypass `macro_rules` identifier hygiene
+#[proc_macro]
+pub fn errdefer(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let (push, input) = split_push(input);
+    let method = if push {
+        quote::quote!(push_on_err)
+    } else {
+        quote::quote!(add_on_err)
+    };
+    let DeferStmtExpr { move_kw, deferred } = match syn::parse(input) {
+        Ok(stmt) => stmt,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    // With `defer-rs/noop`, the closure is only type checked, it's neither queued nor called
+    if cfg!(feature = "noop") {
+        return quote::quote! {
+            {
+                let _ = &mut ___deferred_code_group;
+                let _ = #move_kw || {
+                    #(#deferred)*;
+                };
+            }
+        }
+        .into();
+    }
+    quote::quote! {
+        {
+            ___deferred_code_group.#method(::std::boxed::Box::new(#move_kw || {
+                #(#deferred)*;
+            }));
+        }
+    }
+    .into()
+}
+
+// The statements of a `try_defer_scope!` block (with, or without, its braces)
+struct BlockBody(Vec<Stmt>);
+
+impl Parse for BlockBody {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(Self(input.call(syn::Block::parse_within)?))
+    }
+}
+
+/// Returns a warning for each `defer!` invoked as the last statement of a block in `stmts` (or in the blocks nested in them),
+/// where the deferred code is executed right away, as if it was written inline.
+///
+/// Proc macros can't emit warnings on stable, so each warning is the use of a deprecated constant (`TRAILING_DEFER_RUNS_IMMEDIATELY`),
+/// spanned to the `defer!` invocation, which makes it deny-able using `#[deny(deprecated)]`. Closures (and async blocks) aren't checked,
+/// a `defer!` in their body may be intended to run when they return, and neither are `match` arms.
+fn trailing_defers(stmts: &[Stmt]) -> Vec<Stmt> {
+    let mut warnings = Vec::new();
+    check_block(stmts, &mut warnings);
+    warnings
+}
+
+fn check_block(stmts: &[Stmt], warnings: &mut Vec<Stmt>) {
+    let trailing = match stmts.last() {
+        Some(Stmt::Macro(stmt)) => Some(&stmt.mac),
+        Some(Stmt::Expr(syn::Expr::Macro(expr), _)) => Some(&expr.mac),
+        _ => None,
+    };
+    if let Some(mac) = trailing.filter(|mac| {
+        mac.path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "defer")
+    }) {
+        let span = syn::spanned::Spanned::span(&mac.path);
+        warnings.push(syn::parse_quote_spanned! {span=>
+            {
+                #[deprecated(note = "`defer!` is the last statement of its block, so the deferred code is executed right away, write it inline instead")]
+                const TRAILING_DEFER_RUNS_IMMEDIATELY: () = ();
+                let _ = TRAILING_DEFER_RUNS_IMMEDIATELY;
+            }
+        });
+    }
+    for stmt in stmts {
+        if let Stmt::Expr(expr, _) = stmt {
+            check_expr(expr, warnings);
+        }
+    }
+}
+
+fn check_expr(expr: &syn::Expr, warnings: &mut Vec<Stmt>) {
+    match expr {
+        syn::Expr::Block(expr) => check_block(&expr.block.stmts, warnings),
+        syn::Expr::Unsafe(expr) => check_block(&expr.block.stmts, warnings),
+        syn::Expr::Loop(expr) => check_block(&expr.body.stmts, warnings),
+        syn::Expr::While(expr) => check_block(&expr.body.stmts, warnings),
+        syn::Expr::ForLoop(expr) => check_block(&expr.body.stmts, warnings),
+        syn::Expr::If(expr) => {
+            check_block(&expr.then_branch.stmts, warnings);
+            if let Some((_, else_branch)) = &expr.else_branch {
+                check_expr(else_branch, warnings);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Turns the `defer!` invocations of `stmts` (and of the blocks nested in them) into `defer_scope!` invocations,
+/// initializing a group right before the first one, unless `defer_scope_init!` was invoked before it.
+fn unify_block(stmts: &mut Vec<Stmt>) {
+    let mut has_group = false;
+    let mut index = 0;
+    while index < stmts.len() {
+        let mac = match &mut stmts[index] {
+            Stmt::Macro(stmt) => Some(&mut stmt.mac),
+            Stmt::Expr(syn::Expr::Macro(expr), _) => Some(&mut expr.mac),
+            Stmt::Expr(expr, _) => {
+                unify_expr(expr);
+                None
+            }
+            _ => None,
+        };
+        match mac.as_ref().and_then(|mac| mac.path.segments.last()) {
+            Some(seg) if seg.ident == "defer_scope_init" => has_group = true,
+            Some(seg) if seg.ident == "defer" => {
+                let mac = mac.unwrap();
+                let span = syn::spanned::Spanned::span(&mac.path);
+                mac.path = syn::parse_quote_spanned!(span=> ::defer_rs::defer_scope);
+                if !has_group {
+                    has_group = true;
+                    stmts.insert(index, scope_init_marker());
+                    stmts.insert(index + 1, scope_group_accessor());
+                    stmts.insert(
+                        index + 2,
+                        syn::parse_quote! {
+                            let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+                        },
+                    );
+                    index += 3;
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+}
+
+fn unify_expr(expr: &mut syn::Expr) {
+    match expr {
+        syn::Expr::Block(expr) => unify_block(&mut expr.block.stmts),
+        syn::Expr::Unsafe(expr) => unify_block(&mut expr.block.stmts),
+        syn::Expr::Loop(expr) => unify_block(&mut expr.body.stmts),
+        syn::Expr::While(expr) => unify_block(&mut expr.body.stmts),
+        syn::Expr::ForLoop(expr) => unify_block(&mut expr.body.stmts),
+        syn::Expr::If(expr) => {
+            unify_block(&mut expr.then_branch.stmts);
+            if let Some((_, else_branch)) = &mut expr.else_branch {
+                unify_expr(else_branch);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests are only compiled with `cfg(test)`, which doctests aren't, so this can't be a `compile_fail` doctest
+    #[test]
+    fn test_defer_test_marks_the_scope() {
+        let func = expand_defer_test(syn::parse_quote! {
+            fn registers_teardowns() {
+                defer_rs::defer_scope_init!();
+            }
+        });
+        let marker = scope_init_marker();
+        let stmts: Vec<_> = func
+            .block
+            .stmts
+            .iter()
+            .map(|stmt| quote::quote!(#stmt).to_string())
+            .collect();
+        // The marker shares the body's block with the user's statements, so the second definition is rejected
+        assert_eq!(stmts.len(), 4);
+        assert_eq!(stmts[0], quote::quote!(#marker).to_string());
+        assert!(stmts[3].contains("defer_scope_init"));
+    }
+
+    #[test]
+    fn test_trailing_defers() {
+        let count = |block: syn::Block| trailing_defers(&block.stmts).len();
+        // Trailing in the body, and in nested blocks, branches and loops
+        assert_eq!(
+            count(syn::parse_quote!({
+                defer!(a());
+            })),
+            1
+        );
+        assert_eq!(
+            count(syn::parse_quote!({
+                if x {
+                    defer!(a());
+                } else {
+                    defer!(b())
+                }
+            })),
+            2
+        );
+        assert_eq!(
+            count(syn::parse_quote!({
+                for _ in 0..3 {
+                    defer!(a());
+                }
+                loop {
+                    unsafe {
+                        defer!(a());
+                    }
+                }
+            })),
+            2
+        );
+        // Followed by another statement, or in a closure, async block or `match` arm
+        assert_eq!(
+            count(syn::parse_quote!({
+                defer!(a());
+                b();
+            })),
+            0
+        );
+        assert_eq!(
+            count(syn::parse_quote!({
+                let f = || {
+                    defer!(a());
+                };
+                let g = async {
+                    defer!(a());
+                };
+                f()
+            })),
+            0
+        );
+        assert_eq!(
+            count(syn::parse_quote!({
+                match x {
+                    _ => {
+                        defer!(a());
+                    }
+                }
+            })),
+            0
+        );
     }
-    "let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();"
-        .parse()
-        .unwrap()
 }
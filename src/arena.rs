@@ -0,0 +1,419 @@
+use std::cell::{Cell, RefCell};
+use std::mem::{align_of, size_of, MaybeUninit};
+use std::ptr::NonNull;
+
+// The unit of allocation of the arena, which also bounds the alignment of the values it can hold
+#[repr(align(16))]
+struct Block(#[allow(dead_code)] [u8; 16]);
+
+const BLOCK_SIZE: usize = size_of::<Block>();
+const DEFAULT_CHUNK_BLOCKS: usize = 256;
+
+/// A bump arena the closures of [`ArenaDeferGroup`]s are allocated from, removing the heap allocation per queued closure.
+///
+/// Memory is allocated in chunks, which are only freed when the `DeferArena` is dropped, [`DeferArena::reset`] makes the
+/// memory reusable, e.g. by the groups of the next request handled by the same worker.
+///
+/// Closures whose alignment exceeds 16 bytes (or are zero-sized) are boxed instead.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{ArenaDeferGroup, DeferArena};
+///
+/// let mut arena = DeferArena::new();
+/// for request in 0..3 {
+///     {
+///         let mut defer_group = ArenaDeferGroup::new_in(&arena);
+///         defer_group.add(move || println!("Done handling request #{request}"));
+///         // ... handle the request ...
+///     }
+///     arena.reset();
+/// }
+/// ```
+pub struct DeferArena {
+    // Each chunk is a leaked `Box<[MaybeUninit<Block>]>`, raw pointers are used so handing out references into
+    // one chunk never invalidates the references previously handed out into it
+    chunks: RefCell<Vec<(NonNull<MaybeUninit<Block>>, usize)>>,
+    current: Cell<usize>,
+    used: Cell<usize>,
+}
+
+impl DeferArena {
+    /// Creates a new, empty `DeferArena`, memory is allocated once the first closure is queued.
+    pub const fn new() -> Self {
+        Self {
+            chunks: RefCell::new(Vec::new()),
+            current: Cell::new(0),
+            used: Cell::new(0),
+        }
+    }
+
+    /// Makes all the memory allocated by the arena reusable.
+    ///
+    /// This requires a mutable reference, so no group allocating from the arena can still be in scope.
+    pub fn reset(&mut self) {
+        self.current.set(0);
+        self.used.set(0);
+    }
+
+    /// Returns the total number of bytes allocated by the arena.
+    pub fn allocated_bytes(&self) -> usize {
+        self.chunks
+            .borrow()
+            .iter()
+            .map(|(_, len)| len * BLOCK_SIZE)
+            .sum()
+    }
+
+    // Moves `value` into the arena, handing it back if it can't be allocated from the arena
+    // (each allocation is disjoint, so handing out mutable references from a shared one is fine)
+    #[allow(clippy::mut_from_ref)]
+    fn alloc<T>(&self, value: T) -> Result<&mut T, T> {
+        if align_of::<T>() > BLOCK_SIZE || size_of::<T>() == 0 {
+            return Err(value);
+        }
+        let blocks = size_of::<T>().div_ceil(BLOCK_SIZE);
+
+        let mut chunks = self.chunks.borrow_mut();
+        let mut current = self.current.get();
+        let mut used = self.used.get();
+        // Find a chunk with enough space left, reusing the chunks left over from before a reset
+        loop {
+            match chunks.get(current) {
+                Some(&(_, len)) if len - used >= blocks => break,
+                Some(_) => {
+                    current += 1;
+                    used = 0;
+                }
+                None => {
+                    let chunk = Box::<[MaybeUninit<Block>]>::new_uninit_slice(
+                        blocks.max(DEFAULT_CHUNK_BLOCKS),
+                    );
+                    let len = chunk.len();
+                    // SAFETY: `Box::into_raw` never returns a null pointer
+                    let ptr = unsafe {
+                        NonNull::new_unchecked(Box::into_raw(chunk).cast::<MaybeUninit<Block>>())
+                    };
+                    chunks.push((ptr, len));
+                    used = 0;
+                    break;
+                }
+            }
+        }
+        self.current.set(current);
+        self.used.set(used + blocks);
+
+        // SAFETY: the `blocks` blocks starting at `used` are within the chunk, aren't handed out to anything else
+        // (until the arena is reset, which requires that no references to its values exist), and are properly aligned for `T`
+        unsafe {
+            let ptr = chunks[current].0.as_ptr().add(used).cast::<T>();
+            ptr.write(value);
+            Ok(&mut *ptr)
+        }
+    }
+}
+
+impl Default for DeferArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DeferArena {
+    fn drop(&mut self) {
+        for &(ptr, len) in self.chunks.get_mut().iter() {
+            // SAFETY: the chunk was created by `Box::into_raw` with this length, and nothing borrows from the arena anymore
+            drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len)) });
+        }
+    }
+}
+
+// A type-erased closure that can be called at most once through a mutable reference
+trait CallOnce {
+    fn call_once(&mut self);
+
+    // Drops the closure without calling it
+    fn discard(&mut self);
+}
+
+impl<F: FnOnce()> CallOnce for Option<F> {
+    fn call_once(&mut self) {
+        if let Some(f) = self.take() {
+            f();
+        }
+    }
+
+    fn discard(&mut self) {
+        self.take();
+    }
+}
+
+// A closure allocated from the arena, as the arena never drops the values it holds, the closure is dropped
+// (if it wasn't called) when this is dropped, just like a boxed closure would be
+struct ArenaFn<'a>(&'a mut (dyn CallOnce + 'a));
+
+impl<'a> Drop for ArenaFn<'a> {
+    fn drop(&mut self) {
+        self.0.discard();
+    }
+}
+
+enum Slot<'a> {
+    Arena(ArenaFn<'a>),
+    Boxed(Box<dyn FnOnce() + 'a>),
+}
+
+impl<'a> Slot<'a> {
+    fn call(self) {
+        match self {
+            Slot::Arena(f) => f.0.call_once(),
+            Slot::Boxed(f) => f(),
+        }
+    }
+}
+
+/// A [`DeferGroup`](crate::DeferGroup) whose closures are allocated from a [`DeferArena`].
+///
+/// The queued closures are executed first to last when the `ArenaDeferGroup` instance goes out of scope.
+///
+/// **Note: `ArenaDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// See also: [`DeferArena`], and [`DeferGroup`](crate::DeferGroup).
+#[must_use = "ArenaDeferGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct ArenaDeferGroup<'a> {
+    arena: &'a DeferArena,
+    slots: Vec<Slot<'a>>,
+}
+
+impl<'a> ArenaDeferGroup<'a> {
+    /// Creates a new `ArenaDeferGroup`, allocating its closures from `arena`.
+    ///
+    /// **Note: `ArenaDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub const fn new_in(arena: &'a DeferArena) -> Self {
+        Self {
+            arena,
+            slots: Vec::new(),
+        }
+    }
+
+    fn slot(&self, f: impl FnOnce() + 'a) -> Slot<'a> {
+        crate::hooks::registered("ArenaDeferGroup");
+        match self.arena.alloc(Some(f)) {
+            Ok(f) => Slot::Arena(ArenaFn(f)),
+            Err(f) => Slot::Boxed(Box::new(f.unwrap())),
+        }
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the `ArenaDeferGroup` queue.
+    ///
+    /// The closures queued in `ArenaDeferGroup` will be executed first to last
+    /// when the the `ArenaDeferGroup` instance goes out of scope.
+    pub fn add(&mut self, f: impl FnOnce() + 'a) {
+        let slot = self.slot(f);
+        self.slots.insert(0, slot);
+    }
+
+    /// Pushes a deferred closure to the end of the `ArenaDeferGroup` queue.
+    ///
+    /// The closures queued in `ArenaDeferGroup` will be executed first to last
+    /// when the the `ArenaDeferGroup` instance goes out of scope.
+    pub fn push(&mut self, f: impl FnOnce() + 'a) {
+        let slot = self.slot(f);
+        self.slots.push(slot);
+    }
+
+    /// Returns the number of queued closures.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns `true` if no closures are queued.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+impl<'a> Drop for ArenaDeferGroup<'a> {
+    fn drop(&mut self) {
+        self.run("ArenaDeferGroup");
+    }
+}
+
+impl<'a> ArenaDeferGroup<'a> {
+    // Executes the queued closures, keeping the capacity of the queue
+    fn run(&mut self, source: &'static str) {
+        if self.slots.is_empty()
+            || crate::debug::skipped(|| {
+                format!("{} deferred closure(s) of a `{source}`", self.slots.len())
+            })
+        {
+            self.slots.clear();
+            return;
+        }
+        // Closures queued while executing are dropped, as the group is being dropped
+        let mut slots = std::mem::take(&mut self.slots);
+        for slot in slots.drain(..) {
+            let _span = crate::hooks::executing_queued(source);
+            slot.call();
+        }
+        self.slots = slots;
+    }
+}
+
+/// A reusable, frame-scoped group for game loops (or any loop), whose memory is reused from one frame to the next.
+///
+/// Each call to [`FramePool::frame`] returns a [`Frame`], a group whose closures are executed (first to last) when it goes out
+/// of scope, e.g. at the end of the frame. The closures are allocated from the pool's [`DeferArena`], and the frame's queue
+/// is kept by the pool, so once the pool has grown to the frame's needs, deferring work doesn't allocate at all.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::FramePool;
+///
+/// let mut pool = FramePool::new();
+/// for frame_number in 0..3 {
+///     let mut frame = pool.frame();
+///     frame.add(move || println!("Presenting frame #{frame_number}..."));
+///     frame.push(|| println!("Releasing the frame's transient buffers..."));
+///     // ... update and render ...
+/// }
+/// ```
+#[derive(Default)]
+pub struct FramePool {
+    arena: DeferArena,
+    // Always empty, only its capacity is reused
+    slots: Vec<Slot<'static>>,
+}
+
+impl FramePool {
+    /// Creates a new, empty `FramePool`, memory is allocated once the first closure is queued.
+    pub const fn new() -> Self {
+        Self {
+            arena: DeferArena::new(),
+            slots: Vec::new(),
+        }
+    }
+
+    /// Starts a new frame, reusing the memory of the previous frames.
+    ///
+    /// **Note: `Frame` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn frame(&mut self) -> Frame<'_> {
+        self.arena.reset();
+        let slots = recycle(std::mem::take(&mut self.slots));
+        Frame {
+            group: ArenaDeferGroup {
+                arena: &self.arena,
+                slots,
+            },
+            recycled: &mut self.slots,
+        }
+    }
+
+    /// Returns the total number of bytes allocated by the pool's arena.
+    pub fn allocated_bytes(&self) -> usize {
+        self.arena.allocated_bytes()
+    }
+}
+
+// Reuses the allocation of an empty queue for a queue of closures with another lifetime
+fn recycle<'b>(mut slots: Vec<Slot<'_>>) -> Vec<Slot<'b>> {
+    slots.clear();
+    let mut slots = std::mem::ManuallyDrop::new(slots);
+    // SAFETY: `Slot`s only differ by lifetime, so the allocation has the right layout, and the vector is empty
+    unsafe { Vec::from_raw_parts(slots.as_mut_ptr().cast(), 0, slots.capacity()) }
+}
+
+/// A frame of a [`FramePool`], whose closures are executed (first to last) when it goes out of scope.
+///
+/// **Note: `Frame` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+#[must_use = "Frame MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct Frame<'p> {
+    group: ArenaDeferGroup<'p>,
+    recycled: &'p mut Vec<Slot<'static>>,
+}
+
+impl<'p> Frame<'p> {
+    /// Adds a deferred closure to the start (0-index) of the frame's queue.
+    pub fn add(&mut self, f: impl FnOnce() + 'p) {
+        self.group.add(f);
+    }
+
+    /// Pushes a deferred closure to the end of the frame's queue.
+    pub fn push(&mut self, f: impl FnOnce() + 'p) {
+        self.group.push(f);
+    }
+
+    /// Returns the number of queued closures.
+    pub fn len(&self) -> usize {
+        self.group.len()
+    }
+
+    /// Returns `true` if no closures are queued.
+    pub fn is_empty(&self) -> bool {
+        self.group.is_empty()
+    }
+}
+
+impl<'p> Drop for Frame<'p> {
+    fn drop(&mut self) {
+        self.group.run("Frame");
+        *self.recycled = recycle(std::mem::take(&mut self.group.slots));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+
+    #[test]
+    fn test_arena_defer_group() {
+        let rec = ExecutionRecorder::new();
+        let mut arena = DeferArena::new();
+        {
+            let mut group = ArenaDeferGroup::new_in(&arena);
+            group.push(rec.callback("2nd"));
+            group.add(rec.callback("1st"));
+            // Boxed, as it's zero-sized
+            group.push(|| {});
+            group.push(rec.callback("3rd"));
+            assert_eq!(group.len(), 4);
+        }
+        rec.assert_order(&["1st", "2nd", "3rd"]);
+
+        let allocated = arena.allocated_bytes();
+        arena.reset();
+        {
+            let mut group = ArenaDeferGroup::new_in(&arena);
+            for i in 0..DEFAULT_CHUNK_BLOCKS {
+                group.push(rec.callback(format!("#{i}")));
+            }
+        }
+        assert_eq!(rec.len(), 3 + DEFAULT_CHUNK_BLOCKS);
+        assert!(arena.allocated_bytes() > allocated);
+    }
+
+    #[test]
+    fn test_frame_pool_reuses_memory() {
+        let rec = ExecutionRecorder::new();
+        let mut pool = FramePool::new();
+        let mut allocated = None;
+        for frame_number in 0..3 {
+            let mut frame = pool.frame();
+            frame.push(rec.callback(format!("frame #{frame_number}")));
+            frame.add(rec.callback("first"));
+            drop(frame);
+
+            assert_eq!(
+                *allocated.get_or_insert(pool.allocated_bytes()),
+                pool.allocated_bytes()
+            );
+            assert!(pool.slots.capacity() >= 2);
+        }
+        rec.assert_order(&[
+            "first", "frame #0", "first", "frame #1", "first", "frame #2",
+        ]);
+    }
+}
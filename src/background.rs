@@ -0,0 +1,380 @@
+//! Offloading deferred cleanups to background threads.
+//!
+//! Expensive cleanups (e.g. `fsync`, or network calls) executed on drop block the code that triggered the scope exit.
+//! A [`DeferSpawn`] guard hands its closure to a freshly spawned thread instead, and returns immediately.
+//!
+//! The spawned threads are tracked by the crate, so the process can wait for all pending cleanups to finish before
+//! exiting, using [`join_all`].
+//!
+//! A [`DelayDefer`] guard executes its closure on a timer thread after a delay, unless it's dropped first, in which case the closure is executed right away.
+//!
+//! For fire-and-forget teardown on hot paths, where spawning a thread per cleanup is too expensive, [`Defer::detached`](crate::Defer::detached)
+//! guards enqueue their closure to a single, long-lived cleanup executor thread instead, which is drained (and stopped) using [`shutdown`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use defer_rs::{background, DeferSpawn};
+//!
+//! fn handle_request() {
+//!     let _flush = DeferSpawn::new(|| {
+//!         // ... flush the request's logs to disk ...
+//!     });
+//!     // ... handle the request ...
+//! }
+//!
+//! handle_request();
+//!
+//! // ... the rest of `main` ...
+//!
+//! // Wait for the cleanups still running in the background
+//! background::join_all().unwrap();
+//! ```
+
+use std::any::Any;
+use std::mem::ManuallyDrop;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::CleanupError;
+
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+// The panic payloads of the finished cleanups that were already joined
+static PANICKED: Mutex<Vec<Box<dyn Any + Send + 'static>>> = Mutex::new(Vec::new());
+
+/// A guard executing its closure on a background thread when it goes out of scope, instead of blocking the current thread.
+///
+/// The closure must be `Send` and `'static`, as it outlives the scope. The spawned thread is tracked, see [`join_all`].
+/// If the thread can't be spawned, the closure is executed on the current thread instead.
+///
+/// **Note: `DeferSpawn` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// See also: the [`background`](self) module, and [`Defer`](crate::Defer).
+#[must_use = "DeferSpawn MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct DeferSpawn<T: FnOnce() + Send + 'static>(ManuallyDrop<T>);
+
+impl<T: FnOnce() + Send + 'static> DeferSpawn<T> {
+    /// Creates a new `DeferSpawn` instance with the given deferred closure.
+    ///
+    /// **Note: `DeferSpawn` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub const fn new(deferred: T) -> Self {
+        Self(ManuallyDrop::new(deferred))
+    }
+}
+
+impl<T: FnOnce() + Send + 'static> Drop for DeferSpawn<T> {
+    fn drop(&mut self) {
+        // SAFETY: the closure is only taken here, and `drop` is never called more than once
+        let deferred = unsafe { ManuallyDrop::take(&mut self.0) };
+        if crate::debug::skipped(|| format!("deferred closure `{}`", std::any::type_name::<T>())) {
+            return;
+        }
+        let _span = crate::hooks::executing::<T>("DeferSpawn");
+        spawn(deferred);
+    }
+}
+
+fn spawn(f: impl FnOnce() + Send + 'static) {
+    // The closure is shared with the spawning attempt, so it can still be executed here if spawning fails
+    let f = std::sync::Arc::new(Mutex::new(Some(f)));
+    let task = f.clone();
+    let spawned = std::thread::Builder::new()
+        .name("defer-rs cleanup".into())
+        .spawn(move || {
+            let f = task.lock().unwrap_or_else(PoisonError::into_inner).take();
+            if let Some(f) = f {
+                f();
+            }
+        });
+
+    match spawned {
+        Ok(handle) => {
+            let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+            // Threads that are already done are joined now (which doesn't block), so `PENDING` doesn't grow indefinitely
+            let (finished, running) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition::<Vec<_>, _>(|handle| handle.is_finished());
+            *pending = running;
+            pending.push(handle);
+            drop(pending);
+
+            let panics = finished
+                .into_iter()
+                .filter_map(|handle| handle.join().err());
+            PANICKED
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend(panics);
+        }
+        Err(_) => {
+            let f = f.lock().unwrap_or_else(PoisonError::into_inner).take();
+            if let Some(f) = f {
+                f();
+            }
+        }
+    }
+}
+
+/// Waits for all the cleanups spawned by [`DeferSpawn`] guards to finish.
+///
+/// Cleanups spawned while `join_all` is waiting (e.g. by one of the cleanups) are waited for as well.
+/// If any of the cleanups panicked (since the previous `join_all` invocation), their panic payloads are returned.
+pub fn join_all() -> Result<(), CleanupError> {
+    let mut panics = std::mem::take(&mut *PANICKED.lock().unwrap_or_else(PoisonError::into_inner));
+    loop {
+        // The lock must not be held while joining, as the cleanups may spawn more cleanups
+        let handle = PENDING.lock().unwrap_or_else(PoisonError::into_inner).pop();
+        match handle {
+            Some(handle) => {
+                if let Err(payload) = handle.join() {
+                    panics.push(payload);
+                }
+            }
+            None => break,
+        }
+    }
+
+    if panics.is_empty() {
+        Ok(())
+    } else {
+        Err(CleanupError { panics })
+    }
+}
+
+/// A guard executing its closure after a delay, or when it goes out of scope, whichever comes first.
+///
+/// This is meant for "release this lease in at most 30 seconds" patterns: the closure is executed exactly once, either by a
+/// timer thread once the delay elapses (even though the guard is still in scope), or by the guard's drop if it happens earlier.
+///
+/// **Note: `DelayDefer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::DelayDefer;
+/// use std::time::Duration;
+///
+/// let lease = DelayDefer::new(Duration::from_secs(30), || println!("Lease released!"));
+///
+/// // ... use the leased resource for (at most) 30 seconds ...
+///
+/// // Done early, prints "Lease released!" now
+/// drop(lease);
+/// ```
+///
+/// See also: [`DeferSpawn`], and [`Defer`](crate::Defer).
+#[must_use = "DelayDefer MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct DelayDefer<T: FnOnce() + Send + 'static>(Arc<DelayState<T>>);
+
+struct DelayState<T> {
+    // The closure (until it's executed), and whether the guard was dropped
+    state: Mutex<(Option<T>, bool)>,
+    dropped: Condvar,
+}
+
+impl<T: FnOnce() + Send + 'static> DelayDefer<T> {
+    /// Creates a new `DelayDefer` instance, executing `deferred` once `delay` elapses (or the guard goes out of scope).
+    ///
+    /// **Note: `DelayDefer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub fn new(delay: Duration, deferred: T) -> Self {
+        let shared = Arc::new(DelayState {
+            state: Mutex::new((Some(deferred), false)),
+            dropped: Condvar::new(),
+        });
+
+        let timer = shared.clone();
+        let spawned = std::thread::Builder::new()
+            .name("defer-rs timer".into())
+            .spawn(move || {
+                let state = timer.state.lock().unwrap_or_else(PoisonError::into_inner);
+                let (mut state, _) = timer
+                    .dropped
+                    .wait_timeout_while(state, delay, |(_, dropped)| !*dropped)
+                    .unwrap_or_else(PoisonError::into_inner);
+                // If the guard was dropped, it already took the closure
+                let deferred = state.0.take();
+                // Decided before releasing the lock, so the closure is already skipped (or not) once `has_fired` returns `true`
+                let skipped = deferred.is_some()
+                    && crate::debug::skipped(|| {
+                        format!("deferred closure `{}`", std::any::type_name::<T>())
+                    });
+                drop(state);
+                if skipped {
+                    return;
+                }
+                if let Some(deferred) = deferred {
+                    let _span = crate::hooks::executing::<T>("DelayDefer");
+                    deferred();
+                }
+            });
+        // Without a timer thread, the closure is only executed when the guard goes out of scope
+        drop(spawned);
+
+        Self(shared)
+    }
+
+    /// Returns `true` if the closure was already executed (or is executing, or was [skipped](crate::debug)) due to the delay elapsing.
+    pub fn has_fired(&self) -> bool {
+        self.0
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+            .is_none()
+    }
+}
+
+impl<T: FnOnce() + Send + 'static> Drop for DelayDefer<T> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.1 = true;
+        let deferred = state.0.take();
+        drop(state);
+        self.0.dropped.notify_one();
+
+        if deferred.is_some()
+            && crate::debug::skipped(|| {
+                format!("deferred closure `{}`", std::any::type_name::<T>())
+            })
+        {
+            return;
+        }
+        if let Some(deferred) = deferred {
+            let _span = crate::hooks::executing::<T>("DelayDefer");
+            deferred();
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+type Panics = Vec<Box<dyn Any + Send + 'static>>;
+
+struct Executor {
+    jobs: Sender<Job>,
+    worker: JoinHandle<Panics>,
+}
+
+static EXECUTOR: Mutex<Option<Executor>> = Mutex::new(None);
+
+// Enqueues `job` to the cleanup executor, starting it if it isn't running
+pub(crate) fn enqueue(job: Job) {
+    let mut executor = EXECUTOR.lock().unwrap_or_else(PoisonError::into_inner);
+    let executor = executor.get_or_insert_with(|| {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let worker = std::thread::Builder::new()
+            .name("defer-rs executor".into())
+            .spawn(move || {
+                // A failing cleanup mustn't take the executor (and the cleanups queued after it) down
+                queue
+                    .into_iter()
+                    .filter_map(|job| catch_unwind(AssertUnwindSafe(job)).err())
+                    .collect()
+            })
+            .expect("failed to spawn the defer-rs cleanup executor thread");
+        Executor { jobs, worker }
+    });
+    // The worker only stops once the sender is dropped (by `shutdown`), so sending can't fail
+    let _ = executor.jobs.send(job);
+}
+
+/// Stops the cleanup executor of [`Defer::detached`](crate::Defer::detached) guards, after executing all the pending cleanups.
+///
+/// If any of the cleanups executed since the executor started panicked, their panic payloads are returned.
+/// The executor is started again (lazily) if another detached guard is dropped afterwards.
+///
+/// Unlike [`join_all`], `shutdown` doesn't wait for the cleanups spawned by [`DeferSpawn`] guards.
+pub fn shutdown() -> Result<(), CleanupError> {
+    let executor = EXECUTOR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    let Some(Executor { jobs, worker }) = executor else {
+        return Ok(());
+    };
+    // Closing the queue lets the worker return once it's drained
+    drop(jobs);
+    let panics = worker.join().unwrap_or_else(|payload| vec![payload]);
+
+    if panics.is_empty() {
+        Ok(())
+    } else {
+        Err(CleanupError { panics })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+
+    #[test]
+    #[cfg_attr(
+        feature = "noop",
+        ignore = "waits for a deferred closure, which `noop` compiles out"
+    )]
+    fn test_defer_spawn_join_all() {
+        let rec = ExecutionRecorder::new();
+        {
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+            let waiter = barrier.clone();
+            let _guard = DeferSpawn::new({
+                let rec = rec.clone();
+                move || {
+                    waiter.wait();
+                    rec.record("background");
+                }
+            });
+            drop(_guard);
+            // The guard didn't block on the cleanup, which is waiting for the current thread
+            rec.record("foreground");
+            barrier.wait();
+        }
+
+        join_all().unwrap();
+        rec.assert_order(&["foreground", "background"]);
+    }
+
+    #[test]
+    #[cfg_attr(
+        feature = "noop",
+        ignore = "waits for a deferred closure, which `noop` compiles out"
+    )]
+    fn test_delay_defer() {
+        let rec = ExecutionRecorder::new();
+
+        let guard = DelayDefer::new(Duration::from_millis(10), rec.callback("delay elapsed"));
+        while !guard.has_fired() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(guard);
+
+        let guard = DelayDefer::new(Duration::from_secs(3600), rec.callback("dropped"));
+        assert!(!guard.has_fired());
+        drop(guard);
+
+        // The first closure may still be executing on the timer thread
+        while rec.len() < 2 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        rec.assert_contains("delay elapsed");
+        rec.assert_contains("dropped");
+        assert_eq!(rec.len(), 2);
+    }
+
+    #[test]
+    fn test_detached_defer_shutdown() {
+        let rec = ExecutionRecorder::new();
+        for i in 0..4 {
+            let _guard = crate::Defer::detached(rec.callback(format!("#{i}")));
+        }
+        let _guard = crate::Defer::detached(|| panic!("detached cleanup failed"));
+        drop(_guard);
+
+        let err = shutdown().unwrap_err();
+        assert!(err.messages().any(|m| m == "detached cleanup failed"));
+        rec.assert_order(&["#0", "#1", "#2", "#3"]);
+    }
+}
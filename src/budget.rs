@@ -0,0 +1,492 @@
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A time budget for executing queued cleanups, see [`DeferGroup::run_with_budget`](crate::DeferGroup::run_with_budget)
+/// and [`registry::run_all_with_budget`](crate::registry::run_all_with_budget).
+///
+/// A budget can limit the time each cleanup is allowed to take, the time the whole run is allowed to take, or both.
+/// What happens when a limit is exceeded is decided by the budget's [`Overrun`] policy (defaults to [`Overrun::Continue`]).
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{Budget, Overrun};
+/// use std::time::Duration;
+///
+/// let budget = Budget::per_entry(Duration::from_millis(100))
+///     .with_total(Duration::from_secs(5))
+///     .on_overrun(Overrun::SkipRest);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    per_entry: Option<Duration>,
+    total: Option<Duration>,
+    on_overrun: Overrun,
+}
+
+/// What to do once a cleanup exceeds its [`Budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overrun {
+    /// Record the overrun in the returned [`RunReport`], and move on to the next cleanup.
+    ///
+    /// Once the whole run's limit is exceeded, there's no budget left for the next cleanups, they're skipped (dropped without being executed)
+    /// instead of being started (and immediately overrunning).
+    #[default]
+    Continue,
+    /// Record the overrun in the returned [`RunReport`], and skip (drop without executing) the remaining cleanups.
+    SkipRest,
+    /// Print a message to `stderr` and abort the process, as soon as the limit is exceeded (even if the cleanup never returns).
+    Abort,
+}
+
+impl Budget {
+    /// Creates a budget limiting the time each cleanup is allowed to take.
+    pub const fn per_entry(limit: Duration) -> Self {
+        Self {
+            per_entry: Some(limit),
+            total: None,
+            on_overrun: Overrun::Continue,
+        }
+    }
+
+    /// Creates a budget limiting the time the whole run is allowed to take.
+    pub const fn total(limit: Duration) -> Self {
+        Self {
+            per_entry: None,
+            total: Some(limit),
+            on_overrun: Overrun::Continue,
+        }
+    }
+
+    /// Sets the limit on the time each cleanup is allowed to take.
+    pub const fn with_per_entry(mut self, limit: Duration) -> Self {
+        self.per_entry = Some(limit);
+        self
+    }
+
+    /// Sets the limit on the time the whole run is allowed to take.
+    pub const fn with_total(mut self, limit: Duration) -> Self {
+        self.total = Some(limit);
+        self
+    }
+
+    /// Sets the policy applied once a limit is exceeded.
+    pub const fn on_overrun(mut self, on_overrun: Overrun) -> Self {
+        self.on_overrun = on_overrun;
+        self
+    }
+
+    // The instant by which the cleanup started at `entry_start` must be done
+    fn deadline(&self, run_start: Instant, entry_start: Instant) -> Option<Instant> {
+        let per_entry = self.per_entry.map(|limit| entry_start + limit);
+        let total = self.total.map(|limit| run_start + limit);
+        match (per_entry, total) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // Whether the whole run's limit was already exceeded, leaving no budget for the next cleanup
+    fn is_spent(&self, run_start: Instant) -> bool {
+        self.total.is_some_and(|limit| run_start.elapsed() >= limit)
+    }
+}
+
+/// A summary of a cleanup run (e.g. a budgeted one, or [`DeferGroup::run_now`](crate::DeferGroup::run_now)), telling whether it was clean.
+///
+/// Cleanups are identified by their position in the run's execution order (0-indexed). The summary is printed (e.g. logged at shutdown)
+/// using its `Display` implementation.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::DeferGroup;
+///
+/// let mut defer_group = DeferGroup::new();
+/// defer_group.push(Box::new(|| println!("Closing the connection...")));
+/// defer_group.push(Box::new(|| panic!("failed to remove the temp dir")));
+///
+/// let report = defer_group.run_now();
+/// assert_eq!(report.executed(), 2);
+/// assert_eq!(report.panicked(), [1]);
+/// eprintln!("Shutdown: {report}");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+    executed: usize,
+    overran: Vec<usize>,
+    skipped: usize,
+    panicked: Vec<usize>,
+    durations: Vec<Duration>,
+}
+
+impl RunReport {
+    /// Returns the number of cleanups that were executed (including the ones that overran their budget).
+    pub fn executed(&self) -> usize {
+        self.executed
+    }
+
+    /// Returns the positions of the cleanups that exceeded their budget.
+    pub fn overran(&self) -> &[usize] {
+        &self.overran
+    }
+
+    /// Returns the number of cleanups that were dropped without being executed.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Returns the positions of the cleanups that panicked (the panic was caught, and the run continued).
+    pub fn panicked(&self) -> &[usize] {
+        &self.panicked
+    }
+
+    /// Returns the time each executed cleanup took (or ran for, before the run moved on from it), in order of execution.
+    pub fn durations(&self) -> &[Duration] {
+        &self.durations
+    }
+
+    /// Returns the total time the executed cleanups took.
+    pub fn duration(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+
+    /// Returns `true` if every cleanup was executed within the budget, without panicking.
+    pub fn is_clean(&self) -> bool {
+        self.overran.is_empty() && self.skipped == 0 && self.panicked.is_empty()
+    }
+}
+
+impl RunReport {
+    // A report of a run whose cleanups were all skipped (see `debug::skip_cleanup`)
+    pub(crate) fn skipped_all(skipped: usize) -> Self {
+        Self {
+            skipped,
+            ..Self::default()
+        }
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "executed {} cleanup(s) in {:?}, {} overran their budget, {} panicked, {} skipped",
+            self.executed,
+            self.duration(),
+            self.overran.len(),
+            self.panicked.len(),
+            self.skipped
+        )
+    }
+}
+
+// Runs `entries` in order on the current thread, catching (and recording) the panics, so every cleanup is executed
+pub(crate) fn run_reported<'a>(
+    entries: impl IntoIterator<Item = Box<dyn FnOnce() + 'a>>,
+    source: &'static str,
+) -> RunReport {
+    let mut report = RunReport::default();
+    for (index, f) in entries.into_iter().enumerate() {
+        let _span = crate::hooks::executing_queued(source);
+        let start = Instant::now();
+        if catch_unwind(AssertUnwindSafe(f)).is_err() {
+            report.panicked.push(index);
+        }
+        report.durations.push(start.elapsed());
+        report.executed += 1;
+    }
+    report
+}
+
+fn abort_overrun(index: usize) -> ! {
+    eprintln!("defer-rs: deferred cleanup #{index} exceeded its time budget, aborting!");
+    std::process::abort()
+}
+
+// A thread aborting the process once the current deadline passes, used to enforce `Overrun::Abort` on closures that can't be moved to another thread
+struct Watchdog(Sender<Option<(usize, Instant)>>);
+
+impl Watchdog {
+    fn spawn() -> Self {
+        let (tx, rx) = mpsc::channel::<Option<(usize, Instant)>>();
+        thread::spawn(move || {
+            let mut current: Option<(usize, Instant)> = None;
+            loop {
+                let msg = match current {
+                    Some((_, deadline)) => {
+                        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match msg {
+                    Ok(next) => current = next,
+                    Err(RecvTimeoutError::Timeout) => abort_overrun(current.unwrap().0),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        Self(tx)
+    }
+
+    fn watch(&self, index: usize, deadline: Instant) {
+        // The watchdog can only hang up by aborting the process
+        let _ = self.0.send(Some((index, deadline)));
+    }
+
+    // Called once the watched cleanup returns, so the watchdog doesn't fire while the run moves on to the next one
+    fn clear(&self) {
+        let _ = self.0.send(None);
+    }
+}
+
+// Runs `entries` in order on the current thread, overruns can only be detected once the cleanup returns (or enforced by aborting the process)
+pub(crate) fn run_local<'a>(
+    entries: impl IntoIterator<Item = Box<dyn FnOnce() + 'a>>,
+    budget: &Budget,
+    source: &'static str,
+) -> RunReport {
+    let watchdog = (budget.on_overrun == Overrun::Abort).then(Watchdog::spawn);
+    let run_start = Instant::now();
+    let mut report = RunReport::default();
+    let mut entries = entries.into_iter();
+
+    for (index, f) in entries.by_ref().enumerate() {
+        if budget.is_spent(run_start) {
+            if budget.on_overrun == Overrun::Abort {
+                abort_overrun(index);
+            }
+            report.skipped += 1;
+            break;
+        }
+        let entry_start = Instant::now();
+        let deadline = budget.deadline(run_start, entry_start);
+        if let (Some(watchdog), Some(deadline)) = (&watchdog, deadline) {
+            watchdog.watch(index, deadline);
+        }
+
+        let _span = crate::hooks::executing_queued(source);
+        f();
+        if let Some(watchdog) = &watchdog {
+            watchdog.clear();
+        }
+        report.executed += 1;
+        report.durations.push(entry_start.elapsed());
+
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            report.overran.push(index);
+            match budget.on_overrun {
+                Overrun::Continue => {}
+                Overrun::SkipRest => break,
+                Overrun::Abort => abort_overrun(index),
+            }
+        }
+    }
+
+    report.skipped += entries.count();
+    report
+}
+
+// Runs `entries` in order, each on its own thread when a limit applies, so the run can move on from a cleanup that overran its budget without waiting for it to return
+pub(crate) fn run_detachable(
+    mut next_entry: impl FnMut() -> Option<Box<dyn FnOnce() + Send + 'static>>,
+    budget: &Budget,
+    source: &'static str,
+) -> RunReport {
+    let run_start = Instant::now();
+    let mut report = RunReport::default();
+    let mut index = 0;
+
+    while let Some(f) = next_entry() {
+        // No cleanup is spawned once there's no budget left for it, it would be reported as overrunning right away,
+        // while running concurrently with (and out of order of) the next ones
+        if budget.is_spent(run_start) {
+            if budget.on_overrun == Overrun::Abort {
+                abort_overrun(index);
+            }
+            report.skipped += 1;
+            while next_entry().is_some() {
+                report.skipped += 1;
+            }
+            break;
+        }
+        let _span = crate::hooks::executing_queued(source);
+        let entry_start = Instant::now();
+        let Some(deadline) = budget.deadline(run_start, entry_start) else {
+            f();
+            report.executed += 1;
+            report.durations.push(entry_start.elapsed());
+            index += 1;
+            continue;
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            f();
+            let _ = tx.send(());
+        });
+        report.executed += 1;
+
+        let res = rx.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        report.durations.push(entry_start.elapsed());
+        match res {
+            Ok(()) => {
+                let _ = handle.join();
+            }
+            // The sender was dropped without sending, i.e. the cleanup panicked
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Err(payload) = handle.join() {
+                    std::panic::resume_unwind(payload);
+                }
+            }
+            // The cleanup is left running on its (detached) thread
+            Err(RecvTimeoutError::Timeout) => {
+                report.overran.push(index);
+                match budget.on_overrun {
+                    Overrun::Continue => {}
+                    Overrun::SkipRest => {
+                        while next_entry().is_some() {
+                            report.skipped += 1;
+                        }
+                        break;
+                    }
+                    Overrun::Abort => abort_overrun(index),
+                }
+            }
+        }
+        index += 1;
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeferGroup;
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_run_local_records_overruns_and_skips() {
+        let ran = Cell::new(0);
+        let slow = || {
+            thread::sleep(Duration::from_millis(20));
+            ran.set(ran.get() + 1);
+        };
+
+        let mut group = DeferGroup::new();
+        group.push(Box::new(|| ran.set(ran.get() + 1)));
+        group.push(Box::new(slow));
+        group.push(Box::new(|| ran.set(ran.get() + 1)));
+        let report = group.run_with_budget(Budget::per_entry(Duration::from_millis(5)));
+        assert_eq!(report.executed(), 3);
+        assert_eq!(report.overran(), [1]);
+        assert_eq!(ran.get(), 3);
+
+        let mut group = DeferGroup::new();
+        group.push(Box::new(slow));
+        group.push(Box::new(|| ran.set(ran.get() + 1)));
+        group.push(Box::new(|| ran.set(ran.get() + 1)));
+        let report = group.run_with_budget(
+            Budget::per_entry(Duration::from_millis(5)).on_overrun(Overrun::SkipRest),
+        );
+        assert_eq!(report.executed(), 1);
+        assert_eq!(report.skipped(), 2);
+        assert!(!report.is_clean());
+        assert_eq!(ran.get(), 4);
+    }
+
+    #[test]
+    fn test_run_now_reports_panics() {
+        let ran = Cell::new(0);
+        let mut group = DeferGroup::new();
+        group.push(Box::new(|| panic!("expected")));
+        group.push(Box::new(|| {
+            thread::sleep(Duration::from_millis(5));
+            ran.set(ran.get() + 1);
+        }));
+        let report = group.run_now();
+        assert_eq!(report.executed(), 2);
+        assert_eq!(report.panicked(), [0]);
+        assert_eq!(report.durations().len(), 2);
+        assert!(report.duration() >= Duration::from_millis(5));
+        assert!(!report.is_clean());
+        assert_eq!(ran.get(), 1);
+        assert!(report.to_string().contains("1 panicked"));
+    }
+
+    #[test]
+    fn test_spent_total_budget_skips_the_rest() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let entry = |ran: &Arc<AtomicUsize>, sleep| {
+            let ran = ran.clone();
+            Box::new(move || {
+                thread::sleep(Duration::from_millis(sleep));
+                ran.fetch_add(1, Ordering::SeqCst);
+            }) as Box<dyn FnOnce() + Send>
+        };
+        let budget = Budget::total(Duration::from_millis(10));
+
+        let mut entries = vec![entry(&ran, 0), entry(&ran, 0), entry(&ran, 30)];
+        let report = run_detachable(|| entries.pop(), &budget, "test");
+        assert_eq!(report.executed(), 1);
+        assert_eq!(report.overran(), [0]);
+        assert_eq!(report.skipped(), 2);
+
+        let entries = vec![entry(&ran, 30), entry(&ran, 0), entry(&ran, 0)];
+        let report = run_local(
+            entries.into_iter().map(|f| f as Box<dyn FnOnce()>),
+            &budget,
+            "test",
+        );
+        assert_eq!(report.executed(), 1);
+        assert_eq!(report.overran(), [0]);
+        assert_eq!(report.skipped(), 2);
+
+        // Only the overrunning cleanups ran, the detached one included
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(ran.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_run_local_abort_ignores_finished_cleanups() {
+        // Moving on to the next cleanup outlasts the previous one's deadline, which it met
+        let slow_to_yield = (0..2).map(|index| {
+            if index > 0 {
+                thread::sleep(Duration::from_millis(30));
+            }
+            Box::new(|| {}) as Box<dyn FnOnce()>
+        });
+        let report = run_local(
+            slow_to_yield,
+            &Budget::per_entry(Duration::from_millis(10)).on_overrun(Overrun::Abort),
+            "test",
+        );
+        assert_eq!(report.executed(), 2);
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn test_run_detachable_moves_on_from_hung_cleanup() {
+        let mut entries: Vec<Box<dyn FnOnce() + Send>> = vec![
+            Box::new(|| thread::sleep(Duration::from_secs(60))),
+            Box::new(|| {}),
+        ];
+        entries.reverse();
+
+        let start = Instant::now();
+        let report = run_detachable(
+            || entries.pop(),
+            &Budget::total(Duration::from_secs(30)).with_per_entry(Duration::from_millis(10)),
+            "test",
+        );
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(report.executed(), 2);
+        assert_eq!(report.overran(), [0]);
+    }
+}
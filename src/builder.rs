@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+use crate::{DeferGroup, PanicPolicy, Strategy};
+
+/// A builder configuring a [`DeferGroup`] in a single expression, created using [`DeferGroup::builder`].
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{DeferGroup, PanicPolicy, Strategy};
+///
+/// let mut defer_group = DeferGroup::builder()
+///     .fifo()
+///     .on_success()
+///     .capacity(16)
+///     .panic_policy(PanicPolicy::Continue)
+///     .name("request teardown")
+///     .build();
+/// defer_group.add(Box::new(|| println!("This will be printed 1st")));
+/// defer_group.add(Box::new(|| println!("This will be printed 2nd")));
+/// assert_eq!(defer_group.strategy(), Strategy::OnSuccess);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeferGroupBuilder {
+    strategy: Strategy,
+    capacity: usize,
+    fifo: bool,
+    panic_policy: PanicPolicy,
+    name: &'static str,
+}
+
+impl DeferGroupBuilder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            strategy: Strategy::Always,
+            capacity: 0,
+            fifo: false,
+            panic_policy: PanicPolicy::Stop,
+            name: "DeferGroup",
+        }
+    }
+
+    /// Executes the queue first to last when the group goes out of scope, so closures queued using [`DeferGroup::add`]
+    /// (e.g. by [`defer_scope!`](crate::defer_scope)) are executed last to first, this is the default.
+    pub const fn lifo(mut self) -> Self {
+        self.fifo = false;
+        self
+    }
+
+    /// Executes the queue last to first when the group goes out of scope, so closures queued using [`DeferGroup::add`]
+    /// (e.g. by [`defer_scope!`](crate::defer_scope)) are executed in the order they were queued in.
+    ///
+    /// The whole queue is reversed, closures with a lower priority are then executed first. Explicitly executing the queued closures
+    /// (e.g. using [`DeferGroup::run_first`]) is not affected.
+    pub const fn fifo(mut self) -> Self {
+        self.fifo = true;
+        self
+    }
+
+    /// Sets the [`Strategy`] of the group, [`Strategy::Always`] by default.
+    pub const fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the [`Strategy`] of the group to [`Strategy::OnSuccess`], see [`DeferGroup::on_success`].
+    pub const fn on_success(self) -> Self {
+        self.strategy(Strategy::OnSuccess)
+    }
+
+    /// Sets the [`Strategy`] of the group to [`Strategy::OnUnwind`], see [`DeferGroup::on_unwind`].
+    pub const fn on_unwind(self) -> Self {
+        self.strategy(Strategy::OnUnwind)
+    }
+
+    /// Reserves space for at least `capacity` deferred closures, see [`DeferGroup::with_capacity`].
+    pub const fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the [`PanicPolicy`] of the group, [`PanicPolicy::Stop`] by default.
+    pub const fn panic_policy(mut self, panic_policy: PanicPolicy) -> Self {
+        self.panic_policy = panic_policy;
+        self
+    }
+
+    /// Sets the source reported to the [hooks](crate::hooks) for the group's closures, `"DeferGroup"` by default.
+    pub const fn name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Creates the configured `DeferGroup`.
+    ///
+    /// **Note: `DeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn build<'a>(self) -> DeferGroup<'a> {
+        DeferGroup {
+            entries: VecDeque::with_capacity(self.capacity),
+            strategy: self.strategy,
+            next_id: 0,
+            fifo: self.fifo,
+            panic_policy: self.panic_policy,
+            name: self.name,
+        }
+    }
+}
+
+impl Default for DeferGroupBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    fn test_defer_group_builder() {
+        let rec = ExecutionRecorder::new();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            let mut group = DeferGroup::builder()
+                .fifo()
+                .panic_policy(PanicPolicy::Continue)
+                .capacity(4)
+                .build();
+            assert!(group.capacity() >= 4);
+            group.add(Box::new(rec.callback("1st")));
+            group.add(Box::new(|| panic!("2nd failed")));
+            group.add(Box::new(rec.callback("3rd")));
+        }));
+        assert!(res.is_err());
+        rec.assert_order(&["1st", "3rd"]);
+
+        let rec = ExecutionRecorder::new();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            let mut group = DeferGroup::builder().build();
+            group.add(Box::new(rec.callback("not executed")));
+            group.add(Box::new(|| panic!("failed")));
+        }));
+        assert!(res.is_err());
+        assert!(rec.is_empty());
+    }
+
+    #[test]
+    fn test_fifo_group_partial_runs_follow_execution_order() {
+        let rec = ExecutionRecorder::new();
+        let fifo_group = || {
+            let mut group = DeferGroup::builder().fifo().build();
+            for label in ["1st", "2nd", "3rd", "4th"] {
+                group.add(Box::new(rec.callback(label)));
+            }
+            group
+        };
+
+        let mut group = fifo_group();
+        group.run_first(1);
+        group.run_range(1..=2);
+        rec.assert_order(&["1st", "3rd", "4th"]);
+        drop(group);
+        rec.assert_order(&["1st", "3rd", "4th", "2nd"]);
+
+        let mut group = DeferGroup::builder().fifo().build();
+        group.add(Box::new(rec.callback("last")));
+        let savepoint = group.savepoint();
+        group.add(Box::new(rec.callback("1st")));
+        group.add(Box::new(rec.callback("2nd")));
+        group.run_since(savepoint);
+        drop(group);
+        rec.assert_order(&["1st", "3rd", "4th", "2nd", "1st", "2nd", "last"]);
+
+        for f in fifo_group() {
+            f();
+        }
+        let mut deferred = fifo_group().into_inner();
+        deferred.remove(0)();
+        rec.assert_order(&[
+            "1st", "3rd", "4th", "2nd", "1st", "2nd", "last", "1st", "2nd", "3rd", "4th", "1st",
+        ]);
+    }
+}
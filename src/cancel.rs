@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::Defer;
+
+/// A cheap, cloneable token that many guards (and queued closures) can be linked to, cancelling all of them at once.
+///
+/// Closures linked to the token (using [`CancelToken::guard`], or [`CancelToken::wrap`]) are skipped once it's
+/// [cancelled](CancelToken::cancel). This models "if the overall operation committed, skip every rollback", where the rollback
+/// steps are registered deep in the call stack, without threading each of their guards back up to the code committing the operation.
+/// Clones of a token refer to the same state, and can be shared across threads.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{CancelToken, DeferGroup};
+///
+/// fn create_tables(rollback: &CancelToken, group: &mut DeferGroup) {
+///     group.add(Box::new(rollback.wrap(|| println!("Dropping the tables..."))));
+/// }
+///
+/// fn migrate(rollback: &CancelToken) {
+///     let _undo = rollback.guard(|| println!("Restoring the schema version..."));
+///     let mut group = DeferGroup::new();
+///     create_tables(rollback, &mut group);
+///     // ... the migration succeeded ...
+///     rollback.cancel();
+/// }
+///
+/// // Nothing is printed
+/// migrate(&CancelToken::new());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a new, uncancelled `CancelToken`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token, so none of the closures linked to it (or to its clones) are executed from now on.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if the token (or one of its clones) was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Links `f` to the token, returning a closure executing it unless the token is cancelled by then.
+    ///
+    /// The returned closure can be registered wherever a closure is expected, e.g. in a [`DeferGroup`](crate::DeferGroup).
+    pub fn wrap(&self, f: impl FnOnce()) -> impl FnOnce() {
+        let token = self.clone();
+        move || {
+            if !token.is_cancelled() {
+                f();
+            }
+        }
+    }
+
+    /// Returns a [`Defer`] executing `f` when it goes out of scope, unless the token is cancelled by then.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub fn guard(&self, f: impl FnOnce()) -> Defer<impl FnOnce()> {
+        Defer::new(self.wrap(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+    use crate::DeferGroup;
+
+    #[test]
+    fn test_cancel_token() {
+        let rec = ExecutionRecorder::new();
+        let token = CancelToken::new();
+        {
+            let _executed = token.guard(rec.callback("executed"));
+        }
+        {
+            let mut group = DeferGroup::new();
+            group.add(Box::new(token.wrap(rec.callback("group"))));
+            let _guard = token.guard(rec.callback("guard"));
+            let clone = token.clone();
+            std::thread::spawn(move || clone.cancel()).join().unwrap();
+            assert!(token.is_cancelled());
+        }
+        rec.assert_order(&["executed"]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::OnceCell;
+
+/// A lazily armed guard: a cell initialized on first access, whose cleanup is only executed (when it goes out of scope) if it was initialized.
+///
+/// This suits functions that only acquire a resource on some paths: the guard is created upfront (disarmed), and the resource
+/// is acquired (arming the cleanup) the first time it's actually needed, using [`DeferCell::get_or_init`]. If it's never needed,
+/// nothing is acquired, and nothing is cleaned up. Like a [`OnceCell`], the cell is initialized through a shared reference.
+///
+/// **Note: `DeferCell` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::DeferCell;
+///
+/// # struct TempDir;
+/// # impl TempDir { fn create() -> Self { TempDir } fn remove(self) {} }
+/// fn convert(input: &str, needs_scratch_space: bool) {
+///     let scratch = DeferCell::new(|dir: TempDir| dir.remove());
+///     if needs_scratch_space {
+///         // The temp dir is only created (and removed) if it's needed
+///         let _dir = scratch.get_or_init(TempDir::create);
+///     }
+///     // ... convert `input` ...
+/// }
+///
+/// convert("data", false);
+/// ```
+#[must_use = "DeferCell MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct DeferCell<T, F: FnOnce(T)> {
+    value: OnceCell<T>,
+    cleanup: Option<F>,
+}
+
+impl<T, F: FnOnce(T)> DeferCell<T, F> {
+    /// Creates a new, empty (disarmed) `DeferCell`, passing its value to `cleanup` when it goes out of scope, if it was initialized.
+    ///
+    /// **Note: `DeferCell` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub const fn new(cleanup: F) -> Self {
+        Self {
+            value: OnceCell::new(),
+            cleanup: Some(cleanup),
+        }
+    }
+
+    /// Returns the value, initializing it using `init` (arming the cleanup) if the cell is empty.
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        self.value.get_or_init(init)
+    }
+
+    /// Returns the value, if the cell was initialized.
+    pub fn get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// Returns the value mutably, if the cell was initialized.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.value.get_mut()
+    }
+
+    /// Returns `true` if the cell was initialized, i.e. the cleanup will be executed when it goes out of scope.
+    pub fn is_armed(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+    /// Takes the value out of the cell (disarming the cleanup), returning it without cleaning it up.
+    ///
+    /// The cell can be initialized again afterwards, arming the cleanup again.
+    pub fn take(&mut self) -> Option<T> {
+        self.value.take()
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for DeferCell<T, F> {
+    fn drop(&mut self) {
+        let (Some(value), Some(cleanup)) = (self.value.take(), self.cleanup.take()) else {
+            return;
+        };
+        if crate::debug::skipped(|| format!("cleanup of `{}`", std::any::type_name::<T>())) {
+            return;
+        }
+        let _span = crate::hooks::executing::<F>("DeferCell");
+        cleanup(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+
+    #[test]
+    fn test_defer_cell_only_cleans_up_if_armed() {
+        let rec = ExecutionRecorder::new();
+        {
+            let cell = DeferCell::new(|value: &str| rec.record(value));
+            assert!(!cell.is_armed());
+        }
+        {
+            let mut cell = DeferCell::new(|value: &str| rec.record(value));
+            assert_eq!(*cell.get_or_init(|| "taken"), "taken");
+            assert_eq!(cell.take(), Some("taken"));
+            cell.get_or_init(|| "armed");
+            cell.get_or_init(|| "ignored");
+            assert!(cell.is_armed());
+        }
+        rec.assert_order(&["armed"]);
+    }
+}
@@ -0,0 +1,82 @@
+//! Drop-in replacements for the macros of other `defer` crates, behind the `compat` feature.
+//!
+//! Projects switching from [`defer-lite`](https://docs.rs/defer-lite), or the [`defer`](https://docs.rs/defer) crate, can replace their
+//! imports with the matching module below, without touching the call sites, then adopt the richer features of `defer-rs` gradually.
+//! The replacements keep the semantics of the original macros: the whole input is deferred as is (arguments of a single
+//! function call are evaluated when the scope exits, unlike with [`defer!`](crate::defer)), and captured by reference.
+//!
+//! # Example
+//!
+//! ```rust
+//! // Was `use defer_lite::defer;`
+//! use defer_rs::compat::defer_lite::defer;
+//!
+//! let path = String::from("/tmp/scratch");
+//! defer! {
+//!     println!("Removing {path}...");
+//! }
+//! ```
+
+/// A drop-in replacement for the [`defer-lite`](https://docs.rs/defer-lite) crate, see the [module level documentation](super).
+pub mod defer_lite {
+    /// Defers the execution of the given code until the current scope exits, like `defer_lite::defer!`.
+    #[doc(inline)]
+    pub use crate::__compat_defer as defer;
+}
+
+/// A drop-in replacement for the [`defer`](https://docs.rs/defer) crate, see the [module level documentation](super).
+pub mod defer {
+    use crate::Defer;
+
+    /// Defers the execution of the given code until the current scope exits, like `defer::defer!`.
+    #[doc(inline)]
+    pub use crate::__compat_defer as defer;
+
+    /// Returns a guard executing `f` when it goes out of scope, like `defer::defer`.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub fn defer<F: FnOnce()>(f: F) -> Defer<F> {
+        Defer::new(f)
+    }
+}
+
+// Exported at the crate root (as all `macro_rules` macros are), but only meant to be used through the `compat` modules
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compat_defer {
+    ($($body:tt)*) => {
+        let ___deferred_code = $crate::Defer::new(|| {
+            $($body)*
+        });
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::ExecutionRecorder;
+    use std::cell::Cell;
+
+    fn add_to_recorder(val: &Cell<i32>, rec: &ExecutionRecorder) {
+        rec.record(format!("x is: {}", val.get()));
+    }
+
+    #[test]
+    fn test_compat_macros() {
+        let rec = ExecutionRecorder::new();
+        let val = Cell::new(0);
+        {
+            use super::defer_lite::defer;
+            // Unlike `crate::defer!`, the arguments are evaluated when the scope exits
+            defer!(add_to_recorder(&val, &rec));
+            defer! {}
+            val.set(3);
+        }
+        {
+            // Imports both the function and the macro
+            use super::defer::defer;
+            let _guard = defer(|| rec.record("function"));
+            defer!(rec.record("macro"));
+        }
+        rec.assert_order(&["x is: 3", "macro", "function"]);
+    }
+}
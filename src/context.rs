@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use crate::DeferScope;
+
+/// A named node in a tree of defer contexts, letting a context escalate cleanups to its ancestors.
+///
+/// Each `DeferContext` has its own queue of closures, and (except for the root context) a parent. A cleanup for a resource
+/// outliving the function that created it can be queued on an ancestor, reached by level ([`DeferContext::ancestor`]),
+/// by name ([`DeferContext::find`]), or directly on the root ([`DeferContext::root`]), e.g. the application-wide context.
+///
+/// A `DeferContext` is a (cloneable) handle, the closures queued on a context are executed (first to last) once its last handle,
+/// and the handles of all its children, go out of scope. As children keep their parent alive, the closures escalated to an
+/// ancestor are always executed after the ones queued on its descendants.
+///
+/// **Note: `DeferContext` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::DeferContext;
+///
+/// fn handle_request(request: &DeferContext) {
+///     let handler = request.child("handler");
+///     handler.add(Box::new(|| println!("Releasing the request buffer...")));
+///
+///     // The connection is reused by the following requests, so it's closed along with the application
+///     handler.root().add(Box::new(|| println!("Closing the pooled connection...")));
+///     // The response is streamed after the handler returns
+///     handler
+///         .find("request")
+///         .unwrap()
+///         .add(Box::new(|| println!("Flushing the response...")));
+/// }
+///
+/// let app = DeferContext::new("app");
+/// {
+///     let request = app.child("request");
+///     handle_request(&request);
+/// }
+/// ```
+///
+/// See also: [`DeferScope`], and [`NestedDeferGroup`](crate::NestedDeferGroup).
+#[must_use = "DeferContext MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!"]
+#[derive(Clone)]
+pub struct DeferContext<'a>(Rc<Node<'a>>);
+
+// Fields are dropped in declaration order, so the closures are executed before the parent is released
+struct Node<'a> {
+    scope: DeferScope<'a>,
+    name: Cow<'static, str>,
+    parent: Option<DeferContext<'a>>,
+}
+
+impl<'a> DeferContext<'a> {
+    /// Creates a new root `DeferContext`, named `name`.
+    ///
+    /// **Note: `DeferContext` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self::with_parent(name.into(), None)
+    }
+
+    /// Creates a new `DeferContext` named `name`, as a child of this context.
+    ///
+    /// **Note: `DeferContext` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn child(&self, name: impl Into<Cow<'static, str>>) -> Self {
+        Self::with_parent(name.into(), Some(self.clone()))
+    }
+
+    fn with_parent(name: Cow<'static, str>, parent: Option<Self>) -> Self {
+        Self(Rc::new(Node {
+            scope: DeferScope::new(),
+            name,
+            parent,
+        }))
+    }
+
+    /// Returns the name of the context.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Returns the parent of the context, or `None` for a root context.
+    pub fn parent(&self) -> Option<&Self> {
+        self.0.parent.as_ref()
+    }
+
+    /// Returns the ancestor `level` levels above the context (`0` being the context itself, and `1` its parent), if any.
+    pub fn ancestor(&self, level: usize) -> Option<&Self> {
+        let mut context = self;
+        for _ in 0..level {
+            context = context.parent()?;
+        }
+        Some(context)
+    }
+
+    /// Returns the closest context named `name`, starting from the context itself and going up its ancestors, if any.
+    pub fn find(&self, name: &str) -> Option<&Self> {
+        let mut context = Some(self);
+        while let Some(current) = context {
+            if current.name() == name {
+                return Some(current);
+            }
+            context = current.parent();
+        }
+        None
+    }
+
+    /// Returns the root of the context's tree (the context itself, if it's a root context).
+    pub fn root(&self) -> &Self {
+        let mut context = self;
+        while let Some(parent) = context.parent() {
+            context = parent;
+        }
+        context
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the context's queue.
+    pub fn add(&self, f: Box<dyn FnOnce() + 'a>) {
+        self.0.scope.add(f);
+    }
+
+    /// Pushes a deferred closure to the end of the context's queue.
+    pub fn push(&self, f: Box<dyn FnOnce() + 'a>) {
+        self.0.scope.push(f);
+    }
+
+    /// Returns the number of deferred closures queued on the context (excluding its ancestors and children).
+    pub fn len(&self) -> usize {
+        self.0.scope.len()
+    }
+
+    /// Returns `true` if no deferred closures are queued on the context.
+    pub fn is_empty(&self) -> bool {
+        self.0.scope.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+
+    #[test]
+    fn test_defer_context_escalation() {
+        let rec = ExecutionRecorder::new();
+        {
+            let app = DeferContext::new("app");
+            app.add(Box::new(rec.callback("app")));
+            {
+                let request = app.child("request");
+                let handler = request.child("handler");
+                handler.add(Box::new(rec.callback("handler")));
+
+                assert_eq!(handler.ancestor(1).unwrap().name(), "request");
+                assert!(handler.ancestor(3).is_none());
+                assert!(handler.find("missing").is_none());
+
+                handler
+                    .root()
+                    .add(Box::new(rec.callback("escalated to app")));
+                handler
+                    .find("request")
+                    .unwrap()
+                    .add(Box::new(rec.callback("escalated to request")));
+            }
+            rec.assert_order(&["handler", "escalated to request"]);
+        }
+        rec.assert_order(&["handler", "escalated to request", "escalated to app", "app"]);
+    }
+}
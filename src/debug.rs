@@ -0,0 +1,224 @@
+//! A debugging switch turning deferred cleanups into no-ops.
+//!
+//! When a test fails, it's often useful to inspect what it left behind (temp dirs, containers, files, ...),
+//! which is impossible if the cleanups run regardless. While cleanup is skipped, the deferred closures are dropped
+//! without being executed, and each skipped cleanup is logged to `stderr` instead.
+//!
+//! Cleanup can be skipped either by setting the `DEFER_RS_SKIP_CLEANUP` environment variable (to anything but `0`) before
+//! the first deferred closure would run, or at runtime using [`skip_cleanup`].
+//!
+//! This only applies to user-supplied closures: the ones executed when a guard (e.g. [`Defer`](crate::Defer), or [`DeferSpawn`](crate::DeferSpawn))
+//! or a group (e.g. [`DeferGroup`](crate::DeferGroup), or [`TeardownGroup`](crate::testing::TeardownGroup)) goes out of scope,
+//! at the end of [`run_scope`](crate::run_scope), once an [`UnlockThen`](crate::UnlockThen) released its lock (which is released regardless),
+//! and by every runner executing a whole group or the registry at once:
+//! - [`DeferGroup::run_now`](crate::DeferGroup::run_now), [`run_in`](crate::DeferGroup::run_in), and [`run_with_budget`](crate::DeferGroup::run_with_budget),
+//! - [`SyncDeferGroup::run_pending`](crate::SyncDeferGroup::run_pending), and the `run_parallel` methods of the `Sync`/`Send` groups,
+//! - [`registry::run_all`](crate::registry::run_all), [`run_all_reported`](crate::registry::run_all_reported),
+//!   [`run_all_with_budget`](crate::registry::run_all_with_budget), [`run_parallel`](crate::registry::run_parallel),
+//!   [`run_phased`](crate::registry::run_phased), and [`run_namespace`](crate::registry::run_namespace).
+//!
+//! The runners returning a [`RunReport`](crate::RunReport) count the skipped closures in it. The only exempt executions are the ones
+//! targeting part of a group's queue, as the caller explicitly asked for these closures to run now: [`DeferGroup::run_first`](crate::DeferGroup::run_first),
+//! [`run_range`](crate::DeferGroup::run_range), and [`run_since`](crate::DeferGroup::run_since) (and their [`DeferScope`](crate::DeferScope) counterparts).
+//!
+//! The library's own guards are never skipped, as skipping them breaks protocols other code relies on, rather than leaving
+//! resources behind: counting down a [`Latch`](crate::Latch), checking an item back into its pool, sending the notification
+//! of a [`NotifyOnDrop`](crate::NotifyOnDrop), releasing the lock of an [`UnlockThen`](crate::UnlockThen), restoring a panic hook
+//! or the terminal, aborting a task, closing a file descriptor, shutting down a TCP stream, and cancelling a [`Nursery`](crate::Nursery).
+//!
+//! # Compiling cleanups out
+//!
+//! For measuring the overhead of deferred cleanups, or for stripped builds, the `noop` feature turns every cleanup skippable
+//! at runtime into a no-op at compile time: cleanup is always skipped (without logging), [`skip_cleanup`] has no effect,
+//! [`defer_scope!`](crate::defer_scope) no longer queues (or allocates) anything, and guards like [`Defer`](crate::Defer)
+//! are reduced to their closure, which is dropped without being called.
+//!
+//! **This is NOT a safe mode: locks, files, temporary resources, transactions, etc. are never released (or rolled back) by
+//! the deferred closures!** Values captured by a deferred closure are still dropped, but those moved into a
+//! [`defer_scope!`](crate::defer_scope) closure are dropped immediately instead of at the end of the targeted scope.
+//! Code waiting for a deferred closure to be executed (e.g. on a barrier it releases, or using [`background::join_all`](crate::background::join_all)
+//! on a closure signalling it) never stops waiting.
+//! As features are additive, a library must never enable `noop`, it's only meant to be enabled by the final binary
+//! (e.g. `cargo bench --features defer-rs/noop`).
+//!
+//! # Example
+//!
+//! ```rust
+//! use defer_rs::{debug, defer};
+//! use std::cell::Cell;
+//!
+//! let cleaned_up = Cell::new(false);
+//! debug::skip_cleanup(true);
+//! {
+//!     // Logs "defer_rs: skipped deferred closure `...`" instead
+//!     defer!(cleaned_up.set(true));
+//! }
+//! assert!(!cleaned_up.get());
+//! ```
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const UNSET: u8 = 0;
+const RUN: u8 = 1;
+const SKIP: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNSET);
+
+/// Enables (or disables) skipping deferred cleanups process-wide, overriding the `DEFER_RS_SKIP_CLEANUP` environment variable.
+///
+/// Has no effect with the `noop` feature enabled.
+pub fn skip_cleanup(skip: bool) {
+    STATE.store(if skip { SKIP } else { RUN }, Ordering::Relaxed);
+}
+
+/// Returns `true` if deferred cleanups are currently skipped.
+///
+/// Always returns `true` with the `noop` feature enabled.
+#[inline]
+pub fn is_cleanup_skipped() -> bool {
+    if cfg!(feature = "noop") {
+        return true;
+    }
+    match STATE.load(Ordering::Relaxed) {
+        UNSET => init_state(),
+        state => state == SKIP,
+    }
+}
+
+// Only called once per process (or until `skip_cleanup` is called), kept out of the guards' drop glue
+#[cold]
+#[inline(never)]
+fn init_state() -> bool {
+    let skip = std::env::var_os("DEFER_RS_SKIP_CLEANUP").is_some_and(|v| !v.is_empty() && v != "0");
+    // A concurrent `skip_cleanup` call takes precedence over the environment variable
+    let _ = STATE.compare_exchange(
+        UNSET,
+        if skip { SKIP } else { RUN },
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+    STATE.load(Ordering::Relaxed) == SKIP
+}
+
+/// Returns `true` (logging `what` was skipped) if cleanup is skipped.
+// Public (but hidden) for the code generated by `make_guard!`
+#[doc(hidden)]
+#[inline]
+pub fn skipped(what: impl FnOnce() -> String) -> bool {
+    // Compiled out cleanups are skipped silently
+    if cfg!(feature = "noop") {
+        return true;
+    }
+    let skip = is_cleanup_skipped();
+    if skip {
+        log_skipped(what);
+    }
+    skip
+}
+
+#[cold]
+#[inline(never)]
+fn log_skipped(what: impl FnOnce() -> String) {
+    eprintln!("defer_rs: skipped {} (cleanup is disabled)", what());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::process::Command;
+
+    // The switch is process-wide, so it's only flipped in a child process (running `skip_cleanup_child`), not to affect the other tests
+    const CHILD: &str = "DEFER_RS_DEBUG_TEST_CHILD";
+
+    #[test]
+    fn skip_cleanup_child() {
+        if std::env::var_os(CHILD).is_none() {
+            return;
+        }
+        // Set by the parent, through `DEFER_RS_SKIP_CLEANUP`
+        assert!(is_cleanup_skipped());
+        let executed = Cell::new(false);
+        {
+            let _skipped = crate::Defer::new(|| executed.set(true));
+        }
+        assert!(!executed.get());
+        // The lock is released regardless, only the closure is skipped
+        let lock = std::sync::Mutex::new(());
+        drop(crate::unlock_then(lock.lock().unwrap(), || {
+            executed.set(true)
+        }));
+        assert!(lock.try_lock().is_ok());
+        assert!(!executed.get());
+
+        // Every runner executing a whole group (or the registry) skips it, only targeted executions are exempt
+        let group = || {
+            let mut group = crate::DeferGroup::new();
+            group.push(Box::new(|| executed.set(true)));
+            group
+        };
+        assert_eq!(group().run_now().skipped(), 1);
+        let budget = crate::Budget::total(std::time::Duration::from_secs(60));
+        assert_eq!(group().run_with_budget(budget).skipped(), 1);
+        let mut in_order = group();
+        in_order.run_in(crate::Order::Registration);
+        assert!(in_order.is_empty());
+        assert!(!executed.get());
+        group().run_first(1);
+        assert!(executed.get());
+        executed.set(false);
+
+        crate::registry::register(|| panic!("the registry must be skipped"));
+        assert_eq!(crate::registry::run_all_with_budget(budget).skipped(), 1);
+        let phases = crate::registry::run_phased(|_| budget);
+        assert_eq!(phases.len(), 1);
+        assert_eq!(phases[0].1.skipped(), 1);
+
+        // Including the closures executed from another thread, e.g. once a delay elapses
+        let fired = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let delayed = crate::DelayDefer::new(std::time::Duration::ZERO, {
+            let fired = fired.clone();
+            move || fired.store(true, std::sync::atomic::Ordering::SeqCst)
+        });
+        while !delayed.has_fired() {
+            std::thread::yield_now();
+        }
+        drop(delayed);
+        assert!(!fired.load(std::sync::atomic::Ordering::SeqCst));
+        // Or once a trigger resolves
+        let (_guard, watcher) = crate::future::defer_until(std::future::ready(()), || {
+            executed.set(true);
+        });
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        assert!(std::pin::pin!(watcher).poll(&mut cx).is_ready());
+        assert!(!executed.get());
+
+        skip_cleanup(false);
+        assert!(!is_cleanup_skipped());
+        {
+            let _executed = crate::Defer::new(|| executed.set(true));
+        }
+        assert!(executed.get());
+    }
+
+    #[test]
+    #[cfg_attr(
+        feature = "noop",
+        ignore = "`noop` skips cleanups silently, regardless of the switch"
+    )]
+    fn test_skip_cleanup() {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "debug::tests::skip_cleanup_child", "--nocapture"])
+            .env(CHILD, "1")
+            .env("DEFER_RS_SKIP_CLEANUP", "1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+        // The closures (and groups) skipped before the switch was turned off were logged
+        assert_eq!(stderr.matches("defer_rs: skipped").count(), 9, "{stderr}");
+        assert!(stderr.contains("defer_rs: skipped deferred closure `"));
+        assert!(stderr.contains("(cleanup is disabled)"));
+    }
+}
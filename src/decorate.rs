@@ -0,0 +1,85 @@
+/// The error decorators registered (using [`defer_err!`](crate::defer_err)) in a function marked with [`err_context`](macro@crate::err_context).
+///
+/// Decorators are closures taking the outgoing error and returning it wrapped (or annotated), executed in reverse order of
+/// registration if the function returns an `Err`. It can also be used directly, to decorate any `Result`.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::ErrorDecorators;
+///
+/// let mut decorators = ErrorDecorators::new();
+/// decorators.add(|err: String| format!("while connecting: {err}"));
+/// decorators.add(|err: String| format!("while resolving the host: {err}"));
+///
+/// let res: Result<(), _> = decorators.apply(Err("no such host".to_string()));
+/// assert_eq!(res.unwrap_err(), "while connecting: while resolving the host: no such host");
+/// ```
+pub struct ErrorDecorators<'a, E>(Vec<Box<dyn FnOnce(E) -> E + 'a>>);
+
+impl<'a, E> ErrorDecorators<'a, E> {
+    /// Creates a new, empty `ErrorDecorators`.
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Registers an error decorator, executed before the previously registered ones.
+    pub fn add(&mut self, decorator: impl FnOnce(E) -> E + 'a) {
+        self.0.push(Box::new(decorator));
+    }
+
+    /// Returns the number of registered decorators.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if no decorators are registered.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Passes the error of `res` (if any) through the registered decorators (last to first), dropping them without being executed otherwise.
+    pub fn apply<T>(self, res: Result<T, E>) -> Result<T, E> {
+        res.map_err(|err| {
+            self.0
+                .into_iter()
+                .rev()
+                .fold(err, |err, decorate| decorate(err))
+        })
+    }
+}
+
+impl<'a, E> Default for ErrorDecorators<'a, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::ExecutionRecorder;
+    use crate::{defer_err, err_context};
+
+    #[err_context]
+    fn fallible(rec: &ExecutionRecorder, fail: bool) -> Result<u8, String> {
+        let rec = rec.clone();
+        defer_err!(move |err| {
+            rec.record("decorated");
+            format!("outer: {err}")
+        });
+        if fail {
+            return Err("failed".into());
+        }
+        defer_err!(|err| format!("unreachable: {err}"));
+        Ok(1)
+    }
+
+    #[test]
+    fn test_err_context() {
+        let rec = ExecutionRecorder::new();
+        assert_eq!(fallible(&rec, false), Ok(1));
+        assert!(rec.is_empty());
+        assert_eq!(fallible(&rec, true), Err("outer: failed".to_string()));
+        rec.assert_order(&["decorated"]);
+    }
+}
@@ -0,0 +1,192 @@
+//! An in-process log of executed deferred closures, behind the `event-log` feature.
+//!
+//! Once [enabled](enable), the execution of every deferred closure (by a guard, a group, or the registry) is recorded into a ring buffer,
+//! keeping the most recent executions, which the application can retrieve (e.g. from a panic hook, or a debug endpoint) using [`events`].
+//! This helps diagnosing ordering bugs between interleaved guards and groups after the fact, without a debugger.
+//!
+//! Each [`LoggedExecution`] records when (and on which thread) the closure was executed, the guard or group that executed it,
+//! and the closure's type name when it's known, which includes the path of the function it was defined in
+//! (e.g. `my_app::db::connect::{{closure}}`). Closures skipped through [`debug::skip_cleanup`](crate::debug::skip_cleanup) aren't recorded.
+//!
+//! Until the log is enabled, the only overhead is a single atomic load per executed closure.
+//!
+//! # Example
+//!
+//! ```rust
+//! use defer_rs::{event_log, Defer, DeferGroup};
+//!
+//! event_log::enable(64);
+//! {
+//!     let _guard = Defer::new(|| println!("Closing the connection..."));
+//!     let mut group = DeferGroup::new();
+//!     group.add(Box::new(|| println!("Removing the temp dir...")));
+//! }
+//! for event in event_log::events() {
+//!     eprintln!("{event}");
+//! }
+//! event_log::disable();
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::SystemTime;
+
+/// A deferred closure's execution, recorded in the log, see the [module level documentation](self).
+#[derive(Debug, Clone)]
+pub struct LoggedExecution {
+    time: SystemTime,
+    thread: ThreadId,
+    source: &'static str,
+    closure: Option<&'static str>,
+}
+
+impl LoggedExecution {
+    /// Returns when the closure was executed (right before it was called).
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Returns the id of the thread the closure was executed on.
+    pub fn thread(&self) -> ThreadId {
+        self.thread
+    }
+
+    /// Returns the name of the guard or group that executed the closure (e.g. `"Defer"`, or `"DeferGroup"`).
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+
+    /// Returns the type name of the closure, if it's known (it isn't for closures queued on groups, which are type-erased).
+    pub fn closure(&self) -> Option<&'static str> {
+        self.closure
+    }
+}
+
+impl fmt::Display for LoggedExecution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        write!(
+            f,
+            "[{}.{:06}] {:?} `{}`",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros(),
+            self.thread,
+            self.source
+        )?;
+        if let Some(closure) = self.closure {
+            write!(f, " executed `{closure}`")?;
+        }
+        Ok(())
+    }
+}
+
+struct Log {
+    events: VecDeque<LoggedExecution>,
+    capacity: usize,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOG: Mutex<Log> = Mutex::new(Log {
+    events: VecDeque::new(),
+    capacity: 0,
+});
+
+/// Starts recording executions, keeping the `capacity` most recent ones (older ones are discarded).
+///
+/// If the log is already enabled, its capacity is changed, discarding the oldest executions if it's shrunk.
+pub fn enable(capacity: usize) {
+    let mut log = LOG.lock().unwrap_or_else(PoisonError::into_inner);
+    log.capacity = capacity;
+    let excess = log.events.len().saturating_sub(capacity);
+    log.events.drain(..excess);
+    ENABLED.store(capacity > 0, Ordering::Release);
+}
+
+/// Stops recording executions, the recorded ones are kept until [`clear`] is called.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+/// Returns `true` if executions are being recorded.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Returns the recorded executions, oldest first.
+pub fn events() -> Vec<LoggedExecution> {
+    LOG.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .events
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Discards the recorded executions.
+pub fn clear() {
+    LOG.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .events
+        .clear();
+}
+
+/// Records the execution of a closure by `source`, if the log is enabled.
+#[inline]
+pub(crate) fn record(source: &'static str, closure: Option<&'static str>) {
+    if ENABLED.load(Ordering::Acquire) {
+        record_enabled(source, closure);
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn record_enabled(source: &'static str, closure: Option<&'static str>) {
+    let event = LoggedExecution {
+        time: SystemTime::now(),
+        thread: thread::current().id(),
+        source,
+        closure,
+    };
+    let mut log = LOG.lock().unwrap_or_else(PoisonError::into_inner);
+    if log.capacity == 0 {
+        return;
+    }
+    if log.events.len() == log.capacity {
+        log.events.pop_front();
+    }
+    log.events.push_back(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Defer, DeferGroup};
+
+    #[test]
+    fn test_event_log() {
+        enable(1024);
+        {
+            let _defer = Defer::new(|| {});
+            let mut group = DeferGroup::new();
+            group.add(Box::new(|| {}));
+        }
+        disable();
+        // Other tests run concurrently, only this thread's executions are checked
+        let test_thread = thread::current().id();
+        let sources: Vec<_> = events()
+            .into_iter()
+            .filter(|event| event.thread() == test_thread)
+            .map(|event| (event.source(), event.closure().is_some()))
+            .collect();
+        assert_eq!(sources, [("DeferGroup", false), ("Defer", true)]);
+        assert!(events().iter().any(|event| event
+            .closure()
+            .is_some_and(|closure| closure.contains("test_event_log"))));
+    }
+}
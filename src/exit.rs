@@ -0,0 +1,124 @@
+use std::mem::ManuallyDrop;
+
+/// A utility struct for deferred execution of a closure told whether the scope is exited due to a panic.
+///
+/// `DeferExit` is identical to [`Defer`](crate::Defer), except that the deferred closure receives a `bool`, which is `true`
+/// if the scope is being exited due to a panic (i.e. the thread is unwinding). This lets a single closure branch
+/// between commit and rollback behavior, instead of needing two guards (or two [`DeferGroup`](crate::DeferGroup)s with different strategies).
+///
+/// **Note: `DeferExit` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::DeferExit;
+/// use std::cell::Cell;
+/// use std::panic::{catch_unwind, AssertUnwindSafe};
+///
+/// let outcome = Cell::new("");
+/// let res = catch_unwind(AssertUnwindSafe(|| {
+///     let _guard = DeferExit::new(|unwinding| {
+///         outcome.set(if unwinding { "rolled back" } else { "committed" });
+///     });
+///     panic!("Something went wrong!");
+/// }));
+///
+/// assert!(res.is_err());
+/// assert_eq!(outcome.get(), "rolled back");
+/// ```
+///
+/// See also: [`defer_exit!`](crate::defer_exit), and [`Defer`](crate::Defer).
+#[must_use = "DeferExit MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct DeferExit<T: FnOnce(bool)>(ManuallyDrop<T>);
+
+impl<T: FnOnce(bool)> DeferExit<T> {
+    /// Creates a new `DeferExit` instance with the given deferred closure.
+    ///
+    /// The closure will be executed when the `DeferExit` instance goes out of scope, receiving `true` if the thread is panicking.
+    ///
+    /// **Note: `DeferExit` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub const fn new(deferred: T) -> Self {
+        Self(ManuallyDrop::new(deferred))
+    }
+}
+
+impl<T: FnOnce(bool)> Drop for DeferExit<T> {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: the closure is only taken here, and `drop` is never called more than once
+        let deferred = unsafe { ManuallyDrop::take(&mut self.0) };
+        if crate::debug::skipped(|| format!("deferred closure `{}`", std::any::type_name::<T>())) {
+            return;
+        }
+        let _span = crate::hooks::executing::<T>("DeferExit");
+        deferred(std::thread::panicking());
+    }
+}
+
+/// A macro for deferring execution of a closure, told whether the scope is exited due to a panic, until the current scope exits.
+///
+/// The closure's single parameter is a `bool`, which is `true` if the scope is being exited due to a panic (i.e. the thread is unwinding).
+/// `move` can be added before the closure, just like with [`defer!`](crate::defer).
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::defer_exit;
+///
+/// defer_exit!(|unwinding| {
+///     if unwinding {
+///         println!("Rolling back...");
+///     } else {
+///         println!("Committing...");
+///     }
+/// });
+/// ```
+/// ### Expands to:
+///
+/// ```rust
+/// let ___deferred_code = ::defer_rs::DeferExit::new(|unwinding: bool| {
+///     if unwinding {
+///         println!("Rolling back...");
+///     } else {
+///         println!("Committing...");
+///     }
+/// });
+/// ```
+///
+/// See also: [`DeferExit`], and [`defer!`](crate::defer).
+#[macro_export]
+macro_rules! defer_exit {
+    (move |$unwinding:pat_param| $($body:tt)+) => {
+        let ___deferred_code = $crate::DeferExit::new(move |$unwinding: bool| {
+            $($body)+
+        });
+    };
+
+    (|$unwinding:pat_param| $($body:tt)+) => {
+        let ___deferred_code = $crate::DeferExit::new(|$unwinding: bool| {
+            $($body)+
+        });
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::ExecutionRecorder;
+    use std::panic::catch_unwind;
+
+    #[test]
+    fn test_defer_exit() {
+        let rec = ExecutionRecorder::new();
+        {
+            let rec = rec.clone();
+            defer_exit!(move |unwinding| rec.record(format!("unwinding: {unwinding}")));
+        }
+        let res = catch_unwind(|| {
+            defer_exit!(|unwinding| rec.record(format!("unwinding: {unwinding}")));
+            panic!();
+        });
+
+        assert!(res.is_err());
+        rec.assert_order(&["unwinding: false", "unwinding: true"]);
+    }
+}
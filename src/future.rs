@@ -0,0 +1,1134 @@
+//! Runtime-agnostic utilities for asynchronous cleanup.
+//!
+//! Nothing in this module depends on a specific async runtime: timers, signals, and the like are
+//! passed in as plain futures (e.g. `tokio::time::sleep(..)`), and the returned futures can be
+//! awaited (or spawned) on any executor.
+//!
+//! As such, there are no runtime-specific features (e.g. `tokio`, or `tokio-util`): wiring a utility to a runtime
+//! (spawning on its blocking pool, or on a `Handle`, cancelling through a `CancellationToken`, ...) is left to the user,
+//! the items' documentation shows how it's done with tokio.
+
+use std::future::Future;
+use std::io;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::{Defer, DeferGroup, SendDeferGroup, SyncDeferGroup};
+
+/// Wraps an async cleanup with a deadline, and a fallback action to run if the deadline is hit first.
+///
+/// The returned future resolves to the cleanup's output if it completes before `deadline` does, otherwise,
+/// the cleanup is abandoned (it's dropped along with the returned future) and `fallback` is invoked, its return
+/// value becomes the output. The fallback is where the "hard upper bound" behavior is chosen: logging the failure,
+/// aborting a task, or simply giving up.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::future::with_deadline;
+///
+/// # fn block_on<F: std::future::Future>(f: F) -> F::Output {
+/// #     let mut f = std::pin::pin!(f);
+/// #     let waker = std::task::Waker::noop();
+/// #     let mut cx = std::task::Context::from_waker(&waker);
+/// #     loop { if let std::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) { return out; } }
+/// # }
+/// async fn close_connection() -> Result<(), &'static str> {
+///     // A graceful close that never completes...
+///     std::future::pending().await
+/// }
+///
+/// // With tokio, the deadline would be `tokio::time::sleep(Duration::from_secs(5))`
+/// let deadline = std::future::ready(());
+///
+/// let res = block_on(with_deadline(close_connection(), deadline, || {
+///     eprintln!("Timed out while closing the connection, dropping it");
+///     Err("timed out")
+/// }));
+/// assert_eq!(res, Err("timed out"));
+/// ```
+pub fn with_deadline<C, D, F>(cleanup: C, deadline: D, fallback: F) -> Deadline<C, D, F>
+where
+    C: Future,
+    D: Future,
+    F: FnOnce() -> C::Output,
+{
+    Deadline {
+        cleanup,
+        deadline,
+        fallback: Some(fallback),
+    }
+}
+
+/// The future returned by [`with_deadline`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Deadline<C, D, F> {
+    cleanup: C,
+    deadline: D,
+    fallback: Option<F>,
+}
+
+impl<C, D, F> Future for Deadline<C, D, F>
+where
+    C: Future,
+    D: Future,
+    F: FnOnce() -> C::Output,
+{
+    type Output = C::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `cleanup` and `deadline` are structurally pinned, they're never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+
+        if let Poll::Ready(out) = unsafe { Pin::new_unchecked(&mut this.cleanup) }.poll(cx) {
+            return Poll::Ready(out);
+        }
+        if unsafe { Pin::new_unchecked(&mut this.deadline) }
+            .poll(cx)
+            .is_ready()
+        {
+            let fallback = this
+                .fallback
+                .take()
+                .expect("`Deadline` polled after completion");
+            return Poll::Ready(fallback());
+        }
+        Poll::Pending
+    }
+}
+
+/// Wraps a future with an async cleanup, polled to completion after the future completes, before its output is yielded.
+///
+/// Unlike a [`Defer`] (or an [`AsyncDefer`]), the cleanup is awaited inline by whoever awaits the returned future, so it's
+/// deterministic, and doesn't require a spawner: once the returned future resolves, the cleanup is done. If the returned future
+/// is dropped before that (e.g. it's cancelled by a `select!`, or a timeout), `fallback` is executed instead, synchronously,
+/// as a best-effort replacement for the cleanup (e.g. closing a connection without the graceful shutdown).
+///
+/// The cleanup future is only polled once the future completes, and is dropped without being polled if it never does.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::future::finally;
+///
+/// # fn block_on<F: std::future::Future>(f: F) -> F::Output {
+/// #     let mut f = std::pin::pin!(f);
+/// #     let waker = std::task::Waker::noop();
+/// #     let mut cx = std::task::Context::from_waker(&waker);
+/// #     loop { if let std::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) { return out; } }
+/// # }
+/// async fn handle_request() -> u16 {
+///     200
+/// }
+///
+/// let status = block_on(finally(
+///     handle_request(),
+///     async { println!("Flushing the access log...") },
+///     || eprintln!("Cancelled, the access log wasn't flushed"),
+/// ));
+/// assert_eq!(status, 200);
+/// ```
+pub fn finally<F, C, S>(future: F, cleanup: C, fallback: S) -> Finally<F, C, S>
+where
+    F: Future,
+    C: Future<Output = ()>,
+    S: FnOnce(),
+{
+    Finally {
+        future: Some(future),
+        cleanup: Some(cleanup),
+        output: None,
+        fallback: Some(fallback),
+    }
+}
+
+/// The future returned by [`finally`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Finally<F: Future, C, S: FnOnce()> {
+    // Dropped in place once it completes
+    future: Option<F>,
+    cleanup: Option<C>,
+    output: Option<F::Output>,
+    // Taken once the cleanup completes
+    fallback: Option<S>,
+}
+
+impl<F, C, S> Future for Finally<F, C, S>
+where
+    F: Future,
+    C: Future<Output = ()>,
+    S: FnOnce(),
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` and `cleanup` are structurally pinned, they're never moved out of `self` (only dropped in place)
+        let this = unsafe { self.get_unchecked_mut() };
+
+        let mut future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Some(fut) = future.as_mut().as_pin_mut() {
+            let Poll::Ready(out) = fut.poll(cx) else {
+                return Poll::Pending;
+            };
+            this.output = Some(out);
+            future.set(None);
+        }
+
+        let mut cleanup = unsafe { Pin::new_unchecked(&mut this.cleanup) };
+        let Some(fut) = cleanup.as_mut().as_pin_mut() else {
+            panic!("`Finally` polled after completion");
+        };
+        if fut.poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        cleanup.set(None);
+        this.fallback = None;
+        // `output` is only taken once the cleanup completes
+        Poll::Ready(this.output.take().unwrap())
+    }
+}
+
+impl<F: Future, C, S: FnOnce()> Drop for Finally<F, C, S> {
+    fn drop(&mut self) {
+        let Some(fallback) = self.fallback.take() else {
+            return;
+        };
+        if crate::debug::skipped(|| format!("cleanup fallback `{}`", std::any::type_name::<S>())) {
+            return;
+        }
+        let _span = crate::hooks::executing::<S>("Finally");
+        fallback();
+    }
+}
+
+/// Runs a blocking closure on a new thread, returning a future resolving to its output.
+///
+/// This is a runtime-agnostic counterpart to `tokio::task::spawn_blocking`, for blocking cleanup (e.g. filesystem operations)
+/// in async code. If the closure panics, the panic is resumed when the future is polled.
+///
+/// Unlike tokio's, the closure always runs on a dedicated thread, not on a bounded pool. To use tokio's blocking pool instead,
+/// call `tokio::task::spawn_blocking` directly, this crate doesn't provide a tokio-backed variant.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::future::spawn_blocking;
+///
+/// # fn block_on<F: std::future::Future>(f: F) -> F::Output {
+/// #     let mut f = std::pin::pin!(f);
+/// #     let waker = std::task::Waker::noop();
+/// #     let mut cx = std::task::Context::from_waker(&waker);
+/// #     loop { if let std::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) { return out; } }
+/// # }
+/// let len = block_on(async { spawn_blocking(|| std::fs::read_dir(".").unwrap().count()).await });
+/// assert!(len > 0);
+/// ```
+pub fn spawn_blocking<T, F>(f: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(BlockingState {
+        output: None,
+        waker: None,
+    }));
+    let thread_shared = shared.clone();
+    thread::spawn(move || {
+        let output = catch_unwind(AssertUnwindSafe(f));
+        let mut state = thread_shared.lock().unwrap_or_else(PoisonError::into_inner);
+        state.output = Some(output);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    Blocking(shared)
+}
+
+/// The future returned by [`spawn_blocking`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Blocking<T>(Arc<Mutex<BlockingState<T>>>);
+
+struct BlockingState<T> {
+    output: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.output.take() {
+            Some(Ok(output)) => Poll::Ready(output),
+            Some(Err(payload)) => {
+                drop(state);
+                resume_unwind(payload)
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A guard removing a file (or a directory, recursively) when it goes out of scope, without blocking the async executor.
+///
+/// When dropped, the removal is handed to the crate's cleanup executor (see [`Defer::detached`]), so dropping the guard
+/// in an async scope (e.g. when a request handler returns, or is cancelled) never blocks the executor's thread.
+/// To wait for the removal (and handle its error), use [`RemoveOnDrop::remove`] instead. A missing path isn't considered an error.
+///
+/// The removal never goes through the runtime's blocking pool (e.g. tokio's), so it isn't bounded (or awaited) by the runtime's shutdown.
+///
+/// **Note: `RemoveOnDrop` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, removing the path!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::future::RemoveOnDrop;
+///
+/// # fn block_on<F: std::future::Future>(f: F) -> F::Output {
+/// #     let mut f = std::pin::pin!(f);
+/// #     let waker = std::task::Waker::noop();
+/// #     let mut cx = std::task::Context::from_waker(&waker);
+/// #     loop { if let std::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) { return out; } }
+/// # }
+/// async fn convert(upload: &[u8]) -> std::io::Result<()> {
+///     let dir = std::env::temp_dir().join("defer-rs-upload");
+///     std::fs::create_dir_all(&dir)?;
+///     let dir = RemoveOnDrop::new(dir);
+///
+///     std::fs::write(dir.path().join("upload"), upload)?;
+///     // ... convert the upload, returning early on failure ...
+///
+///     dir.remove().await
+/// }
+///
+/// block_on(convert(b"data")).unwrap();
+/// ```
+#[must_use = "RemoveOnDrop MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, removing the path!"]
+pub struct RemoveOnDrop {
+    path: Option<PathBuf>,
+}
+
+impl RemoveOnDrop {
+    /// Creates a new `RemoveOnDrop` guard for the given path.
+    ///
+    /// **Note: `RemoveOnDrop` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, removing the path!**
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    /// Returns the guarded path.
+    pub fn path(&self) -> &Path {
+        // `self.path` can only be `None` once the guard is consumed
+        self.path.as_deref().unwrap()
+    }
+
+    /// Removes the path now (on another thread), returning a future resolving once it's removed.
+    pub fn remove(mut self) -> Blocking<io::Result<()>> {
+        let path = self.path.take().unwrap();
+        spawn_blocking(move || remove_path(&path))
+    }
+
+    /// Consumes the guard, returning the path without removing it.
+    pub fn into_inner(mut self) -> PathBuf {
+        self.path.take().unwrap()
+    }
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    let res = match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(err) => Err(err),
+    };
+    match res {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _removal = Defer::detached(move || {
+                let _ = remove_path(&path);
+            });
+        }
+    }
+}
+
+/// A guard spawning an async cleanup on an executor when it goes out of scope.
+///
+/// `Drop` can't `.await`, so the cleanup future is handed to `spawn` (captured when the guard is created) instead.
+/// There's no tokio-specific guard (e.g. a `TokioDefer`), the spawner is the integration point. With tokio, it would capture
+/// the runtime's `Handle::current()` (so the guard can be dropped outside of the runtime's threads):
+///
+/// ```rust,ignore
+/// let handle = tokio::runtime::Handle::current();
+/// let _cleanup = AsyncDefer::new(move |cleanup| drop(handle.spawn(cleanup)), async move {
+///     connection.close().await;
+/// });
+/// ```
+///
+/// Embassy is integrated the same way, through the spawner. The crate can't depend on `embassy-executor` (so there's no adapter,
+/// nor a feature for it), and Embassy tasks are statically allocated, non-generic functions, so the cleanup is boxed and handed
+/// to a task from a pool (the crate depends on `std`, so this only applies to Embassy on `std` targets):
+///
+/// ```rust,ignore
+/// #[embassy_executor::task(pool_size = 4)]
+/// async fn run_cleanup(cleanup: Pin<Box<dyn Future<Output = ()>>>) {
+///     cleanup.await
+/// }
+///
+/// // `Spawner` is `Copy`, e.g. the one passed to `main`
+/// let _cleanup = AsyncDefer::new(
+///     move |cleanup| {
+///         // Fails if every task of the pool is busy, the cleanup is dropped then
+///         if spawner.spawn(run_cleanup(Box::pin(cleanup))).is_err() {
+///             log::warn!("No task left to run the cleanup");
+///         }
+///     },
+///     async move { connection.close().await },
+/// );
+/// ```
+///
+/// This is a pragmatic, best-effort mechanism, with the following failure modes:
+/// - The cleanup isn't awaited by the scope: it runs concurrently with the code following the scope, and may complete after it.
+/// - The cleanup is lost if the executor is shut down (e.g. the runtime is dropped, or `main` returns) before it completes.
+/// - The spawner itself may panic (e.g. if it calls `tokio::spawn` outside of a runtime), possibly while unwinding, aborting the process.
+///
+/// To wait for a cleanup, await it explicitly instead (e.g. using [`with_deadline`] to bound it).
+///
+/// **Note: `AsyncDefer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, spawning the cleanup!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::future::AsyncDefer;
+/// use std::cell::RefCell;
+/// use std::future::Future;
+/// use std::pin::Pin;
+///
+/// // A task queue, standing in for an executor
+/// let tasks = RefCell::new(Vec::<Pin<Box<dyn Future<Output = ()>>>>::new());
+/// {
+///     let _cleanup = AsyncDefer::new(
+///         |cleanup| tasks.borrow_mut().push(Box::pin(cleanup)),
+///         async { println!("Closing the connection...") },
+///     );
+///     // ... use the connection ...
+/// }
+/// assert_eq!(tasks.borrow().len(), 1);
+/// ```
+///
+/// See also: [`defer_until`], and [`Defer::detached`].
+#[must_use = "AsyncDefer MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, spawning the cleanup!"]
+pub struct AsyncDefer<Fut: Future, S: FnOnce(Fut)> {
+    cleanup: Option<(Fut, S)>,
+}
+
+impl<Fut: Future, S: FnOnce(Fut)> AsyncDefer<Fut, S> {
+    /// Creates a new `AsyncDefer` instance, passing `cleanup` to `spawn` when it goes out of scope.
+    ///
+    /// **Note: `AsyncDefer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, spawning the cleanup!**
+    pub fn new(spawn: S, cleanup: Fut) -> Self {
+        Self {
+            cleanup: Some((cleanup, spawn)),
+        }
+    }
+}
+
+impl<Fut: Future, S: FnOnce(Fut)> Drop for AsyncDefer<Fut, S> {
+    fn drop(&mut self) {
+        let Some((cleanup, spawn)) = self.cleanup.take() else {
+            return;
+        };
+        if crate::debug::skipped(|| format!("async cleanup `{}`", std::any::type_name::<Fut>())) {
+            return;
+        }
+        let _span = crate::hooks::executing::<Fut>("AsyncDefer");
+        spawn(cleanup);
+    }
+}
+
+/// A guard aborting a task (or a set of tasks) when it goes out of scope, so background tasks don't outlive the scope that spawned them.
+///
+/// The guard holds the task's handle, and the closure aborting it, e.g. `|task| task.abort()` for a `tokio::task::JoinHandle`,
+/// or `|set| set.abort_all()` for a `tokio::task::JoinSet`. It dereferences to the handle, so the task can still be awaited (e.g. `(&mut *task).await`)
+/// or managed while the guard is held. [`AbortOnDrop::disarm`] and [`AbortOnDrop::detach`] let the task keep running past the scope.
+///
+/// No tokio-specific constructor is provided (the crate doesn't depend on any runtime), the abort closure is the integration point,
+/// as shown above. The task is aborted even while cleanup is [skipped](crate::debug), as the guard exists to bound the task's lifetime.
+///
+/// **Note: `AbortOnDrop` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, aborting the task!**
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use defer_rs::future::AbortOnDrop;
+///
+/// async fn handle_connection(connection: Connection) {
+///     let _heartbeat = AbortOnDrop::new(tokio::spawn(heartbeat(connection.clone())), |task| task.abort());
+///     // ... the heartbeat task is aborted once the connection is handled (or the handler is cancelled) ...
+/// }
+/// ```
+///
+/// See also: [`AsyncDefer`], and [`PoolGuard`](crate::PoolGuard).
+#[must_use = "AbortOnDrop MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, aborting the task!"]
+pub struct AbortOnDrop<H, F: FnOnce(&mut H)> {
+    handle: Option<H>,
+    abort: Option<F>,
+}
+
+impl<H, F: FnOnce(&mut H)> AbortOnDrop<H, F> {
+    /// Creates a new `AbortOnDrop` instance, calling `abort` with `handle` when it goes out of scope.
+    ///
+    /// **Note: `AbortOnDrop` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, aborting the task!**
+    pub fn new(handle: H, abort: F) -> Self {
+        Self {
+            handle: Some(handle),
+            abort: Some(abort),
+        }
+    }
+
+    /// Disarms the guard, the task won't be aborted when the guard goes out of scope (the handle is still dropped along with it).
+    pub fn disarm(&mut self) {
+        self.abort = None;
+    }
+
+    /// Consumes the guard, returning the handle without aborting the task.
+    pub fn detach(mut self) -> H {
+        // `self.handle` is only `None` once the `AbortOnDrop` is detached or dropped
+        self.handle.take().unwrap()
+    }
+}
+
+impl<H, F: FnOnce(&mut H)> std::ops::Deref for AbortOnDrop<H, F> {
+    type Target = H;
+
+    fn deref(&self) -> &H {
+        self.handle.as_ref().unwrap()
+    }
+}
+
+impl<H, F: FnOnce(&mut H)> std::ops::DerefMut for AbortOnDrop<H, F> {
+    fn deref_mut(&mut self) -> &mut H {
+        self.handle.as_mut().unwrap()
+    }
+}
+
+impl<H, F: FnOnce(&mut H)> Drop for AbortOnDrop<H, F> {
+    fn drop(&mut self) {
+        let (Some(mut handle), Some(abort)) = (self.handle.take(), self.abort.take()) else {
+            return;
+        };
+        // The task must not outlive the guard, so the abort is never skipped
+        let _span = crate::hooks::executing::<F>("AbortOnDrop");
+        abort(&mut handle);
+    }
+}
+
+/// Creates a guard executing `deferred` once `trigger` resolves, or when the guard goes out of scope, whichever comes first.
+///
+/// This lets cancellation-driven teardown (e.g. a `tokio_util::sync::CancellationToken` being cancelled) and scope-exit teardown
+/// share one mechanism: the closure is executed exactly once, either by the returned [`UntilWatcher`] future once the trigger resolves,
+/// or by the [`UntilGuard`]'s drop if it happens earlier. The watcher must be spawned (or otherwise polled) for the trigger to be observed,
+/// it completes (without waiting for the trigger) once the guard is dropped.
+///
+/// Any future can be used as the trigger, e.g. `token.cancelled_owned()`, a shutdown signal, or a timer.
+/// There's no `tokio-util` feature (nor a `CancellationToken`-specific constructor): the token is passed in as the future
+/// returned by `CancellationToken::cancelled_owned`, and spawning the watcher is left to the user, as shown below.
+///
+/// **Note: `UntilGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use defer_rs::future::defer_until;
+/// use tokio_util::sync::CancellationToken;
+///
+/// async fn serve(token: CancellationToken) {
+///     let (_guard, watcher) = defer_until(token.cancelled_owned(), || println!("Deregistering the service..."));
+///     tokio::spawn(watcher);
+///
+///     // The service is deregistered either as soon as `token` is cancelled, or once `serve` returns
+/// }
+/// ```
+pub fn defer_until<Fut, F>(trigger: Fut, deferred: F) -> (UntilGuard<F>, UntilWatcher<Fut, F>)
+where
+    Fut: Future,
+    F: FnOnce(),
+{
+    let shared = Arc::new(Mutex::new(UntilState {
+        deferred: Some(deferred),
+        waker: None,
+    }));
+    (UntilGuard(shared.clone()), UntilWatcher { trigger, shared })
+}
+
+struct UntilState<F> {
+    // The closure, until it's executed (or the guard is dropped while skipping cleanup)
+    deferred: Option<F>,
+    // The watcher's waker, woken once the guard is dropped
+    waker: Option<Waker>,
+}
+
+/// The guard returned by [`defer_until`], executing the closure when it goes out of scope (unless the trigger already resolved).
+#[must_use = "UntilGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct UntilGuard<F: FnOnce()>(Arc<Mutex<UntilState<F>>>);
+
+impl<F: FnOnce()> UntilGuard<F> {
+    /// Returns `true` if the closure was already executed (or is executing) due to the trigger resolving.
+    pub fn has_fired(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .deferred
+            .is_none()
+    }
+}
+
+impl<F: FnOnce()> Drop for UntilGuard<F> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let deferred = state.deferred.take();
+        let waker = state.waker.take();
+        drop(state);
+        // The watcher no longer has anything to wait for
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        if deferred.is_some()
+            && crate::debug::skipped(|| {
+                format!("deferred closure `{}`", std::any::type_name::<F>())
+            })
+        {
+            return;
+        }
+        if let Some(deferred) = deferred {
+            let _span = crate::hooks::executing::<F>("UntilGuard");
+            deferred();
+        }
+    }
+}
+
+/// The future returned by [`defer_until`], executing the closure once the trigger resolves (unless the guard was already dropped).
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct UntilWatcher<Fut, F> {
+    trigger: Fut,
+    shared: Arc<Mutex<UntilState<F>>>,
+}
+
+impl<Fut: Future, F: FnOnce()> Future for UntilWatcher<Fut, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // SAFETY: `trigger` is structurally pinned, it's never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+
+        {
+            let mut state = this.shared.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.deferred.is_none() {
+                return Poll::Ready(());
+            }
+            state.waker = Some(cx.waker().clone());
+        }
+        if unsafe { Pin::new_unchecked(&mut this.trigger) }
+            .poll(cx)
+            .is_pending()
+        {
+            return Poll::Pending;
+        }
+
+        // If the guard was dropped in the meantime, it already took the closure
+        let mut state = this.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        let deferred = state.deferred.take();
+        // Decided before releasing the lock, so the closure is already skipped (or not) once `has_fired` returns `true`
+        let skipped = deferred.is_some()
+            && crate::debug::skipped(|| {
+                format!("deferred closure `{}`", std::any::type_name::<F>())
+            });
+        drop(state);
+        if skipped {
+            return Poll::Ready(());
+        }
+        if let Some(deferred) = deferred {
+            let _span = crate::hooks::executing::<F>("UntilGuard");
+            deferred();
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<'a> DeferGroup<'a> {
+    /// Arms the `DeferGroup` with a trigger: its closures are executed (first to last) once `trigger` resolves, or when the returned guard
+    /// goes out of scope, whichever comes first, see [`defer_until`].
+    ///
+    /// This enables graceful-shutdown patterns, where teardown is either initiated externally (e.g. through a [`Trigger`]), or at scope exit.
+    ///
+    /// **Note: `UntilGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::{future::Trigger, DeferGroup};
+    ///
+    /// # fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    /// #     let mut f = std::pin::pin!(f);
+    /// #     let waker = std::task::Waker::noop();
+    /// #     let mut cx = std::task::Context::from_waker(&waker);
+    /// #     loop { if let std::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) { return out; } }
+    /// # }
+    /// let shutdown = Trigger::new();
+    ///
+    /// let mut teardown = DeferGroup::new();
+    /// teardown.push(Box::new(|| println!("Stopping the workers...")));
+    /// teardown.push(Box::new(|| println!("Flushing the logs...")));
+    /// let (teardown, watcher) = teardown.until(shutdown.fired());
+    ///
+    /// // e.g. from a signal handler
+    /// shutdown.fire();
+    /// block_on(watcher);
+    /// assert!(teardown.has_fired());
+    /// ```
+    pub fn until<Fut: Future>(
+        self,
+        trigger: Fut,
+    ) -> (
+        UntilGuard<impl FnOnce() + 'a>,
+        UntilWatcher<Fut, impl FnOnce() + 'a>,
+    ) {
+        defer_until(trigger, move || drop(self))
+    }
+}
+
+impl<'a> SendDeferGroup<'a> {
+    /// Arms the `SendDeferGroup` with a trigger, see [`DeferGroup::until`].
+    ///
+    /// Unlike with a `DeferGroup`, the returned watcher is `Send` (as long as the trigger is), so it can be spawned on a multi-threaded executor.
+    ///
+    /// **Note: `UntilGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn until<Fut: Future>(
+        self,
+        trigger: Fut,
+    ) -> (
+        UntilGuard<impl FnOnce() + Send + 'a>,
+        UntilWatcher<Fut, impl FnOnce() + Send + 'a>,
+    ) {
+        defer_until(trigger, move || drop(self))
+    }
+}
+
+/// Wraps a shutdown signal, executing `cleanup` as soon as it resolves, before resolving to the signal's output.
+///
+/// The returned future is meant to be passed as the shutdown signal of a server supporting graceful shutdown
+/// (e.g. axum's `with_graceful_shutdown`, or hyper-util's `GracefulShutdown`): the cleanup is executed by the server task once the signal fires,
+/// before the server stops (and its future is dropped). If the returned future is dropped before the signal resolves, `cleanup` is dropped
+/// without being executed. The cleanup is typically the execution of a shared group (see [`SyncDeferGroup::on_shutdown`]),
+/// or of the [registry](crate::registry) (i.e. `on_shutdown(signal, registry::run_all)`).
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use defer_rs::{future::on_shutdown, registry};
+///
+/// async fn serve(listener: tokio::net::TcpListener, app: axum::Router) {
+///     registry::register(|| println!("Closing the database pool..."));
+///     axum::serve(listener, app)
+///         .with_graceful_shutdown(on_shutdown(
+///             async { tokio::signal::ctrl_c().await.unwrap() },
+///             registry::run_all,
+///         ))
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub fn on_shutdown<S: Future, F: FnOnce()>(signal: S, cleanup: F) -> OnShutdown<S, F> {
+    OnShutdown {
+        signal,
+        cleanup: Some(cleanup),
+    }
+}
+
+/// The future returned by [`on_shutdown`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct OnShutdown<S, F> {
+    signal: S,
+    cleanup: Option<F>,
+}
+
+impl<S: Future, F: FnOnce()> Future for OnShutdown<S, F> {
+    type Output = S::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<S::Output> {
+        // SAFETY: `signal` is structurally pinned, it's never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        let out = match unsafe { Pin::new_unchecked(&mut this.signal) }.poll(cx) {
+            Poll::Ready(out) => out,
+            Poll::Pending => return Poll::Pending,
+        };
+        if let Some(cleanup) = this.cleanup.take() {
+            if !crate::debug::skipped(|| {
+                format!("shutdown cleanup `{}`", std::any::type_name::<F>())
+            }) {
+                let _span = crate::hooks::executing::<F>("OnShutdown");
+                cleanup();
+            }
+        }
+        Poll::Ready(out)
+    }
+}
+
+impl<'a> SyncDeferGroup<'a> {
+    /// Wraps a shutdown signal, executing the queued closures (first to last) as soon as it resolves, see [`on_shutdown`].
+    ///
+    /// The group can still be used to queue closures while the signal is pending (e.g. from request handlers).
+    /// Closures queued after the signal resolved are executed when the group goes out of scope, like the ones queued before,
+    /// if the returned future is dropped before the signal resolves.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::{future::Trigger, SyncDeferGroup};
+    ///
+    /// # fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    /// #     let mut f = std::pin::pin!(f);
+    /// #     let waker = std::task::Waker::noop();
+    /// #     let mut cx = std::task::Context::from_waker(&waker);
+    /// #     loop { if let std::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) { return out; } }
+    /// # }
+    /// static CLEANUP: SyncDeferGroup<'static> = SyncDeferGroup::new();
+    ///
+    /// let shutdown = Trigger::new();
+    /// // With axum, this would be passed to `with_graceful_shutdown`
+    /// let signal = CLEANUP.on_shutdown(shutdown.fired());
+    ///
+    /// CLEANUP.push(Box::new(|| println!("Closing the database pool...")));
+    /// CLEANUP.push(Box::new(|| println!("Flushing the metrics...")));
+    ///
+    /// // e.g. from a signal handler
+    /// shutdown.fire();
+    /// block_on(signal);
+    /// ```
+    pub fn on_shutdown<S: Future>(
+        &self,
+        signal: S,
+    ) -> OnShutdown<S, impl FnOnce() + use<'_, 'a, S>> {
+        on_shutdown(signal, || self.run_pending())
+    }
+}
+
+/// A cloneable, one-shot flag that can be fired from anywhere, and awaited using [`Trigger::fired`].
+///
+/// This is meant to initiate teardown externally, e.g. as the trigger of [`defer_until`] or [`DeferGroup::until`].
+/// All the clones of a `Trigger` share the same flag.
+#[derive(Clone, Default)]
+pub struct Trigger(Arc<Mutex<TriggerState>>);
+
+#[derive(Default)]
+struct TriggerState {
+    fired: bool,
+    wakers: Vec<Waker>,
+}
+
+impl Trigger {
+    /// Creates a new `Trigger`, which isn't fired yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fires the trigger, waking every task awaiting it. Firing an already fired trigger has no effect.
+    pub fn fire(&self) {
+        let wakers = {
+            let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            state.fired = true;
+            std::mem::take(&mut state.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Returns `true` if the trigger was fired.
+    pub fn is_fired(&self) -> bool {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).fired
+    }
+
+    /// Returns a future resolving once the trigger is fired.
+    pub fn fired(&self) -> Fired {
+        Fired(self.clone())
+    }
+}
+
+/// The future returned by [`Trigger::fired`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Fired(Trigger);
+
+impl Future for Fired {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0 .0.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.fired {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::task::Wake;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // A minimal executor, parking the current thread until the future is woken
+    pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = std::pin::pin!(f);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(out) => return out,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    // A timer future backed by a thread, so tests don't need an async runtime
+    pub(crate) async fn sleep(duration: Duration) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut timer = None;
+        std::future::poll_fn(|cx| {
+            if rx.try_recv().is_ok() {
+                return Poll::Ready(());
+            }
+            if timer.is_none() {
+                let (tx, waker) = (tx.clone(), cx.waker().clone());
+                timer = Some(thread::spawn(move || {
+                    thread::sleep(duration);
+                    let _ = tx.send(());
+                    waker.wake();
+                }));
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    #[test]
+    fn test_finally() {
+        let rec = crate::testing::ExecutionRecorder::new();
+        let res = block_on(finally(
+            async {
+                sleep(Duration::from_millis(10)).await;
+                rec.record("future");
+                1
+            },
+            async {
+                sleep(Duration::from_millis(10)).await;
+                rec.record("cleanup");
+            },
+            rec.callback("fallback"),
+        ));
+        assert_eq!(res, 1);
+        rec.assert_order(&["future", "cleanup"]);
+
+        // Cancelled while the cleanup is pending
+        let rec = crate::testing::ExecutionRecorder::new();
+        let res = block_on(with_deadline(
+            finally(
+                async { 1 },
+                std::future::pending(),
+                rec.callback("fallback"),
+            ),
+            sleep(Duration::from_millis(10)),
+            || 0,
+        ));
+        assert_eq!(res, 0);
+        rec.assert_order(&["fallback"]);
+    }
+
+    #[test]
+    fn test_with_deadline() {
+        let res = block_on(with_deadline(
+            async { "done" },
+            sleep(Duration::from_secs(60)),
+            || "timed out",
+        ));
+        assert_eq!(res, "done");
+
+        let res = block_on(with_deadline(
+            async {
+                sleep(Duration::from_secs(60)).await;
+                "done"
+            },
+            sleep(Duration::from_millis(10)),
+            || "timed out",
+        ));
+        assert_eq!(res, "timed out");
+    }
+
+    #[test]
+    fn test_defer_until() {
+        let rec = crate::testing::ExecutionRecorder::new();
+
+        let (guard, watcher) =
+            defer_until(sleep(Duration::from_millis(10)), rec.callback("triggered"));
+        block_on(watcher);
+        assert!(guard.has_fired());
+        drop(guard);
+        rec.assert_order(&["triggered"]);
+
+        let (guard, watcher) = defer_until(std::future::pending::<()>(), rec.callback("dropped"));
+        let watcher = thread::spawn(move || block_on(watcher));
+        thread::sleep(Duration::from_millis(10));
+        drop(guard);
+        // The watcher completes once the guard is dropped
+        watcher.join().unwrap();
+        rec.assert_order(&["triggered", "dropped"]);
+    }
+
+    #[test]
+    fn test_defer_group_until() {
+        let rec = crate::testing::ExecutionRecorder::new();
+        let shutdown = Trigger::new();
+
+        let mut group = SendDeferGroup::new();
+        group.push(Box::new(rec.callback("1st")));
+        group.push(Box::new(rec.callback("2nd")));
+        let (guard, watcher) = group.until(shutdown.fired());
+        let watcher = thread::spawn(move || block_on(watcher));
+        thread::sleep(Duration::from_millis(10));
+        assert!(rec.is_empty());
+
+        shutdown.fire();
+        watcher.join().unwrap();
+        rec.assert_order(&["1st", "2nd"]);
+        assert!(guard.has_fired());
+        drop(guard);
+        rec.assert_order(&["1st", "2nd"]);
+
+        let mut group = DeferGroup::new();
+        group.push(Box::new(rec.callback("dropped")));
+        let (guard, _watcher) = group.until(shutdown.fired());
+        drop(guard);
+        rec.assert_order(&["1st", "2nd", "dropped"]);
+    }
+
+    #[test]
+    fn test_on_shutdown() {
+        let rec = crate::testing::ExecutionRecorder::new();
+        let shutdown = Trigger::new();
+        let group = SyncDeferGroup::new();
+
+        let signal = group.on_shutdown(async {
+            shutdown.fired().await;
+            "signalled"
+        });
+        group.push(Box::new(rec.callback("1st")));
+        group.push(Box::new(rec.callback("2nd")));
+        let out = thread::scope(|s| {
+            let server = s.spawn(|| block_on(signal));
+            thread::sleep(Duration::from_millis(10));
+            assert!(rec.is_empty());
+            shutdown.fire();
+            server.join().unwrap()
+        });
+        assert_eq!(out, "signalled");
+        rec.assert_order(&["1st", "2nd"]);
+
+        // Dropped before the signal resolved
+        drop(on_shutdown(
+            std::future::pending::<()>(),
+            rec.callback("dropped"),
+        ));
+        group.push(Box::new(rec.callback("3rd")));
+        drop(group);
+        rec.assert_order(&["1st", "2nd", "3rd"]);
+    }
+
+    #[test]
+    fn test_async_defer() {
+        let rec = crate::testing::ExecutionRecorder::new();
+        {
+            let _cleanup =
+                AsyncDefer::new(|cleanup| drop(thread::spawn(move || block_on(cleanup))), {
+                    let rec = rec.clone();
+                    async move {
+                        spawn_blocking(|| thread::sleep(Duration::from_millis(10))).await;
+                        rec.record("cleaned up");
+                    }
+                });
+        }
+        // The cleanup is spawned, not awaited
+        assert!(rec.is_empty());
+        for _ in 0..500 {
+            if !rec.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        rec.assert_order(&["cleaned up"]);
+    }
+
+    #[test]
+    fn test_abort_on_drop() {
+        let task = Trigger::new();
+        drop(AbortOnDrop::new(task.clone(), |task| task.fire()));
+        assert!(task.is_fired());
+
+        let task = Trigger::new();
+        let mut guard = AbortOnDrop::new(task.clone(), |task| task.fire());
+        guard.disarm();
+        drop(guard);
+        let guard = AbortOnDrop::new(task.clone(), |task| task.fire());
+        assert!(!guard.detach().is_fired());
+    }
+
+    #[test]
+    fn test_remove_on_drop() {
+        // Unique to the process, so concurrent test runs don't remove each other's directory
+        let dir = std::env::temp_dir().join(format!(
+            "defer-rs-test-remove-on-drop-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/file"), "").unwrap();
+
+        block_on(RemoveOnDrop::new(&dir).remove()).unwrap();
+        assert!(!dir.exists());
+        // Already removed
+        block_on(RemoveOnDrop::new(&dir).remove()).unwrap();
+
+        std::fs::write(&dir, "").unwrap();
+        drop(RemoveOnDrop::new(&dir));
+        // The removal is executed in the background
+        for _ in 0..500 {
+            if !dir.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!dir.exists());
+    }
+}
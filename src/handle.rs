@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{run_entries, DeferGroup, Savepoint};
+
+/// A shared handle to a [`DeferGroup`], letting closures be queued on (and the group be managed through) multiple bindings.
+///
+/// This is what [`defer_scope_init!(let name)`](crate::defer_scope_init) binds to `name`: the same group is also bound to
+/// the hidden identifier [`defer_scope!`](crate::defer_scope) registers to, so macro registrations can be mixed with direct
+/// method calls (e.g. [`DeferScope::len`], or [`DeferScope::rollback_to`]) on the same group.
+///
+/// All the clones of a `DeferScope` refer to the same group, whose closures are executed when the last clone goes out of scope.
+/// As the handle doesn't borrow the group, a clone must not be captured by one of its own closures (the group would never be dropped).
+///
+/// **Note: `DeferScope` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!(let cleanup);
+///
+/// defer_scope!(println!("Closing the connection..."));
+/// let before_upload = cleanup.savepoint();
+/// defer_scope!(println!("Removing the uploaded file..."));
+/// assert_eq!(cleanup.len(), 2);
+///
+/// // The upload was committed, its file must be kept
+/// cleanup.rollback_to(before_upload);
+/// assert_eq!(cleanup.len(), 1);
+/// ```
+///
+/// See also: [`DeferGroup`], and [`defer_scope_init!`](crate::defer_scope_init).
+#[must_use = "DeferScope MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!"]
+#[derive(Clone, Default)]
+pub struct DeferScope<'a>(Rc<RefCell<DeferGroup<'a>>>);
+
+impl<'a> DeferScope<'a> {
+    /// Creates a new `DeferScope` handle, to a new, empty `DeferGroup`.
+    ///
+    /// **Note: `DeferScope` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the group's queue, see [`DeferGroup::add`].
+    pub fn add(&self, f: Box<dyn FnOnce() + 'a>) {
+        self.0.borrow_mut().add(f);
+    }
+
+    /// Pushes a deferred closure to the end of the group's queue, see [`DeferGroup::push`].
+    pub fn push(&self, f: Box<dyn FnOnce() + 'a>) {
+        self.0.borrow_mut().push(f);
+    }
+
+    /// Adds a deferred closure with the given priority to the group's queue, see [`DeferGroup::add_with_priority`].
+    pub fn add_with_priority(&self, priority: i32, f: Box<dyn FnOnce() + 'a>) {
+        self.0.borrow_mut().add_with_priority(priority, f);
+    }
+
+    /// Pushes a deferred closure with the given priority to the group's queue, see [`DeferGroup::push_with_priority`].
+    pub fn push_with_priority(&self, priority: i32, f: Box<dyn FnOnce() + 'a>) {
+        self.0.borrow_mut().push_with_priority(priority, f);
+    }
+
+    /// Returns the number of deferred closures queued in the group.
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    /// Returns `true` if no deferred closures are queued in the group.
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Returns a [`Savepoint`] marking the current point in the registration history of the group, see [`DeferGroup::savepoint`].
+    pub fn savepoint(&self) -> Savepoint {
+        self.0.borrow().savepoint()
+    }
+
+    /// Cancels (removes without executing) every closure queued after the given [`Savepoint`] was taken, see [`DeferGroup::rollback_to`].
+    pub fn rollback_to(&self, savepoint: Savepoint) {
+        self.0.borrow_mut().rollback_to(savepoint);
+    }
+
+    /// Executes every closure queued after the given [`Savepoint`] was taken immediately, see [`DeferGroup::run_since`].
+    pub fn run_since(&self, savepoint: Savepoint) {
+        // The group isn't borrowed while the closures are executed
+        let since = self.0.borrow_mut().take_since(savepoint);
+        run_entries(since);
+    }
+
+    /// Executes the first `n` queued closures immediately, see [`DeferGroup::run_first`].
+    pub fn run_first(&self, n: usize) {
+        let first = {
+            let mut group = self.0.borrow_mut();
+            let n = n.min(group.len());
+            group.take_range(..n)
+        };
+        run_entries(first);
+    }
+
+    /// Calls `f` with the group, for the rest of the [`DeferGroup`] API.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the group is accessed through another clone of the handle from within `f`.
+    pub fn with_group<R>(&self, f: impl FnOnce(&mut DeferGroup<'a>) -> R) -> R {
+        f(&mut self.0.borrow_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::ExecutionRecorder;
+    use crate::{defer_scope, defer_scope_init};
+
+    #[test]
+    fn test_defer_scope_handle() {
+        let rec = ExecutionRecorder::new();
+        {
+            defer_scope_init!(let scope);
+            defer_scope!(rec.record("last"));
+            scope.add(Box::new(rec.callback("2nd")));
+
+            let savepoint = scope.savepoint();
+            defer_scope!(rec.record("cancelled"));
+            scope.rollback_to(savepoint);
+
+            defer_scope!(rec.record("1st"));
+            scope.run_first(1);
+            assert_eq!(scope.len(), 2);
+        }
+        rec.assert_order(&["1st", "2nd", "last"]);
+    }
+}
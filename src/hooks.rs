@@ -0,0 +1,229 @@
+//! Process-wide observer hooks, called whenever a deferred closure is registered or executed.
+//!
+//! An application can install an [`on_register`] and an [`on_execute`] callback to audit its cleanups, or to detect
+//! leaked ones (closures that were registered, but never executed), without wrapping every guard and group.
+//! Each callback is passed an [`Event`], describing where the closure was registered (or executed from).
+//!
+//! Registrations are reported for closures queued on a group (e.g. [`DeferGroup`](crate::DeferGroup), or
+//! [`SyncDeferGroup`](crate::SyncDeferGroup)) and in the [`registry`](crate::registry). Creating a guard
+//! (e.g. [`Defer::new`](crate::Defer::new)) isn't reported, as guards can be created in `const` contexts,
+//! but executing one is. Executions are reported right before the closure is called, for both guards and groups.
+//! Closures skipped through [`debug::skip_cleanup`](crate::debug::skip_cleanup) aren't reported as executed.
+//!
+//! Until a hook is installed, the only overhead is a single atomic load per registration/execution.
+//!
+//! # Example
+//!
+//! ```rust
+//! use defer_rs::{hooks, DeferGroup};
+//! use std::sync::atomic::{AtomicIsize, Ordering};
+//!
+//! static PENDING: AtomicIsize = AtomicIsize::new(0);
+//!
+//! hooks::on_register(|_| {
+//!     PENDING.fetch_add(1, Ordering::Relaxed);
+//! });
+//! hooks::on_execute(|event| {
+//!     PENDING.fetch_sub(1, Ordering::Relaxed);
+//!     eprintln!("executing a closure queued on a `{}`", event.source());
+//! });
+//! {
+//!     let mut group = DeferGroup::new();
+//!     group.add(Box::new(|| {}));
+//!     group.add(Box::new(|| {}));
+//! }
+//! hooks::clear();
+//!
+//! assert_eq!(PENDING.load(Ordering::Relaxed), 0);
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// The kind of an [`Event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A deferred closure was registered.
+    Register,
+    /// A deferred closure is about to be executed.
+    Execute,
+}
+
+/// Describes a deferred closure being registered or executed, passed to the hooks, see the [module level documentation](self).
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    kind: EventKind,
+    source: &'static str,
+    closure: Option<&'static str>,
+}
+
+impl Event {
+    /// Returns whether the closure is being registered or executed.
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    /// Returns the name of the guard or group the closure was registered on (e.g. `"Defer"`, or `"DeferGroup"`).
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+
+    /// Returns the type name of the closure, if it's known (it isn't for closures queued on groups, which are type-erased).
+    pub fn closure(&self) -> Option<&'static str> {
+        self.closure
+    }
+}
+
+type Hook = Arc<dyn Fn(&Event) + Send + Sync>;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static ON_REGISTER: RwLock<Option<Hook>> = RwLock::new(None);
+static ON_EXECUTE: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Installs the hook called whenever a deferred closure is registered, replacing the previous one (if any).
+pub fn on_register(hook: impl Fn(&Event) + Send + Sync + 'static) {
+    install(&ON_REGISTER, Arc::new(hook));
+}
+
+/// Installs the hook called whenever a deferred closure is about to be executed, replacing the previous one (if any).
+pub fn on_execute(hook: impl Fn(&Event) + Send + Sync + 'static) {
+    install(&ON_EXECUTE, Arc::new(hook));
+}
+
+/// Removes both hooks.
+pub fn clear() {
+    // Both slots are held while resetting the flag, so a hook installed concurrently can't be left unreachable
+    let mut on_register = ON_REGISTER.write().unwrap_or_else(PoisonError::into_inner);
+    let mut on_execute = ON_EXECUTE.write().unwrap_or_else(PoisonError::into_inner);
+    *on_register = None;
+    *on_execute = None;
+    INSTALLED.store(false, Ordering::Release);
+}
+
+fn install(slot: &RwLock<Option<Hook>>, hook: Hook) {
+    let mut slot = slot.write().unwrap_or_else(PoisonError::into_inner);
+    *slot = Some(hook);
+    // Set while the slot is still held, so it can't be overwritten by a concurrent `clear`
+    INSTALLED.store(true, Ordering::Release);
+}
+
+#[inline]
+fn notify(slot: &RwLock<Option<Hook>>, event: Event) {
+    if INSTALLED.load(Ordering::Acquire) {
+        notify_installed(slot, event);
+    }
+}
+
+// Kept out of the guards' drop glue, as hooks are rarely installed
+#[cold]
+#[inline(never)]
+fn notify_installed(slot: &RwLock<Option<Hook>>, event: Event) {
+    // The lock is released before calling the hook, so the hook may (re)install hooks, or register closures
+    let hook = slot.read().unwrap_or_else(PoisonError::into_inner).clone();
+    if let Some(hook) = hook {
+        hook(&event);
+    }
+}
+
+/// Reports a closure registered on `source`.
+#[inline]
+pub(crate) fn registered(source: &'static str) {
+    notify(
+        &ON_REGISTER,
+        Event {
+            kind: EventKind::Register,
+            source,
+            closure: None,
+        },
+    );
+}
+
+/// Reports a closure (of type `F`) about to be executed by `source`, until the returned [`Span`] is dropped.
+// Public (but hidden) for the code generated by `make_guard!`
+#[doc(hidden)]
+#[inline]
+pub fn executing<F>(source: &'static str) -> Span {
+    notify(
+        &ON_EXECUTE,
+        Event {
+            kind: EventKind::Execute,
+            source,
+            closure: Some(std::any::type_name::<F>()),
+        },
+    );
+    #[cfg(feature = "event-log")]
+    crate::event_log::record(source, Some(std::any::type_name::<F>()));
+    Span::new(source)
+}
+
+/// Reports a (type-erased) queued closure about to be executed by `source`, until the returned [`Span`] is dropped.
+#[inline]
+pub(crate) fn executing_queued(source: &'static str) -> Span {
+    notify(
+        &ON_EXECUTE,
+        Event {
+            kind: EventKind::Execute,
+            source,
+            closure: None,
+        },
+    );
+    #[cfg(feature = "event-log")]
+    crate::event_log::record(source, None);
+    Span::new(source)
+}
+
+/// Covers the execution of a closure, reported to the profiler (if any, see the `profile` feature) as a scope.
+#[doc(hidden)]
+#[must_use = "the closure must be executed while the Span is alive"]
+pub struct Span {
+    #[cfg(feature = "profile")]
+    _scope: crate::profile::ProfileScope,
+}
+
+impl Span {
+    #[inline]
+    #[cfg_attr(not(feature = "profile"), allow(unused_variables))]
+    fn new(source: &'static str) -> Self {
+        Self {
+            #[cfg(feature = "profile")]
+            _scope: crate::profile::scope(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+    use crate::{Defer, DeferGroup};
+    use std::thread;
+
+    #[test]
+    fn test_hooks_observe_guards_and_groups() {
+        let rec = ExecutionRecorder::new();
+        // Other tests run concurrently, only this thread's events are recorded
+        let test_thread = thread::current().id();
+        let hook = |rec: ExecutionRecorder| {
+            move |event: &Event| {
+                if thread::current().id() == test_thread {
+                    rec.record(format!("{:?} {}", event.kind(), event.source()));
+                }
+            }
+        };
+        on_register(hook(rec.clone()));
+        on_execute(hook(rec.clone()));
+        {
+            let _defer = Defer::new(|| {});
+            let mut group = DeferGroup::new();
+            group.add(Box::new(|| {}));
+        }
+        clear();
+        // Back to the single atomic load per registration/execution
+        assert!(!INSTALLED.load(Ordering::Acquire));
+        {
+            let _defer = Defer::new(|| {});
+        }
+
+        rec.assert_order(&["Register DeferGroup", "Execute DeferGroup", "Execute Defer"]);
+    }
+}
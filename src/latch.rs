@@ -0,0 +1,157 @@
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A countdown latch, letting a thread wait for a number of workers to finish (like Go's `sync.WaitGroup`).
+///
+/// The count is incremented for each worker (using [`Latch::add`], or [`Latch::enter`]), and decremented once it's done.
+/// [`Latch::wait`] blocks until the count reaches zero. A `Latch` is a (cloneable) handle, all its clones share the same count.
+///
+/// Decrementing through a [`LatchGuard`] (the `defer wg.Done()` idiom) makes sure a worker signals its completion even if it panics,
+/// so the waiting thread is never left hanging.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::Latch;
+///
+/// let latch = Latch::new(0);
+/// for shard in 0..4 {
+///     let done = latch.enter();
+///     std::thread::spawn(move || {
+///         let _done = done;
+///         println!("Indexing shard #{shard}...");
+///     });
+/// }
+/// latch.wait();
+/// assert_eq!(latch.count(), 0);
+/// ```
+#[derive(Clone, Default)]
+pub struct Latch(Arc<(Mutex<usize>, Condvar)>);
+
+impl Latch {
+    /// Creates a new `Latch`, with the given initial count.
+    pub fn new(count: usize) -> Self {
+        Self(Arc::new((Mutex::new(count), Condvar::new())))
+    }
+
+    /// Increments the count by `n`.
+    pub fn add(&self, n: usize) {
+        *self.lock() += n;
+    }
+
+    /// Decrements the count by one, waking the waiting threads once it reaches zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the count is already zero.
+    pub fn count_down(&self) {
+        let mut count = self.lock();
+        *count = count
+            .checked_sub(1)
+            .expect("`Latch::count_down` called with a count of zero");
+        if *count == 0 {
+            self.0 .1.notify_all();
+        }
+    }
+
+    /// Increments the count by one, returning a guard decrementing it when it goes out of scope.
+    ///
+    /// **Note: `LatchGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, decrementing the count!**
+    pub fn enter(&self) -> LatchGuard {
+        self.add(1);
+        LatchGuard::new(self.clone())
+    }
+
+    /// Returns the current count.
+    pub fn count(&self) -> usize {
+        *self.lock()
+    }
+
+    /// Blocks the current thread until the count reaches zero.
+    pub fn wait(&self) {
+        let count = self.lock();
+        drop(
+            self.0
+                 .1
+                .wait_while(count, |count| *count > 0)
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+
+    /// Blocks the current thread until the count reaches zero, or `timeout` elapses, returning `true` if the count reached zero.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.lock();
+        while *count > 0 {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            count = self
+                .0
+                 .1
+                .wait_timeout(count, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+
+    // A panicking worker never leaves the count inconsistent, so poisoning is ignored
+    fn lock(&self) -> std::sync::MutexGuard<'_, usize> {
+        self.0 .0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A guard decrementing a [`Latch`] when it goes out of scope (including when unwinding), see [`Latch::enter`].
+///
+/// **Note: `LatchGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, decrementing the count!**
+#[must_use = "LatchGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, decrementing the count!"]
+pub struct LatchGuard(Latch);
+
+impl LatchGuard {
+    /// Creates a new `LatchGuard`, decrementing `latch` (without incrementing it first) when it goes out of scope.
+    ///
+    /// **Note: `LatchGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, decrementing the count!**
+    pub fn new(latch: Latch) -> Self {
+        Self(latch)
+    }
+}
+
+impl Drop for LatchGuard {
+    fn drop(&mut self) {
+        // Counting down is a synchronization signal (not a cleanup), it's never skipped, nor reported to the hooks
+        self.0.count_down();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_latch_guard_counts_down_on_panic() {
+        let latch = Latch::new(0);
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let done = latch.enter();
+                thread::spawn(move || {
+                    let _done = done;
+                    thread::sleep(Duration::from_millis(10));
+                    assert!(i % 2 == 0, "worker #{i} failed");
+                })
+            })
+            .collect();
+        // The failed workers count down as well
+        assert!(latch.wait_timeout(Duration::from_secs(10)));
+        let failed = workers
+            .into_iter()
+            .map(|worker| worker.join())
+            .filter(Result::is_err)
+            .count();
+        assert_eq!(failed, 2);
+
+        let _pending = latch.enter();
+        assert!(!latch.wait_timeout(Duration::from_millis(10)));
+    }
+}
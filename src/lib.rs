@@ -3,9 +3,120 @@
 // This `extern` is to facilitate easier crate resolution in tests for the proc generated code
 extern crate self as defer_rs;
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::mem::ManuallyDrop;
+
 #[cfg(not(doc))]
 pub use defer_rs_impl::{defer_scope, defer_scope_init};
 
+pub use defer_rs_impl::{
+    defer_err, defer_test, err_context, errdefer, try_defer_scope, unified_defers,
+};
+
+// Only used by `defer!` (and `defer_guard!`), downstream crates don't depend on `defer_rs_impl` directly
+#[doc(hidden)]
+pub use defer_rs_impl::{call_indexed, defer_priority};
+
+#[cfg(feature = "arena")]
+mod arena;
+#[cfg(feature = "arena")]
+pub use arena::{ArenaDeferGroup, DeferArena, Frame, FramePool};
+
+mod budget;
+pub use budget::{Budget, Overrun, RunReport};
+
+mod builder;
+pub use builder::DeferGroupBuilder;
+
+mod cancel;
+pub use cancel::CancelToken;
+
+mod cell;
+pub use cell::DeferCell;
+
+mod context;
+pub use context::DeferContext;
+
+mod decorate;
+pub use decorate::ErrorDecorators;
+
+mod exit;
+pub use exit::DeferExit;
+
+mod handle;
+pub use handle::DeferScope;
+
+mod latch;
+pub use latch::{Latch, LatchGuard};
+
+mod lock;
+pub use lock::{unlock_then, UnlockThen};
+
+mod nested;
+pub use nested::NestedDeferGroup;
+
+mod notify;
+pub use notify::{Notifier, NotifyOnDrop};
+
+mod nursery;
+pub use nursery::{nursery, Nursery};
+
+mod once;
+pub use once::{OnceDefer, RunOnce};
+
+mod panic_hook;
+pub use panic_hook::PanicHookGuard;
+
+mod pool;
+pub use pool::{Pool, PoolGuard};
+
+mod registrar;
+pub use registrar::DeferRegistrar;
+
+mod retry;
+pub use retry::Retry;
+
+mod rollback;
+pub use rollback::RollbackGuard;
+
+mod scope;
+pub use scope::{run_scope, run_scope_with, with_resource, CleanupError};
+
+mod shutdown;
+#[cfg(unix)]
+pub use shutdown::FdCloseGuard;
+pub use shutdown::{ErrorPolicy, FlushGuard, TcpShutdownGuard};
+
+mod sync;
+pub use sync::{sync_scope, SendDeferGroup, SyncDeferGroup};
+
+#[cfg(feature = "terminal")]
+mod terminal;
+#[cfg(feature = "terminal")]
+pub use terminal::TerminalGuard;
+
+mod transaction;
+pub use transaction::{Transaction, TransactionGuard};
+
+mod try_scope;
+pub use try_scope::TryDeferGroup;
+
+pub mod background;
+pub use background::{DeferSpawn, DelayDefer};
+
+#[cfg(feature = "compat")]
+pub mod compat;
+pub mod debug;
+#[cfg(feature = "event-log")]
+pub mod event_log;
+pub mod future;
+pub mod hooks;
+#[cfg(feature = "profile")]
+pub mod profile;
+pub mod registry;
+pub mod testing;
+
 /// A utility struct for deferred execution of a closure.
 ///
 /// The `Defer` struct allows you to execute a closure once the `Defer` instance goes out of scope.
@@ -35,9 +146,31 @@ pub use defer_rs_impl::{defer_scope, defer_scope_init};
 /// }
 /// ```
 ///
+/// # Layout
+///
+/// `Defer<T>` is guaranteed to have the same size, alignment, and ABI as `T`, so a guard costs nothing beyond its closure:
+/// it's zero-sized for a closure that captures nothing, and can be freely passed around or embedded in other structs.
+///
+/// ```
+/// use defer_rs::Defer;
+///
+/// let guard = Defer::new(|| println!("Cleaning up..."));
+/// assert_eq!(std::mem::size_of_val(&guard), 0);
+/// ```
+///
 /// See also: [`defer!`], and [`DeferGroup`].
 #[must_use = "Defer MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
-pub struct Defer<T: FnOnce()>(Option<T>);
+#[repr(transparent)]
+pub struct Defer<T: FnOnce()>(ManuallyDrop<T>);
+
+// `Defer` adds no state to its closure
+const _: () = {
+    use std::mem::{align_of, size_of};
+    assert!(size_of::<Defer<fn()>>() == size_of::<fn()>());
+    assert!(size_of::<Option<Defer<fn()>>>() == size_of::<fn()>());
+    assert!(size_of::<BoxDefer>() == size_of::<Box<dyn FnOnce()>>());
+    assert!(align_of::<Defer<fn()>>() == align_of::<fn()>());
+};
 
 impl<T: FnOnce()> Defer<T> {
     /// Creates a new `Defer` instance with the given deferred closure.
@@ -59,15 +192,315 @@ impl<T: FnOnce()> Defer<T> {
     ///
     /// // The deferred action will be executed when `defer_instance` goes out of scope.
     /// ```
-    pub fn new(deferred: T) -> Self {
-        Self(Some(deferred))
+    ///
+    /// As `new` is a `const fn`, guards can also be created in const contexts:
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    ///
+    /// fn release_lock() {
+    ///     println!("Lock released!");
+    /// }
+    ///
+    /// const RELEASE_LOCK: Defer<fn()> = Defer::new(release_lock);
+    ///
+    /// let _guard = RELEASE_LOCK;
+    /// ```
+    pub const fn new(deferred: T) -> Self {
+        Self(ManuallyDrop::new(deferred))
+    }
+
+    /// Consumes the `Defer` instance, dropping the deferred closure without executing it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    ///
+    /// let rollback = Defer::new(|| println!("Rolling back..."));
+    /// // ... the operation succeeded ...
+    /// rollback.cancel();
+    /// ```
+    pub fn cancel(self) {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so the closure is never used again
+        unsafe { ManuallyDrop::drop(&mut this.0) }
+    }
+
+    /// Consumes the `Defer` instance, executing the deferred closure now, instead of when it goes out of scope.
+    ///
+    /// This allows a cleanup to be sequenced precisely (e.g. before a following step that depends on it), while the guard still
+    /// executes it on early returns or panics occurring before that point.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    ///
+    /// # fn write_all() -> Result<(), ()> { Ok(()) }
+    /// let close = Defer::new(|| println!("Closing the file..."));
+    /// // The file is still closed if this fails
+    /// write_all()?;
+    /// // The file must be closed before it's renamed
+    /// close.run_now();
+    /// println!("Renaming the file...");
+    /// # Ok::<(), ()>(())
+    /// ```
+    pub fn run_now(self) {
+        drop(self);
+    }
+
+    /// Replaces the deferred closure with `deferred`, returning the previous one, without executing it.
+    ///
+    /// This suits operations going through several states, each needing a different cleanup. As the closures must be of the
+    /// same type, this is usually done on a [`BoxDefer`] (or a `Defer<fn()>`).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    ///
+    /// let mut cleanup = Defer::boxed(|| println!("Removing the temp file..."));
+    /// // ... the temp file was written, and moved into place ...
+    /// let _previous = cleanup.replace(Box::new(|| println!("Restoring the backup...")));
+    /// // ... the backup was verified ...
+    /// cleanup.replace(Box::new(|| println!("Removing the backup...")));
+    /// // "Removing the backup..." is printed when `cleanup` goes out of scope
+    /// ```
+    pub fn replace(&mut self, deferred: T) -> T {
+        std::mem::replace(&mut *self.0, deferred)
+    }
+
+    /// Chains another closure onto the `Defer` instance, executed right after its own closure, without allocating.
+    ///
+    /// If the first closure panics, the chained closure isn't executed.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    ///
+    /// fn open_connection() -> Defer<impl FnOnce()> {
+    ///     Defer::new(|| println!("Closing the connection..."))
+    /// }
+    ///
+    /// let _guard = open_connection().and_then(|| println!("Connection closed!"));
+    /// ```
+    pub fn and_then<F: FnOnce()>(self, then: F) -> Defer<impl FnOnce()> {
+        let deferred = self.into_deferred();
+        Defer::new(move || {
+            deferred();
+            then();
+        })
+    }
+
+    /// Merges two `Defer` instances into one, executing this instance's closure first, then `other`'s.
+    ///
+    /// This lets a function return a single guard for several cleanups, instead of a tuple of guards that must all be kept alive.
+    /// If the first closure panics, the second one isn't executed.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    ///
+    /// fn start_server() -> Defer<impl FnOnce()> {
+    ///     let stop_listener = Defer::new(|| println!("Stopping the listener..."));
+    ///     let flush_logs = Defer::new(|| println!("Flushing the logs..."));
+    ///     stop_listener.zip(flush_logs)
+    /// }
+    ///
+    /// let _server = start_server();
+    /// ```
+    pub fn zip<U: FnOnce()>(self, other: Defer<U>) -> Defer<impl FnOnce()> {
+        self.and_then(other.into_deferred())
+    }
+
+    // Takes the closure out of the `Defer` instance, without executing it
+    fn into_deferred(self) -> T {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so the closure is only taken once
+        unsafe { ManuallyDrop::take(&mut this.0) }
+    }
+}
+
+// The constructors of `Defer`s whose closure's type is not given by the caller are implemented on `Defer<fn()>`,
+// so the type parameter of `Defer` can be inferred when they are called as `Defer::constructor(...)`
+impl Defer<fn()> {
+    /// Creates a new `Defer` instance calling `deferred` with `args` when it goes out of scope.
+    ///
+    /// `args` is evaluated immediately (when `with_args` is called), which is the behavior of [`defer!`] when given a single
+    /// function call, without going through the macro. Multiple arguments can be passed as a tuple.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    /// use std::cell::Cell;
+    ///
+    /// let x = Cell::new(0);
+    /// let seen = Cell::new(None);
+    /// {
+    ///     // `x.get()` is evaluated now
+    ///     let _guard = Defer::with_args((x.get(), "x"), |(value, name)| seen.set(Some((value, name))));
+    ///     x.set(3);
+    /// }
+    /// assert_eq!(seen.get(), Some((0, "x")));
+    /// ```
+    pub fn with_args<A, F: FnOnce(A)>(args: A, deferred: F) -> Defer<impl FnOnce()> {
+        Defer::new(move || deferred(args))
+    }
+
+    /// Creates a new `Defer` instance which, when it goes out of scope, enqueues `deferred` to the crate's cleanup executor (a long-lived
+    /// worker thread) and returns immediately, instead of executing it.
+    ///
+    /// The executor is started when the first detached guard is dropped, and executes the enqueued closures in order.
+    /// Use [`background::shutdown`] to wait for the pending closures before the process exits.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, enqueuing the enclosed closure!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::{background, Defer};
+    ///
+    /// fn handle_request() {
+    ///     let _guard = Defer::detached(|| {
+    ///         // ... notify the metrics service ...
+    ///     });
+    ///     // ... handle the request ...
+    /// }
+    ///
+    /// handle_request();
+    ///
+    /// // ... the rest of `main` ...
+    ///
+    /// background::shutdown().unwrap();
+    /// ```
+    pub fn detached(deferred: impl FnOnce() + Send + 'static) -> Defer<impl FnOnce()> {
+        Defer::new(move || background::enqueue(Box::new(deferred)))
+    }
+
+    /// Creates a new `Defer` instance which, when it goes out of scope, executes `deferred` with the value `weak` points to,
+    /// only if it's still alive (i.e. `weak` can still be upgraded) at that point.
+    ///
+    /// This is useful for cleanups that are meaningless once the owning object is gone, without keeping it alive
+    /// for the lifetime of the guard (as capturing an [`Arc`](std::sync::Arc) would).
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::Defer;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let cache = Arc::new(Mutex::new(vec!["entry"]));
+    /// {
+    ///     let _guard = Defer::if_alive(Arc::downgrade(&cache), |cache| cache.lock().unwrap().clear());
+    ///     // ... use the cache ...
+    /// }
+    /// assert!(cache.lock().unwrap().is_empty());
+    ///
+    /// let _guard = Defer::if_alive(Arc::downgrade(&cache), |_| unreachable!());
+    /// // The cache is gone before the guard is dropped, so there's nothing to clean up
+    /// drop(cache);
+    /// ```
+    pub fn if_alive<T: ?Sized, F: FnOnce(std::sync::Arc<T>)>(
+        weak: std::sync::Weak<T>,
+        deferred: F,
+    ) -> Defer<impl FnOnce()> {
+        Defer::new(move || {
+            if let Some(value) = weak.upgrade() {
+                deferred(value);
+            }
+        })
+    }
+}
+
+/// A [`Defer`] holding a type-erased (boxed) closure.
+///
+/// As the type of a `Defer` depends on the type of its closure, guards wrapping different closures can't be
+/// stored in the same collection (or struct field), `BoxDefer` erases the closure's type to allow that.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{BoxDefer, Defer};
+///
+/// struct Connection {
+///     // Dropped (executing the closures) when the connection is dropped
+///     on_close: Vec<BoxDefer<'static>>,
+/// }
+///
+/// let name = String::from("db");
+/// let conn = Connection {
+///     on_close: vec![
+///         Defer::boxed(|| println!("Flushing...")),
+///         Defer::boxed(move || println!("Closing {name}...")),
+///     ],
+/// };
+/// ```
+///
+/// See also: [`DynSendDefer`].
+pub type BoxDefer<'a> = Defer<Box<dyn FnOnce() + 'a>>;
+
+impl<'a> Defer<Box<dyn FnOnce() + 'a>> {
+    /// Creates a new [`BoxDefer`], boxing the given deferred closure.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub fn boxed(deferred: impl FnOnce() + 'a) -> Self {
+        Self::new(Box::new(deferred))
+    }
+}
+
+/// A [`Defer`] holding a type-erased, `Send` and `'static` closure.
+///
+/// Unlike a `Defer` holding a closure directly, a `DynSendDefer` has a nameable type, and can be moved into
+/// spawned threads (or tasks), e.g. to be stored along with the resource it cleans up.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{Defer, DynSendDefer};
+///
+/// let guard: DynSendDefer = Defer::boxed_send(|| println!("Worker done!"));
+/// std::thread::spawn(move || {
+///     let _guard = guard;
+///     // ... do work ...
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+///
+/// See also: [`BoxDefer`].
+pub type DynSendDefer = Defer<Box<dyn FnOnce() + Send + 'static>>;
+
+impl Defer<Box<dyn FnOnce() + Send + 'static>> {
+    /// Creates a new [`DynSendDefer`], boxing the given deferred closure.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub fn boxed_send(deferred: impl FnOnce() + Send + 'static) -> Self {
+        Self::new(Box::new(deferred))
     }
 }
 
 impl<T: FnOnce()> Drop for Defer<T> {
+    #[inline]
     fn drop(&mut self) {
-        // This is safe, as there is no way to have a `Defer` struct containing a `None` value
-        unsafe { (self.0.take().unwrap_unchecked())() }
+        // SAFETY: the closure is only taken here, and `drop` is never called more than once
+        let deferred = unsafe { ManuallyDrop::take(&mut self.0) };
+        if debug::skipped(|| format!("deferred closure `{}`", std::any::type_name::<T>())) {
+            return;
+        }
+        let _span = hooks::executing::<T>("Defer");
+        deferred();
     }
 }
 
@@ -98,7 +531,96 @@ impl<T: FnOnce()> Drop for Defer<T> {
 ///
 /// See also: [`defer_scope!`], [`defer_scope_init!`], [`Defer`], and [`defer!`].
 #[must_use = "DeferGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
-pub struct DeferGroup<'a>(Vec<Option<Box<dyn FnOnce() + 'a>>>);
+pub struct DeferGroup<'a> {
+    entries: VecDeque<Entry<'a>>,
+    strategy: Strategy,
+    // The id of the next registered closure, ids are never reused
+    next_id: u64,
+    // Set by `DeferGroupBuilder::fifo`, the queue is then executed last to first when the group goes out of scope
+    fifo: bool,
+    panic_policy: PanicPolicy,
+    // The source reported to `hooks`
+    name: &'static str,
+}
+
+type Deferred<'a> = Box<dyn FnOnce() + 'a>;
+
+struct Entry<'a> {
+    id: u64,
+    // Set for closures queued using `add_once`/`push_once`
+    key: Option<Cow<'static, str>>,
+    // The queue is kept sorted by priority (highest first), closures queued without one have a priority of `0`
+    priority: i32,
+    deferred: Job<'a>,
+}
+
+enum Job<'a> {
+    Plain(Deferred<'a>),
+    // Receives the group executing it, to queue more closures on it
+    Reentrant(Box<dyn FnOnce(&mut DeferGroup<'a>) + 'a>),
+}
+
+impl<'a> Job<'a> {
+    // Closures queued by a re-entrant closure executed outside of the group's drop are executed right after it
+    fn into_deferred(self) -> Deferred<'a> {
+        match self {
+            Job::Plain(f) => f,
+            Job::Reentrant(f) => Box::new(move || f(&mut DeferGroup::new())),
+        }
+    }
+}
+
+/// A marker for a point in the registration history of a [`DeferGroup`], see [`DeferGroup::savepoint`].
+///
+/// A `Savepoint` is only meaningful for the `DeferGroup` that created it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Savepoint(u64);
+
+/// Decides whether the closures queued in a [`DeferGroup`] are executed when it goes out of scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Always execute the queued closures.
+    #[default]
+    Always,
+    /// Only execute the queued closures if the scope is exited normally (i.e. the thread isn't panicking), e.g. to commit.
+    OnSuccess,
+    /// Only execute the queued closures if the scope is exited due to a panic (i.e. while unwinding), e.g. to roll back.
+    OnUnwind,
+}
+
+impl Strategy {
+    /// Returns `true` if closures should be executed given the current panicking state of the thread.
+    #[inline]
+    pub fn should_run(self) -> bool {
+        match self {
+            Strategy::Always => true,
+            Strategy::OnSuccess => !std::thread::panicking(),
+            Strategy::OnUnwind => std::thread::panicking(),
+        }
+    }
+}
+
+/// Decides what a [`DeferGroup`] does when one of its closures panics while it goes out of scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Propagate the panic immediately, the remaining closures are dropped without being executed.
+    #[default]
+    Stop,
+    /// Execute the remaining closures, then propagate the first panic (unless the thread was already panicking, in which case the panics are discarded).
+    Continue,
+}
+
+/// The order [`DeferGroup::run_in`] executes the queued closures in, regardless of their position in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Order {
+    /// The order the closures were queued in (by any method), the first queued closure is executed first.
+    Registration,
+    /// The reverse of the order the closures were queued in, the last queued closure is executed first (like `defer` in Go).
+    Reverse,
+    /// The highest priority first (see [`DeferGroup::add_with_priority`]), closures with the same priority are executed
+    /// in [`Order::Reverse`] order.
+    Priority,
+}
 
 impl<'a> DeferGroup<'a> {
     /// Creates a new `DeferGroup`.
@@ -113,202 +635,1716 @@ impl<'a> DeferGroup<'a> {
     /// let mut defer_group = DeferGroup::new();
     /// // Add deferred actions...
     /// ```
-    pub fn new() -> Self {
-        Self(Vec::new())
+    pub const fn new() -> Self {
+        Self::with_strategy(Strategy::Always)
     }
 
-    /// Adds a deferred closure to the start (0-index) of the `DeferGroup` queue.
+    /// Creates a new, empty `DeferGroup` with space for at least `capacity` deferred closures.
     ///
-    /// The closures queued in `DeferGroup` will be executed first to last
-    /// when the the `DeferGroup` instance goes out of scope.
+    /// **Note: `DeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
     ///
     /// # Example
     ///
     /// ```
     /// use defer_rs::DeferGroup;
     ///
-    /// let mut defer_group = DeferGroup::new();
-    /// {
-    ///     defer_group.add(Box::new(|| {
-    ///         println!("This will be printed 2nd");        
-    ///     }));
-    ///     defer_group.add(Box::new(|| {
-    ///         println!("This will be printed 1st");
-    ///     }));
-    /// }
+    /// let mut defer_group = DeferGroup::with_capacity(16);
+    /// assert!(defer_group.capacity() >= 16);
     /// ```
-    pub fn add(&mut self, f: Box<dyn FnOnce() + 'a>) {
-        self.0.insert(0, Some(f));
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            strategy: Strategy::Always,
+            next_id: 0,
+            fifo: false,
+            panic_policy: PanicPolicy::Stop,
+            name: "DeferGroup",
+        }
     }
 
-    /// Pushes a deferred closure to the end of the `DeferGroup` queue.
+    /// Creates a new `DeferGroup` whose closures are only executed if it goes out of scope normally (i.e. not due to a panic).
     ///
-    /// The closures queued in `DeferGroup` will be executed first to last
-    /// when the the `DeferGroup` instance goes out of scope.
+    /// This is useful for groups of commit-only actions. Explicitly executing the queued closures
+    /// (e.g. using [`DeferGroup::run_first`]) is not affected by the strategy.
+    ///
+    /// **Note: `DeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
     ///
     /// # Example
     ///
     /// ```
     /// use defer_rs::DeferGroup;
+    /// use std::cell::Cell;
+    /// use std::panic::{catch_unwind, AssertUnwindSafe};
     ///
-    /// let mut defer_group = DeferGroup::new();
+    /// let committed = Cell::new(false);
+    /// let res = catch_unwind(AssertUnwindSafe(|| {
+    ///     let mut on_success = DeferGroup::on_success();
+    ///     on_success.add(Box::new(|| committed.set(true)));
+    ///     panic!("Something went wrong!");
+    /// }));
+    ///
+    /// assert!(res.is_err());
+    /// assert!(!committed.get());
+    /// ```
+    pub const fn on_success() -> Self {
+        Self::with_strategy(Strategy::OnSuccess)
+    }
+
+    /// Creates a new `DeferGroup` whose closures are only executed if it goes out of scope due to a panic (i.e. while unwinding).
+    ///
+    /// This is useful for groups of rollback-only actions. Explicitly executing the queued closures
+    /// (e.g. using [`DeferGroup::run_first`]) is not affected by the strategy.
+    ///
+    /// **Note: `DeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    /// use std::cell::Cell;
+    ///
+    /// let rolled_back = Cell::new(false);
     /// {
-    ///     defer_group.push(Box::new(|| {
-    ///         println!("This will be printed 1st");
-    ///     }));
-    ///     defer_group.push(Box::new(|| {
-    ///         println!("This will be printed 2nd");        
-    ///     }));
-    /// }    
+    ///     let mut on_unwind = DeferGroup::on_unwind();
+    ///     on_unwind.add(Box::new(|| rolled_back.set(true)));
+    /// }
+    ///
+    /// assert!(!rolled_back.get());
     /// ```
-    pub fn push(&mut self, f: Box<dyn FnOnce() + 'a>) {
-        self.0.push(Some(f));
+    pub const fn on_unwind() -> Self {
+        Self::with_strategy(Strategy::OnUnwind)
     }
-}
 
-impl<'a> Drop for DeferGroup<'a> {
-    fn drop(self: &mut DeferGroup<'a>) {
-        for deferred in &mut self.0 {
-            unsafe { deferred.take().unwrap_unchecked()() };
+    /// Creates a new `DeferGroup` with the given [`Strategy`].
+    ///
+    /// **Note: `DeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub const fn with_strategy(strategy: Strategy) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            strategy,
+            next_id: 0,
+            fifo: false,
+            panic_policy: PanicPolicy::Stop,
+            name: "DeferGroup",
         }
     }
-}
 
-/// A macro for deferring execution of code until the current scope exits.
-///
-/// The `defer!` macro allows you to specify code that should be executed when the current
-/// scope (such as a function or block) is exited, regardless of whether the exit occurs normally
+    /// Returns a [`DeferGroupBuilder`], configuring a new `DeferGroup` (its [`Strategy`], capacity, execution order, [`PanicPolicy`], and the name reported to the [hooks]) in a single expression.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::builder().lifo().on_success().capacity(16).build();
+    /// defer_group.add(Box::new(|| println!("Committing the transaction...")));
+    /// ```
+    pub const fn builder() -> DeferGroupBuilder {
+        DeferGroupBuilder::new()
+    }
+
+    /// Returns the [`Strategy`] of the `DeferGroup`.
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Returns the [`PanicPolicy`] of the `DeferGroup`.
+    pub fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+
+    /// Returns the number of deferred closures the `DeferGroup` can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    /// Reserves capacity for at least `additional` more deferred closures, see [`VecDeque::reserve`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.reserve(10);
+    /// assert!(defer_group.capacity() >= 10);
+    /// ```
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    /// Reserves capacity for exactly `additional` more deferred closures, see [`VecDeque::reserve_exact`].
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.entries.reserve_exact(additional);
+    }
+
+    /// Shrinks the capacity of the `DeferGroup` as much as possible, see [`VecDeque::shrink_to_fit`].
+    pub fn shrink_to_fit(&mut self) {
+        self.entries.shrink_to_fit();
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the `DeferGroup` queue.
+    ///
+    /// The closures queued in `DeferGroup` will be executed first to last
+    /// when the the `DeferGroup` instance goes out of scope.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// {
+    ///     defer_group.add(Box::new(|| {
+    ///         println!("This will be printed 2nd");        
+    ///     }));
+    ///     defer_group.add(Box::new(|| {
+    ///         println!("This will be printed 1st");
+    ///     }));
+    /// }
+    /// ```
+    pub fn add(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        let entry = self.entry(Job::Plain(f));
+        self.insert_front(entry);
+    }
+
+    /// Pushes a deferred closure to the end of the `DeferGroup` queue.
+    ///
+    /// The closures queued in `DeferGroup` will be executed first to last
+    /// when the the `DeferGroup` instance goes out of scope.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// {
+    ///     defer_group.push(Box::new(|| {
+    ///         println!("This will be printed 1st");
+    ///     }));
+    ///     defer_group.push(Box::new(|| {
+    ///         println!("This will be printed 2nd");        
+    ///     }));
+    /// }    
+    /// ```
+    pub fn push(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        let entry = self.entry(Job::Plain(f));
+        self.insert_back(entry);
+    }
+
+    /// Adds a deferred closure with the given `priority` to the `DeferGroup` queue, before the closures with the same (or a lower) priority.
+    ///
+    /// The queue is kept sorted by priority, closures with a higher priority are executed first. Closures queued without a priority
+    /// (e.g. using [`DeferGroup::add`], or [`DeferGroup::push`]) have a priority of `0`, so as long as no priority is used, the queue is only
+    /// ordered by registration. This lets cleanups registered independently (e.g. by different helpers, or using `defer_scope!(priority = N; ...)`)
+    /// be ordered relative to each other, e.g. flushing a buffer before the file it's written to is closed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add_with_priority(10, Box::new(|| println!("This will be printed 1st")));
+    /// defer_group.add(Box::new(|| println!("This will be printed 2nd")));
+    /// defer_group.add_with_priority(-10, Box::new(|| println!("This will be printed 3rd")));
+    /// ```
+    pub fn add_with_priority(&mut self, priority: i32, f: Box<dyn FnOnce() + 'a>) {
+        let mut entry = self.entry(Job::Plain(f));
+        entry.priority = priority;
+        self.insert_front(entry);
+    }
+
+    /// Pushes a deferred closure with the given `priority` to the `DeferGroup` queue, after the closures with the same (or a higher) priority.
+    ///
+    /// See [`DeferGroup::add_with_priority`].
+    pub fn push_with_priority(&mut self, priority: i32, f: Box<dyn FnOnce() + 'a>) {
+        let mut entry = self.entry(Job::Plain(f));
+        entry.priority = priority;
+        self.insert_back(entry);
+    }
+
+    // Inserts the entry before the entries with the same (or a lower) priority
+    fn insert_front(&mut self, entry: Entry<'a>) {
+        match self
+            .entries
+            .iter()
+            .position(|e| e.priority <= entry.priority)
+        {
+            Some(0) => self.entries.push_front(entry),
+            Some(index) => self.entries.insert(index, entry),
+            None => self.entries.push_back(entry),
+        }
+    }
+
+    // Inserts the entry after the entries with the same (or a higher) priority
+    fn insert_back(&mut self, entry: Entry<'a>) {
+        match self
+            .entries
+            .iter()
+            .rposition(|e| e.priority >= entry.priority)
+        {
+            Some(index) => self.entries.insert(index + 1, entry),
+            None => self.entries.push_front(entry),
+        }
+    }
+
+    /// Inserts a deferred closure at position `index` of the `DeferGroup` queue, shifting the closures after it towards the end.
+    ///
+    /// The closures queued in `DeferGroup` will be executed first to last
+    /// when the the `DeferGroup` instance goes out of scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the number of queued closures, see [`VecDeque::insert`].
+    ///
+    /// As the queue is kept sorted by priority (see [`DeferGroup::add_with_priority`]), the closure takes the priority of the closure
+    /// it's inserted before (or of the last closure, when it's inserted at the end), instead of a priority of `0`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.push(Box::new(|| println!("Closing the connection...")));
+    /// defer_group.push(Box::new(|| println!("Removing the socket file...")));
+    ///
+    /// // It turns out the connection must be flushed before being closed
+    /// defer_group.insert_at(0, Box::new(|| println!("Flushing the connection...")));
+    /// ```
+    pub fn insert_at(&mut self, index: usize, f: Box<dyn FnOnce() + 'a>) {
+        let mut entry = self.entry(Job::Plain(f));
+        let neighbour = self.entries.get(index).or_else(|| {
+            index
+                .checked_sub(1)
+                .and_then(|before| self.entries.get(before))
+        });
+        entry.priority = neighbour.map_or(0, |neighbour| neighbour.priority);
+        self.entries.insert(index, entry);
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the `DeferGroup` queue, unless a closure with the same `key` is already queued.
+    ///
+    /// This allows idempotent registration, e.g. from helper functions that may be called multiple times.
+    /// Returns `true` if the closure was queued. Once the closure with a given key is executed (or removed), the key can be used again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// fn create_temp_dir(defer_group: &mut DeferGroup) {
+    ///     // ... create the temp dir, if it doesn't exist ...
+    ///     defer_group.add_once("temp dir", Box::new(|| println!("Removing the temp dir...")));
+    /// }
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// create_temp_dir(&mut defer_group);
+    /// create_temp_dir(&mut defer_group);
+    /// assert_eq!(defer_group.len(), 1);
+    /// ```
+    pub fn add_once(
+        &mut self,
+        key: impl Into<Cow<'static, str>>,
+        f: Box<dyn FnOnce() + 'a>,
+    ) -> bool {
+        self.keyed_entry(key.into(), f)
+            .map(|entry| self.insert_front(entry))
+            .is_some()
+    }
+
+    /// Pushes a deferred closure to the end of the `DeferGroup` queue, unless a closure with the same `key` is already queued.
+    ///
+    /// See [`DeferGroup::add_once`].
+    pub fn push_once(
+        &mut self,
+        key: impl Into<Cow<'static, str>>,
+        f: Box<dyn FnOnce() + 'a>,
+    ) -> bool {
+        self.keyed_entry(key.into(), f)
+            .map(|entry| self.insert_back(entry))
+            .is_some()
+    }
+
+    /// Removes the closure queued with the given `key` (using [`DeferGroup::add_once`], or [`DeferGroup::push_once`]), returning it without executing it.
+    ///
+    /// The returned closure can be executed manually, or queued on another group, e.g. when the ownership of a cleanup
+    /// moves to another subsystem. Returns `None` if no closure with the given key is queued.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut request = DeferGroup::new();
+    /// request.add_once("connection", Box::new(|| println!("Closing the connection...")));
+    ///
+    /// // The connection is kept alive, it's now closed when the pool is dropped
+    /// let mut pool = DeferGroup::new();
+    /// pool.push(request.take("connection").unwrap());
+    /// assert!(request.is_empty());
+    /// ```
+    pub fn take(&mut self, key: &str) -> Option<Box<dyn FnOnce() + 'a>> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.key.as_deref() == Some(key))?;
+        self.entries
+            .remove(index)
+            .map(|entry| entry.deferred.into_deferred())
+    }
+
+    /// Cancels (removes without executing) every queued closure for which `f` returns `false`, keeping the rest in their order.
+    ///
+    /// `f` receives the key of each closure (queued using [`DeferGroup::add_once`], or [`DeferGroup::push_once`]), or `None` for
+    /// closures queued without one. This allows cancelling a family of cleanups at once, e.g. by prefixing their keys with
+    /// the name of the resource they belong to.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("Closing the connection...")));
+    /// defer_group.add_once("replica/detach", Box::new(|| println!("Detaching the replica...")));
+    /// defer_group.add_once("replica/drop-slot", Box::new(|| println!("Dropping the replication slot...")));
+    ///
+    /// // The replica ended up not being created, its cleanups are no longer relevant
+    /// defer_group.retain(|key| !key.is_some_and(|key| key.starts_with("replica/")));
+    /// assert_eq!(defer_group.len(), 1);
+    /// ```
+    pub fn retain(&mut self, mut f: impl FnMut(Option<&str>) -> bool) {
+        self.entries.retain(|entry| f(entry.key.as_deref()));
+    }
+
+    fn keyed_entry(
+        &mut self,
+        key: Cow<'static, str>,
+        f: Box<dyn FnOnce() + 'a>,
+    ) -> Option<Entry<'a>> {
+        if self
+            .entries
+            .iter()
+            .any(|entry| entry.key.as_ref() == Some(&key))
+        {
+            return None;
+        }
+        let mut entry = self.entry(Job::Plain(f));
+        entry.key = Some(key);
+        Some(entry)
+    }
+
+    /// Adds a deferred closure receiving the `DeferGroup` to the start (0-index) of the `DeferGroup` queue, so it can queue more closures once executed.
+    ///
+    /// When executed as the `DeferGroup` goes out of scope, closures added by it (using [`DeferGroup::add`]) are executed next, and closures pushed by it
+    /// (using [`DeferGroup::push`]) are executed after the rest of the queue. When executed explicitly (e.g. using [`DeferGroup::run_first`]),
+    /// the closures it queues are executed right after it returns.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("This will be printed 3rd")));
+    /// defer_group.add_reentrant(Box::new(|defer_group| {
+    ///     println!("This will be printed 1st");
+    ///     // e.g. a cleanup discovering more cleanup to do
+    ///     defer_group.add(Box::new(|| println!("This will be printed 2nd")));
+    ///     defer_group.push(Box::new(|| println!("This will be printed 4th")));
+    /// }));
+    /// ```
+    pub fn add_reentrant(&mut self, f: Box<dyn FnOnce(&mut DeferGroup<'a>) + 'a>) {
+        let entry = self.entry(Job::Reentrant(f));
+        self.insert_front(entry);
+    }
+
+    /// Pushes a deferred closure receiving the `DeferGroup` to the end of the `DeferGroup` queue, so it can queue more closures once executed.
+    ///
+    /// See [`DeferGroup::add_reentrant`].
+    pub fn push_reentrant(&mut self, f: Box<dyn FnOnce(&mut DeferGroup<'a>) + 'a>) {
+        let entry = self.entry(Job::Reentrant(f));
+        self.insert_back(entry);
+    }
+
+    /// Adds another `DeferGroup` to the start (0-index) of the `DeferGroup` queue, as a single deferred entry.
+    ///
+    /// Once the entry is executed, the closures queued on `group` are executed in its own order (and according to its own [`Strategy`]),
+    /// as if `group` went out of scope at that point. This lets a helper build an ordered bundle of cleanups, and attach it to the caller's group as a unit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// fn open_session() -> DeferGroup<'static> {
+    ///     let mut cleanup = DeferGroup::new();
+    ///     cleanup.push(Box::new(|| println!("This will be printed 1st")));
+    ///     cleanup.push(Box::new(|| println!("This will be printed 2nd")));
+    ///     cleanup
+    /// }
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("This will be printed 3rd")));
+    /// defer_group.add_group(open_session());
+    /// assert_eq!(defer_group.len(), 2);
+    /// ```
+    pub fn add_group(&mut self, group: DeferGroup<'a>) {
+        self.add(Box::new(move || drop(group)));
+    }
+
+    /// Pushes another `DeferGroup` to the end of the `DeferGroup` queue, as a single deferred entry.
+    ///
+    /// See [`DeferGroup::add_group`].
+    pub fn push_group(&mut self, group: DeferGroup<'a>) {
+        self.push(Box::new(move || drop(group)));
+    }
+
+    /// Adds a guard of any type (e.g. a `scopeguard::ScopeGuard`, or a `MutexGuard`) to the start (0-index) of the `DeferGroup` queue,
+    /// as a single deferred entry, dropping it once the entry is executed.
+    ///
+    /// This lets guards from other crates take part in the group's ordering (and be cancelled or executed early along with it),
+    /// e.g. while migrating a codebase to `defer-rs` incrementally. If the entry is cancelled, the guard is still dropped (as the
+    /// closure owning it is), its own cancellation mechanism (e.g. `ScopeGuard::into_inner`) must be used to avoid that.
+    ///
+    /// This is the only interop with `scopeguard`: the crate doesn't depend on it, so there are no `From` conversions between a
+    /// `ScopeGuard` and this crate's guards (e.g. [`Defer`]), a `ScopeGuard` can only be queued (and dropped) as is.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::{Defer, DeferGroup};
+    ///
+    /// // A guard from another crate (e.g. `scopeguard::guard((), |_| ...)`)
+    /// let flush_logs = Defer::new(|| println!("This will be printed 2nd"));
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add_guard(flush_logs);
+    /// defer_group.add(Box::new(|| println!("This will be printed 1st")));
+    /// ```
+    pub fn add_guard<G: 'a>(&mut self, guard: G) {
+        self.add(Box::new(move || drop(guard)));
+    }
+
+    /// Pushes a guard of any type to the end of the `DeferGroup` queue, as a single deferred entry, dropping it once the entry is executed.
+    ///
+    /// See [`DeferGroup::add_guard`].
+    pub fn push_guard<G: 'a>(&mut self, guard: G) {
+        self.push(Box::new(move || drop(guard)));
+    }
+
+    fn entry(&mut self, deferred: Job<'a>) -> Entry<'a> {
+        hooks::registered(self.name);
+        let id = self.next_id;
+        self.next_id += 1;
+        Entry {
+            id,
+            key: None,
+            priority: 0,
+            deferred,
+        }
+    }
+
+    // Removes the next closure to execute when the group goes out of scope
+    fn pop_next(&mut self) -> Option<Entry<'a>> {
+        if self.fifo {
+            self.entries.pop_back()
+        } else {
+            self.entries.pop_front()
+        }
+    }
+
+    // Removes all the queued closures (in execution order), without executing them
+    pub(crate) fn take_all(&mut self) -> impl DoubleEndedIterator<Item = Deferred<'a>> {
+        let entries = std::mem::take(&mut self.entries);
+        self.in_execution_order(entries)
+            .into_iter()
+            .map(|entry| entry.deferred.into_deferred())
+    }
+
+    // Reorders closures taken from the queue (in queue order) in the order the group executes them
+    fn in_execution_order(&self, entries: VecDeque<Entry<'a>>) -> VecDeque<Entry<'a>> {
+        if self.fifo {
+            entries.into_iter().rev().collect()
+        } else {
+            entries
+        }
+    }
+
+    /// Returns the number of deferred closures queued in the `DeferGroup`.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no deferred closures are queued in the `DeferGroup`.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Executes the first `n` queued closures immediately (first to last, in execution order), removing them from the `DeferGroup` queue.
+    ///
+    /// The rest of the queued closures are kept pending, and will be executed when the `DeferGroup` instance goes out of scope.
+    /// If fewer than `n` closures are queued, all of them are executed. On a group built with [`DeferGroupBuilder::fifo`], which executes
+    /// its queue last to first, these are the last `n` closures of the queue, i.e. the ones it would have executed first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("Tear down phase 1")));
+    ///
+    /// // Closures queued using `add` are executed before the ones queued earlier,
+    /// // so everything registered since `marker` is at the start of the queue
+    /// let marker = defer_group.len();
+    /// defer_group.add(Box::new(|| println!("Tear down phase 2, step 2")));
+    /// defer_group.add(Box::new(|| println!("Tear down phase 2, step 1")));
+    ///
+    /// // Phase 2 is done, tear it down now
+    /// defer_group.run_first(defer_group.len() - marker);
+    /// assert_eq!(defer_group.len(), 1);
+    /// ```
+    pub fn run_first(&mut self, n: usize) {
+        self.run_range(..n.min(self.entries.len()));
+    }
+
+    /// Executes the queued closures in the given range of the `DeferGroup`'s execution order immediately (first to last), removing them from its queue.
+    ///
+    /// Positions are counted in the order the group executes its closures in, which is the reverse of the queue order on a group built with
+    /// [`DeferGroupBuilder::fifo`].
+    ///
+    /// The rest of the queued closures are kept pending, and will be executed when the `DeferGroup` instance goes out of scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds (or if its start is greater than its end).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.push(Box::new(|| println!("This will be printed 3rd")));
+    /// defer_group.push(Box::new(|| println!("This will be printed 1st")));
+    /// defer_group.push(Box::new(|| println!("This will be printed 2nd")));
+    /// defer_group.push(Box::new(|| println!("This will be printed 4th")));
+    ///
+    /// defer_group.run_range(1..3);
+    /// println!("This will be printed 3rd");
+    /// ```
+    pub fn run_range(&mut self, range: impl std::ops::RangeBounds<usize>) {
+        run_entries(self.take_range(range));
+    }
+
+    /// Executes the queued closures immediately in the given [`Order`], removing them from the `DeferGroup` queue.
+    ///
+    /// The order only depends on when (and with which priority) each closure was queued, not on its position in the queue
+    /// (e.g. whether it was queued using [`DeferGroup::add`] or [`DeferGroup::push`]), so it's the same for any sequence of registrations,
+    /// and doesn't change between releases. The `DeferGroup` can still be used to queue new closures afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::{DeferGroup, Order};
+    ///
+    /// # let shutting_down = true;
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.push(Box::new(|| println!("Stopping the workers...")));
+    /// defer_group.add(Box::new(|| println!("Draining the queue...")));
+    ///
+    /// // The queue is drained before the workers are stopped on shutdown, but not when reloading
+    /// let order = if shutting_down { Order::Reverse } else { Order::Registration };
+    /// defer_group.run_in(order);
+    /// assert!(defer_group.is_empty());
+    /// ```
+    pub fn run_in(&mut self, order: Order) {
+        if !self.is_empty()
+            && debug::skipped(|| format!("{} deferred closure(s) of a `DeferGroup`", self.len()))
+        {
+            self.entries.clear();
+            return;
+        }
+        let mut entries = Vec::from(std::mem::take(&mut self.entries));
+        match order {
+            Order::Registration => entries.sort_by_key(|entry| entry.id),
+            Order::Reverse => entries.sort_by_key(|entry| std::cmp::Reverse(entry.id)),
+            Order::Priority => {
+                entries.sort_by_key(|entry| std::cmp::Reverse((entry.priority, entry.id)))
+            }
+        }
+        run_entries(entries);
+    }
+
+    // Removes the closures in the given range of the execution order (in execution order), without executing them
+    pub(crate) fn take_range(
+        &mut self,
+        range: impl std::ops::RangeBounds<usize>,
+    ) -> Vec<Entry<'a>> {
+        if !self.fifo {
+            return self.entries.drain(range).collect();
+        }
+        // The execution order is the reverse of the queue order
+        use std::ops::Bound;
+        let len = self.entries.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        assert!(
+            start <= end && end <= len,
+            "range {start}..{end} out of bounds for a queue of {len} closure(s)"
+        );
+        self.entries.drain(len - end..len - start).rev().collect()
+    }
+
+    /// Returns a [`Savepoint`] marking the current point in the registration history of the `DeferGroup`.
+    ///
+    /// The closures queued after the savepoint was taken (using any method, e.g. [`DeferGroup::add`] or [`DeferGroup::push`]) can later be cancelled
+    /// using [`DeferGroup::rollback_to`], or executed early using [`DeferGroup::run_since`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("Releasing the connection...")));
+    ///
+    /// let savepoint = defer_group.savepoint();
+    /// defer_group.add(Box::new(|| println!("Removing the temporary table...")));
+    ///
+    /// // The temporary table ended up not being created, its cleanup is no longer relevant
+    /// defer_group.rollback_to(savepoint);
+    /// assert_eq!(defer_group.len(), 1);
+    /// ```
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint(self.next_id)
+    }
+
+    /// Cancels (removes without executing) every closure queued after the given [`Savepoint`] was taken.
+    ///
+    /// See [`DeferGroup::savepoint`].
+    pub fn rollback_to(&mut self, savepoint: Savepoint) {
+        self.entries.retain(|entry| entry.id < savepoint.0);
+    }
+
+    /// Cancels (removes without executing) every queued closure, returning the number of cancelled closures.
+    ///
+    /// Unlike running the closures early, none of them is executed, e.g. when abandoning a speculative operation whose cleanups
+    /// no longer apply. The `DeferGroup` can still be used to queue new closures afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("Deleting the prefetched pages...")));
+    /// defer_group.add(Box::new(|| println!("Releasing the prefetch buffer...")));
+    ///
+    /// // The prefetched pages were handed over to the cache, which now owns them
+    /// assert_eq!(defer_group.disarm_all(), 2);
+    /// assert!(defer_group.is_empty());
+    /// ```
+    pub fn disarm_all(&mut self) -> usize {
+        let disarmed = self.entries.len();
+        self.entries.clear();
+        disarmed
+    }
+
+    /// Executes every closure queued after the given [`Savepoint`] was taken immediately (in execution order), removing them from the `DeferGroup` queue.
+    ///
+    /// The rest of the queued closures are kept pending, and will be executed when the `DeferGroup` instance goes out of scope.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.add(Box::new(|| println!("This will be printed last")));
+    ///
+    /// let phase_2 = defer_group.savepoint();
+    /// defer_group.push(Box::new(|| println!("This will be printed 2nd")));
+    /// defer_group.add(Box::new(|| println!("This will be printed 1st")));
+    ///
+    /// // Phase 2 is done, tear it down now
+    /// defer_group.run_since(phase_2);
+    /// assert_eq!(defer_group.len(), 1);
+    /// ```
+    pub fn run_since(&mut self, savepoint: Savepoint) {
+        run_entries(self.take_since(savepoint));
+    }
+
+    // Removes the closures queued after `savepoint` was taken (in execution order), without executing them
+    pub(crate) fn take_since(&mut self, savepoint: Savepoint) -> VecDeque<Entry<'a>> {
+        let (since, before) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|entry| entry.id >= savepoint.0);
+        self.entries = before;
+        self.in_execution_order(since)
+    }
+
+    /// Executes the queued closures immediately (first to last), within the given time [`Budget`].
+    ///
+    /// As a running closure can't be interrupted, an overrun is only detected once the closure returns,
+    /// unless the budget's policy is [`Overrun::Abort`], in which case the process is aborted as soon as the limit is exceeded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::{Budget, DeferGroup, Overrun};
+    /// use std::time::Duration;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.push(Box::new(|| std::thread::sleep(Duration::from_millis(50))));
+    /// defer_group.push(Box::new(|| println!("Skipped!")));
+    ///
+    /// let report = defer_group.run_with_budget(
+    ///     Budget::per_entry(Duration::from_millis(10)).on_overrun(Overrun::SkipRest),
+    /// );
+    /// assert_eq!(report.overran(), [0]);
+    /// assert_eq!(report.skipped(), 1);
+    /// ```
+    pub fn run_with_budget(mut self, budget: Budget) -> RunReport {
+        if !self.is_empty()
+            && debug::skipped(|| format!("{} deferred closure(s) of a `DeferGroup`", self.len()))
+        {
+            let skipped = self.take_all().count();
+            return RunReport::skipped_all(skipped);
+        }
+        budget::run_local(self.take_all(), &budget, "DeferGroup")
+    }
+
+    /// Executes the queued closures immediately (first to last), returning a [`RunReport`] of the run.
+    ///
+    /// Unlike dropping the group, a panicking closure doesn't stop the run: the panic is caught and recorded in the report,
+    /// along with the time each closure took, so it can be checked (or logged) whether the cleanup was actually clean.
+    /// The closures are executed regardless of the group's [`Strategy`].
+    ///
+    /// See [`RunReport`] for an example.
+    pub fn run_now(mut self) -> RunReport {
+        if !self.is_empty()
+            && debug::skipped(|| format!("{} deferred closure(s) of a `DeferGroup`", self.len()))
+        {
+            let skipped = self.take_all().count();
+            return RunReport::skipped_all(skipped);
+        }
+        budget::run_reported(self.take_all(), "DeferGroup")
+    }
+
+    /// Converts the `DeferGroup` into a single closure, executing the queued closures (first to last) once called.
+    ///
+    /// The closures are executed as if the `DeferGroup` went out of scope when the returned closure is called (according to its [`Strategy`]),
+    /// and are never executed if it isn't. This lets the whole bundle be handed to APIs accepting a callback (e.g. another crate's shutdown hook).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// fn on_shutdown(callback: Box<dyn FnOnce()>) {
+    ///     // ... stored until the application shuts down ...
+    ///     callback();
+    /// }
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.push(Box::new(|| println!("Closing the connections...")));
+    /// defer_group.push(Box::new(|| println!("Flushing the logs...")));
+    /// on_shutdown(defer_group.into_fn());
+    /// ```
+    pub fn into_fn(self) -> Box<dyn FnOnce() + 'a> {
+        Box::new(move || drop(self))
+    }
+
+    /// Consumes the `DeferGroup` into a `Vec` of its queued closures (in execution order), without executing them.
+    ///
+    /// This disarms the `DeferGroup`, e.g. to reorder the closures manually, or hand them to a custom scheduler.
+    /// Keys (see [`DeferGroup::add_once`]) and priorities are discarded. See also the [`IntoIterator`] implementation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use defer_rs::DeferGroup;
+    ///
+    /// let mut defer_group = DeferGroup::new();
+    /// defer_group.push(Box::new(|| println!("This will be printed 2nd")));
+    /// defer_group.push(Box::new(|| println!("This will be printed 1st")));
+    ///
+    /// let mut deferred = defer_group.into_inner();
+    /// deferred.reverse();
+    /// for f in deferred {
+    ///     f();
+    /// }
+    /// ```
+    pub fn into_inner(self) -> Vec<Box<dyn FnOnce() + 'a>> {
+        self.into_iter().collect()
+    }
+}
+
+/// Consumes the `DeferGroup` into an iterator over its queued closures (in execution order), without executing them.
+///
+/// This disarms the `DeferGroup`, the closures are only executed if they're explicitly called,
+/// or re-scheduled elsewhere (e.g. on another `DeferGroup`).
+///
+/// # Example
+///
+/// ```
+/// use defer_rs::DeferGroup;
+///
+/// let mut outer = DeferGroup::new();
+/// {
+///     let mut inner = DeferGroup::new();
+///     inner.push(Box::new(|| println!("This will be printed when `outer` is dropped")));
+///
+///     // Move the closures queued on `inner` to `outer`
+///     for deferred in inner {
+///         outer.push(deferred);
+///     }
+/// }
+/// ```
+impl<'a> IntoIterator for DeferGroup<'a> {
+    type Item = Box<dyn FnOnce() + 'a>;
+    type IntoIter = IntoIter<'a>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        let entries = std::mem::take(&mut self.entries);
+        IntoIter(self.in_execution_order(entries).into_iter())
+    }
+}
+
+/// An iterator over the queued closures of a [`DeferGroup`], created by its [`IntoIterator`] implementation.
+///
+/// Closures remaining in the iterator when it's dropped are dropped without being executed.
+pub struct IntoIter<'a>(std::collections::vec_deque::IntoIter<Entry<'a>>);
+
+impl<'a> Iterator for IntoIter<'a> {
+    type Item = Box<dyn FnOnce() + 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|entry| entry.deferred.into_deferred())
+    }
+}
+
+impl<'a> DoubleEndedIterator for IntoIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0
+            .next_back()
+            .map(|entry| entry.deferred.into_deferred())
+    }
+}
+
+// Executes closures removed from a `DeferGroup` queue, in order
+pub(crate) fn run_entries<'a>(entries: impl IntoIterator<Item = Entry<'a>>) {
+    for entry in entries {
+        let _span = hooks::executing_queued("DeferGroup");
+        entry.deferred.into_deferred()();
+    }
+}
+
+impl<'a> Default for DeferGroup<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Implements `From<(F1, F2, ...)>` for `DeferGroup`, for each of the given arities
+macro_rules! impl_from_tuple {
+    ($(($($f:ident),+))*) => {$(
+        /// Creates a `DeferGroup` from a tuple of (up to 12) closures, boxing and queueing them in order.
+        ///
+        /// The closures are executed in the order they appear in the tuple (first to last).
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use defer_rs::DeferGroup;
+        ///
+        /// let defer_group = DeferGroup::from((
+        ///     || println!("This will be printed 1st"),
+        ///     || println!("This will be printed 2nd"),
+        ///     || println!("This will be printed 3rd"),
+        /// ));
+        /// assert_eq!(defer_group.len(), 3);
+        /// ```
+        impl<'a, $($f: FnOnce() + 'a),+> From<($($f,)+)> for DeferGroup<'a> {
+            #[allow(non_snake_case)]
+            fn from(($($f,)+): ($($f,)+)) -> Self {
+                let mut group = Self::new();
+                $(group.push(Box::new($f));)+
+                group
+            }
+        }
+    )*};
+}
+
+impl_from_tuple! {
+    (F1)
+    (F1, F2)
+    (F1, F2, F3)
+    (F1, F2, F3, F4)
+    (F1, F2, F3, F4, F5)
+    (F1, F2, F3, F4, F5, F6)
+    (F1, F2, F3, F4, F5, F6, F7)
+    (F1, F2, F3, F4, F5, F6, F7, F8)
+    (F1, F2, F3, F4, F5, F6, F7, F8, F9)
+    (F1, F2, F3, F4, F5, F6, F7, F8, F9, F10)
+    (F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11)
+    (F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12)
+}
+
+impl<'a> Drop for DeferGroup<'a> {
+    #[inline]
+    fn drop(self: &mut DeferGroup<'a>) {
+        if !self.strategy.should_run()
+            || self.is_empty()
+            || debug::skipped(|| format!("{} deferred closure(s) of a `DeferGroup`", self.len()))
+        {
+            return;
+        }
+        let mut panic = None;
+        // The queue is drained one closure at a time, as re-entrant closures may queue more closures while it's executed
+        while let Some(entry) = self.pop_next() {
+            let _span = hooks::executing_queued(self.name);
+            let panic_policy = self.panic_policy;
+            let execute = || match entry.deferred {
+                Job::Plain(f) => f(),
+                Job::Reentrant(f) => f(self),
+            };
+            match panic_policy {
+                PanicPolicy::Stop => execute(),
+                PanicPolicy::Continue => execute_catching(execute, &mut panic),
+            }
+        }
+        // Unwinding again while already unwinding would abort
+        if let Some(payload) = panic.filter(|_| !std::thread::panicking()) {
+            resume_panic(payload);
+        }
+    }
+}
+
+// The panic handling of `DeferGroup::drop` is kept out of line, so the (inlined) common path only executes the closures:
+// executes the closure, keeping the first panic caught (by a group using `PanicPolicy::Continue`)
+#[cold]
+#[inline(never)]
+fn execute_catching(execute: impl FnOnce(), panic: &mut Option<Box<dyn std::any::Any + Send>>) {
+    if let Err(payload) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(execute)) {
+        panic.get_or_insert(payload);
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn resume_panic(payload: Box<dyn std::any::Any + Send>) -> ! {
+    std::panic::resume_unwind(payload)
+}
+
+/// A macro for deferring execution of code until the current scope exits.
+///
+/// The `defer!` macro allows you to specify code that should be executed when the current
+/// scope (such as a function or block) is exited, regardless of whether the exit occurs normally
 /// or due to an early return, panic, or other unwinding.
 ///
-/// # Examples
+/// # Examples
+///
+/// ## Basic usage:
+///
+/// ```rust
+/// use defer_rs::defer;
+/// defer! {
+///     println!("This will be executed when the current scope exits.");
+/// }
+/// ```
+/// ### Expands to:
+/// ```rust
+/// {} - ::defer_rs::__EXPRESSION_POSITION;
+/// let ___deferred_code = ::defer_rs::Defer::new( || {
+///     println!("This will be executed when the current scope exits.");
+/// });
+/// ```
+/// The leading statement (an empty block, followed by a no-op negation) is part of every expansion, so that using `defer!` where a value
+/// is expected is reported as such, see [Expression position](#expression-position).
+///
+/// ## Multiple statements:
+/// Multiple statements also work (enclosed in a block or not).
+///
+/// ```rust
+/// use defer_rs::defer;
+/// defer! {
+///     println!("1st statement.");
+///     println!("2nd statement.");
+/// }
+/// ```
+/// ### Expands to:
+///
+/// ```rust
+/// {} - ::defer_rs::__EXPRESSION_POSITION;
+/// let ___deferred_code = ::defer_rs::Defer::new( || {
+///     println!("1st statement.");
+///     println!("2nd statement.");
+/// });
+/// ```
+///
+/// ## Move capturred values:
+/// Sometimes it's necessary to `move` the values capturred from the environment into the generated closure,
+/// in this case `move` can be added before the deferred statements and it will be added to the closure.
+///
+/// ```rust
+/// use defer_rs::defer;
+/// let val = "Variable that must be passed by value!";
+/// defer!( move {
+///     println!("`val` is captured by value!");
+///     println!("{}", val);
+/// });
+/// ```
+/// ### Expands to:
+///
+/// ```rust
+/// let val = "Variable that must be passed by value!";
+/// {} - ::defer_rs::__EXPRESSION_POSITION;
+/// let ___deferred_code = ::defer_rs::Defer::new( move || {
+///     println!("`val` is captured by value!");
+///     println!("{}", val);
+/// });
+/// ```
+///
+/// ## Immmediately evaluating passed arguments:
+/// A common pattern in languages with a `defer` keyword (i.e., `Go`),
+/// is to defer a single function/method call,
+/// in that case, arguments passed to the call are usually evaluated immediately,
+/// this can be mimicked using the `defer!` macro on a single call expression,
+/// This behavior can easily be disabled by postfixing the  call expression.
+///
+/// Note: `move` cannot be used when the macro is used this way,
+/// as it's implied.
+///
+/// ```rust
+/// use defer_rs::defer;
+/// use std::cell::Cell;
+///
+/// fn print(to_print: String) {
+///     println!("{to_print}");
+/// }
+///
+/// let x = Cell::new(0);   
+/// // This will evaluate the arguments passed to the invoked at function at time of macro invocation (now), results in `0`
+/// defer!(print(format!("Var x now is: {}", x.get())));
+/// // This will evaluate the arguments passed to the invoked at function at call time (deferred-to time, later), results in `3`
+/// defer!(print(format!("Var x later is: {}", x.get())););
+/// x.set(3);
+/// ```
+/// ### Expands to:
+///
+/// ```rust
+/// use std::cell::Cell;
+///
+/// fn print(to_print: String) {
+///     println!("{to_print}");
+/// }
+///
+/// let x = Cell::new(0);
+/// {} - ::defer_rs::__EXPRESSION_POSITION;
+/// let ___deferred_code_captured_args = (format!("Var x now is: {}", x.get()), );
+/// let ___deferred_code = ::defer_rs::Defer::new( move || {
+///                 print(___deferred_code_captured_args.0);
+/// });
+/// {} - ::defer_rs::__EXPRESSION_POSITION;
+/// let ___deferred_code = ::defer_rs::Defer::new(|| {
+///     print(format!("Var x later is: {}", x.get()))
+/// });
+/// x.set(3);
+/// ```
+///
+/// ## Binding the guard:
+/// Prefixing the deferred code with `as name;` binds the [`Defer`] guard to `name` instead of a hidden identifier,
+/// so the deferred code can be executed early (using [`Defer::run_now`]), or cancelled (using [`Defer::cancel`]).
+/// Single call expressions still have their arguments evaluated immediately.
+///
+/// ```rust
+/// use defer_rs::defer;
+///
+/// fn write_all() -> Result<(), ()> {
+///     Ok(())
+/// }
+///
+/// defer!(as close; println!("Closing the file..."));
+/// // The file is closed even if this fails
+/// write_all()?;
+/// // The file must be closed before it's renamed
+/// close.run_now();
+/// println!("Renaming the file...");
+/// # Ok::<(), ()>(())
+/// ```
+/// ### Expands to:
+///
+/// ```rust
+/// # fn write_all() -> Result<(), ()> { Ok(()) }
+/// {} - ::defer_rs::__EXPRESSION_POSITION;
+/// let close = ::defer_rs::Defer::new(|| {
+///     println!("Closing the file...");
+/// });
+/// write_all()?;
+/// close.run_now();
+/// println!("Renaming the file...");
+/// # Ok::<(), ()>(())
+/// ```
+///
+/// As the guard's closure has an anonymous type, it can't be [replaced](Defer::replace) by a different closure,
+/// a [`BoxDefer`] (created using [`Defer::boxed`]) must be bound directly for that.
+///
+/// ## Trailing `defer!`:
+/// A `defer!` invoked as the last statement of its block is executed right away, when the block ends, as if its code was written inline,
+/// which is usually a mistake (e.g. a `defer!` meant to run at the end of the function, placed inside an `if` block).
+/// In the bodies of [`try_defer_scope!`], [`defer_test`](macro@defer_test), and [`err_context`](macro@err_context), this is reported
+/// as the "use of deprecated constant `TRAILING_DEFER_RUNS_IMMEDIATELY`" (proc macros can't emit their own warnings on stable),
+/// pointing at the `defer!`. Being a `deprecated` lint, it can be denied using `#[deny(deprecated)]`, or allowed using `#[allow(deprecated)]`.
+///
+/// Trailing `defer!`s are caught in the body and in the blocks nested in it (including branches, loops and `unsafe` blocks).
+/// They aren't caught in closures and async blocks (where they may be meant to run when these return), in `match` arms,
+/// nor anywhere outside the bodies of these macros.
+///
+/// ```rust,compile_fail
+/// #![deny(deprecated)]
+/// use defer_rs::{defer, try_defer_scope};
+///
+/// fn connect(retry: bool) -> Result<(), String> {
+///     try_defer_scope! {
+///         if retry {
+///             // Executed right away, not when the block exits
+///             defer!(println!("Resetting the backoff..."));
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// ```rust
+/// #![deny(deprecated)]
+/// use defer_rs::{defer, try_defer_scope};
+///
+/// fn connect(retry: bool) -> Result<(), String> {
+///     try_defer_scope! {
+///         // Not caught: the closure's `defer!` runs when the closure returns, which may be intended
+///         let reset = || {
+///             defer!(println!("Resetting the backoff..."));
+///         };
+///         if retry {
+///             reset();
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// ## Expression position:
+/// `defer!` is a statement, it can't be used where a value is expected (e.g. bound using `let`, or returned), which is reported as
+/// "`defer!` is a statement, it can't be used as an expression" (along with rustc's "the usage of `defer!` is likely invalid in expression context").
+/// Use [`defer_guard!`] (or [`Defer::new`]) to create the guard as a value instead.
+///
+/// ```rust,compile_fail,E0277
+/// use defer_rs::defer;
+///
+/// let guard = defer!(println!("Closing the connection..."));
+/// ```
+///
+/// ```rust
+/// use defer_rs::defer_guard;
+///
+/// let guard = defer_guard!(println!("Closing the connection..."));
+/// ```
+///
+/// ## Priorities:
+/// Code deferred using `defer!` is executed in reverse order of declaration (the order its hidden bindings are dropped in).
+/// To order code deferred independently, prefix it with `priority = N;` (`N` being an `i32` expression): it's then queued on the group
+/// initialized by [`defer_scope_init!`] (which must be invoked beforehand, in the same scope), exactly like
+/// [`defer_scope!(priority = N; ...)`](defer_scope!), code with a higher priority being executed first.
+/// `move` can follow the prefix, but arguments of a single call expression aren't evaluated immediately.
+///
+/// ```rust
+/// use defer_rs::{defer, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer!(priority = 1; println!("Closing the file..."));
+/// // Registered later, but executed first
+/// defer!(priority = 10; println!("Flushing the buffer..."));
+/// ```
+/// ### Expands to:
+///
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// ___deferred_code_group.add_with_priority(1, Box::new(|| {
+///     println!("Closing the file...");
+/// }));
+/// ___deferred_code_group.add_with_priority(10, Box::new(|| {
+///     println!("Flushing the buffer...");
+/// }));
+/// ```
+///
+/// Without a group to queue the code on, it fails to compile, the error naming the requirement
+/// (``cannot find macro `defer_scope_init_is_required_before_prioritized_defer` in this scope``):
+///
+/// ```rust,compile_fail
+/// use defer_rs::defer;
+///
+/// defer!(priority = 1; println!("Closing the file..."));
+/// ```
+///
+/// **Note:** any input starting with `priority = ...;` is taken as a priority, including code which used to be deferred as is,
+/// e.g. `defer!(priority = 0; f())` previously deferred the assignment `priority = 0` (of an existing `priority` variable),
+/// followed by `f()`. To defer such code, wrap it in a block: `defer!({ priority = 0; f() })`.
+///
+/// See also: [`Defer`], [`DeferGroup`], [`defer_fn!`], [`defer_guard!`], and [`defer_scope!`].
+#[macro_export]
+macro_rules! defer{
+    // The expansions start with `{} - $crate::__EXPRESSION_POSITION;`: as statements, an empty block followed by a (no-op) negation,
+    // but in expression position, a subtraction whose unsatisfied bound reports that `defer!` is a statement (see `__Statement`),
+    // along with rustc's "the usage of `defer!` is likely invalid in expression context", instead of an unexpected `let` statement
+
+    // The guard is bound to the given name, instead of the hidden identifier
+    (as $name:ident; move $($body:tt)+) => {
+        {} - $crate::__EXPRESSION_POSITION;
+        let $name = $crate::Defer::new(move || {
+            $($body)+
+        });
+    };
+
+    (as $name:ident; $func:ident($($arg:expr),* $(,)? )) => {
+        {} - $crate::__EXPRESSION_POSITION;
+        let ___deferred_code_captured_args = ( $( $arg, )* );
+        let $name = $crate::Defer::new(move|| {
+            $crate::__call_indexed!($func, ___deferred_code_captured_args; $($arg),*);
+        });
+    };
+
+    (as $name:ident; $($body:tt)+) => {
+        {} - $crate::__EXPRESSION_POSITION;
+        let $name = $crate::Defer::new(|| {
+            $($body)+
+        });
+    };
+
+    // Prioritized code is queued on the group initialized by `defer_scope_init!`, which a `macro_rules` macro can't refer to directly
+    (priority = $priority:expr; $($body:tt)+) => {
+        {} - $crate::__EXPRESSION_POSITION;
+        $crate::defer_priority!(($priority) $($body)+);
+    };
+
+    // This pattern doesn't match the code directly (unless the input is a block statement), but takes the results from the last two patterns!
+    ($(@$move_kw:ident@)? $body:block$(;)?) => {
+        {} - $crate::__EXPRESSION_POSITION;
+        let ___deferred_code =$crate::Defer::new($($move_kw)?||
+            $body
+        );
+    };
+
+    // This either matches immediately or doesn't at all!
+    ($func:ident($($arg:expr),* $(,)? )) => {
+        {} - $crate::__EXPRESSION_POSITION;
+        let ___deferred_code_captured_args = ( $( $arg, )* );
+        let ___deferred_code =$crate::Defer::new(move|| {
+            $crate::__call_indexed!($func, ___deferred_code_captured_args; $($arg),*);
+        });
+    };
+
+    // The following two patterns are only here to surround the input in a block statement and to filter the `move` keyword
+    // and pass it back (if it exists) recursively to the the first case to handle the actual code generation
+    (move $($body:tt)+ ) => {
+        defer!(@move@ {$($body)*})
+    };
+
+    ($($body:tt)+ ) => {
+        defer!({$($body)*})
+    };
+}
+
+// The first expression of every `defer!` expansion, negated when `defer!` is used as a statement (as it should be),
+// but subtracted from `()` when it's used in expression position, which requires the `__Statement` bound
+#[doc(hidden)]
+pub const __EXPRESSION_POSITION: __ExpressionPosition<()> =
+    __ExpressionPosition(std::marker::PhantomData);
+
+#[doc(hidden)]
+pub struct __ExpressionPosition<T>(std::marker::PhantomData<T>);
+
+impl<T> std::ops::Neg for __ExpressionPosition<T> {
+    type Output = ();
+
+    fn neg(self) {}
+}
+
+impl<T: __Statement> std::ops::Sub<__ExpressionPosition<T>> for () {
+    type Output = ();
+
+    fn sub(self, _: __ExpressionPosition<T>) {}
+}
+
+// Never implemented, only here to report `defer!` used in expression position
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "`defer!` is a statement, it can't be used as an expression",
+    label = "`defer!` used in expression position",
+    note = "use `defer_guard!` (or `Defer::new`) to create the guard as a value"
+)]
+pub trait __Statement {}
+
+// Imported by `defer_scope_init!` under a name explaining the requirement, which `defer!(priority = N; ...)` reaches the group through
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __scope_group {
+    ($group:ident) => {
+        $group
+    };
+}
+
+// Calls `$func` with the fields of the `$args` tuple, which holds one field per (already evaluated) argument in `$($arg),*`;
+// the common arities are handled here, so the usual `defer!` call form doesn't go through a proc macro
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __call_indexed {
+    ($func:ident, $args:ident; ) => {
+        $func()
+    };
+
+    ($func:ident, $args:ident; $_0:expr) => {
+        $func($args.0)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr) => {
+        $func($args.0, $args.1)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr) => {
+        $func($args.0, $args.1, $args.2)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr, $_6:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5, $args.6)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr, $_6:expr, $_7:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5, $args.6, $args.7)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr, $_6:expr, $_7:expr, $_8:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5, $args.6, $args.7, $args.8)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr, $_6:expr, $_7:expr, $_8:expr, $_9:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5, $args.6, $args.7, $args.8, $args.9)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr, $_6:expr, $_7:expr, $_8:expr, $_9:expr, $_10:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5, $args.6, $args.7, $args.8, $args.9, $args.10)
+    };
+
+    ($func:ident, $args:ident; $_0:expr, $_1:expr, $_2:expr, $_3:expr, $_4:expr, $_5:expr, $_6:expr, $_7:expr, $_8:expr, $_9:expr, $_10:expr, $_11:expr) => {
+        $func($args.0, $args.1, $args.2, $args.3, $args.4, $args.5, $args.6, $args.7, $args.8, $args.9, $args.10, $args.11)
+    };
+
+    // `call_indexed!` refers to the tuple by its fixed name, rebound here so it resolves from this macro's expansion
+    ($func:ident, $args:ident; $($arg:expr),*) => {{
+        let ___deferred_code_captured_args = $args;
+        $crate::call_indexed!($func($($arg),*))
+    }};
+}
+
+/// A macro for deferring the invocation of an existing callable (a closure or function) until the current scope exits.
+///
+/// Unlike [`defer!`], which wraps the given code in a new closure, `defer_fn!` takes a `FnOnce()` value (evaluated immediately)
+/// and defers calling it, e.g. `defer!(cleanup)` would only evaluate (and discard) `cleanup` when the scope exits,
+/// while `defer_fn!(cleanup)` calls it.
 ///
-/// ## Basic usage:
+/// # Example
 ///
 /// ```rust
-/// use defer_rs::defer;
-/// defer! {
-///     println!("This will be executed when the current scope exits.");
+/// use defer_rs::defer_fn;
+///
+/// fn cleanup() {
+///     println!("Cleaning up...");
 /// }
+///
+/// let on_exit = || println!("Exiting...");
+///
+/// defer_fn!(cleanup);
+/// defer_fn!(on_exit);
 /// ```
 /// ### Expands to:
+///
 /// ```rust
-/// let ___deferred_code = ::defer_rs::Defer::new( || {
-///     println!("This will be executed when the current scope exits.");
-/// });
+/// # fn cleanup() {}
+/// # let on_exit = || {};
+/// let ___deferred_code = ::defer_rs::Defer::new(cleanup);
+/// let ___deferred_code = ::defer_rs::Defer::new(on_exit);
 /// ```
 ///
-/// ## Multiple statements:
-/// Multiple statements also work (enclosed in a block or not).
+/// See also: [`defer!`], and [`Defer`].
+#[macro_export]
+macro_rules! defer_fn {
+    ($func:expr $(,)?) => {
+        let ___deferred_code = $crate::Defer::new($func);
+    };
+}
+
+/// A macro creating a [`Defer`] guard from the given code, as an expression, e.g. to be returned, or stored in a struct.
+///
+/// `defer!` is a statement (binding the guard to a hidden variable), so it can't be used where a value is expected,
+/// `defer_guard!` takes the same input (code, optionally prefixed with `move`, or a single call expression whose arguments
+/// are evaluated immediately), but evaluates to the guard instead.
+///
+/// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// # Example
 ///
 /// ```rust
-/// use defer_rs::defer;
-/// defer! {
-///     println!("1st statement.");
-///     println!("2nd statement.");
+/// use defer_rs::{defer_guard, Defer};
+///
+/// fn open_connection() -> Defer<impl FnOnce()> {
+///     // ... open the connection ...
+///     defer_guard!(println!("Closing the connection..."))
 /// }
+///
+/// let _connection = open_connection();
 /// ```
 /// ### Expands to:
 ///
 /// ```rust
-/// let ___deferred_code = ::defer_rs::Defer::new( || {
-///     println!("1st statement.");
-///     println!("2nd statement.");
-/// });
+/// # use defer_rs::Defer;
+/// fn open_connection() -> Defer<impl FnOnce()> {
+///     ::defer_rs::Defer::new(|| {
+///         println!("Closing the connection...")
+///     })
+/// }
 /// ```
 ///
-/// ## Move capturred values:
-/// Sometimes it's necessary to `move` the values capturred from the environment into the generated closure,
-/// in this case `move` can be added before the deferred statements and it will be added to the closure.
+/// See also: [`defer!`], and [`Defer`].
+#[macro_export]
+macro_rules! defer_guard {
+    ($func:ident($($arg:expr),* $(,)? )) => {
+        {
+            let ___deferred_code_captured_args = ( $( $arg, )* );
+            $crate::Defer::new(move || {
+                $crate::__call_indexed!($func, ___deferred_code_captured_args; $($arg),*);
+            })
+        }
+    };
+
+    (move $($body:tt)+) => {
+        $crate::Defer::new(move || {
+            $($body)+
+        })
+    };
+
+    ($($body:tt)+) => {
+        $crate::Defer::new(|| {
+            $($body)+
+        })
+    };
+}
+
+/// A macro registering code to be executed by the process-wide [registry](crate::registry), when [`registry::run_all`] is invoked.
+///
+/// The code is wrapped in a `move` closure (`move` can also be written explicitly), which must be `Send + 'static`,
+/// this is checked at compile time. Prefixing the code with `priority = N;` (`N` being an `i32` expression) registers it with that
+/// priority, see [`registry::register_with_priority`].
+///
+/// # Example
 ///
 /// ```rust
-/// use defer_rs::defer;
-/// let val = "Variable that must be passed by value!";
-/// defer!( move {
-///     println!("`val` is captured by value!");
-///     println!("{}", val);
-/// });
+/// use defer_rs::{registry, static_defer};
+///
+/// static_defer!(println!("Closing the database pool..."));
+/// static_defer!(priority = 10; println!("Stopping the HTTP server..."));
+///
+/// // ... the rest of `main` ...
+///
+/// registry::run_all();
 /// ```
 /// ### Expands to:
 ///
 /// ```rust
-/// let val = "Variable that must be passed by value!";
-/// let ___deferred_code = ::defer_rs::Defer::new( move || {
-///     println!("`val` is captured by value!");
-///     println!("{}", val);
+/// ::defer_rs::registry::register(move || {
+///     println!("Closing the database pool...")
+/// });
+/// ::defer_rs::registry::register_with_priority(10, move || {
+///     println!("Stopping the HTTP server...")
 /// });
 /// ```
 ///
-/// ## Immmediately evaluating passed arguments:
-/// A common pattern in languages with a `defer` keyword (i.e., `Go`),
-/// is to defer a single function/method call,
-/// in that case, arguments passed to the call are usually evaluated immediately,
-/// this can be mimicked using the `defer!` macro on a single call expression,
-/// This behavior can easily be disabled by postfixing the  call expression.
+/// Closures borrowing local variables are rejected:
 ///
-/// Note: `move` cannot be used when the macro is used this way,
-/// as it's implied.
+/// ```rust,compile_fail
+/// let name = String::from("db");
+/// let name = &name;
+/// defer_rs::static_defer!(println!("Closing {name}..."));
+/// ```
 ///
-/// ```rust
-/// use defer_rs::defer;
-/// use std::cell::Cell;
+/// See also: [`registry::register`], and [`defer!`].
+#[macro_export]
+macro_rules! static_defer {
+    // The closure is always `move`, an explicit `move` is filtered out
+    (priority = $priority:expr; move $($body:tt)+) => {
+        $crate::static_defer!(priority = $priority; $($body)+)
+    };
+
+    (priority = $priority:expr; $($body:tt)+) => {
+        $crate::registry::register_with_priority($priority, move || {
+            $($body)+
+        });
+    };
+
+    (move $($body:tt)+) => {
+        $crate::static_defer!($($body)+)
+    };
+
+    ($($body:tt)+) => {
+        $crate::registry::register(move || {
+            $($body)+
+        });
+    };
+}
+
+/// A macro generating a named RAII guard type for a resource, released (using a given path or closure) when the guard goes out of scope.
 ///
-/// fn print(to_print: String) {
-///     println!("{to_print}");
-/// }
+/// This lets domain-specific guards (e.g. `DbLockGuard`) be minted without boilerplate. The generated guard:
+/// - Is created using `new`, which acquires the resource (using the `acquire` expression, which can use the declared arguments),
+///   or `from_resource`, which wraps an already acquired resource.
+/// - Dereferences (mutably) to the resource.
+/// - Can be disarmed using `disarm` (the resource is then dropped as usual, without being released),
+///   or consumed using `into_inner`, returning the resource without releasing it.
 ///
-/// let x = Cell::new(0);   
-/// // This will evaluate the arguments passed to the invoked at function at time of macro invocation (now), results in `0`
-/// defer!(print(format!("Var x now is: {}", x.get())));
-/// // This will evaluate the arguments passed to the invoked at function at call time (deferred-to time, later), results in `3`
-/// defer!(print(format!("Var x later is: {}", x.get())););
-/// x.set(3);
-/// ```
-/// ### Expands to:
+/// `release` must evaluate to something callable with the resource (by value), e.g. a method path, or a closure.
+/// Generic guard types aren't supported.
+///
+/// **Note: the generated guard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, releasing the resource!**
+///
+/// # Example
 ///
 /// ```rust
-/// use std::cell::Cell;
+/// use defer_rs::make_guard;
 ///
-/// fn print(to_print: String) {
-///     println!("{to_print}");
+/// pub struct Db;
+/// pub struct DbLock(&'static str);
+///
+/// impl Db {
+///     fn lock(&self, table: &'static str) -> DbLock {
+///         println!("Locking `{table}`...");
+///         DbLock(table)
+///     }
 /// }
 ///
-/// let x = Cell::new(0);
-/// let ___deferred_code_captured_args = (format!("Var x now is: {}", x.get()), );
-/// let ___deferred_code = ::defer_rs::Defer::new( move || {
-///                 print(___deferred_code_captured_args.0);
-/// });
-/// let ___deferred_code = ::defer_rs::Defer::new(|| {
-///     print(format!("Var x later is: {}", x.get()))
-/// });
-/// x.set(3);
+/// impl DbLock {
+///     fn unlock(self) {
+///         println!("Unlocking `{}`...", self.0);
+///     }
+/// }
+///
+/// make_guard! {
+///     /// A lock on a database table, unlocked when it goes out of scope.
+///     pub struct DbLockGuard(DbLock) {
+///         acquire(db: &Db, table: &'static str) => db.lock(table),
+///         release => DbLock::unlock,
+///     }
+/// }
+///
+/// let lock = DbLockGuard::new(&Db, "users");
+/// assert_eq!(lock.0, "users");
+/// // "Unlocking `users`..." is printed when `lock` goes out of scope
 /// ```
 ///
-/// See also: [`Defer`], [`DeferGroup`], and [`defer_scope!`].
+/// See also: [`Defer`], and [`with_resource`].
 #[macro_export]
-macro_rules! defer{
-    // This pattern doesn't match the code directly (unless the input is a block statement), but takes the results from the last two patterns!
-    ($(@$move_kw:ident@)? $body:block$(;)?) => {
-        let ___deferred_code =$crate::Defer::new($($move_kw)?||
-            $body
-        );
-    };
+macro_rules! make_guard {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($resource:ty) {
+            acquire($($arg:ident: $arg_ty:ty),* $(,)?) => $acquire:expr,
+            release => $release:expr $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[must_use]
+        $vis struct $name {
+            resource: ::std::option::Option<$resource>,
+            armed: bool,
+        }
 
-    // This either matches immediately or doesn't at all!
-    ($func:ident($($arg:expr),* $(,)? )) => {
-        let ___deferred_code_captured_args = ( $( $arg, )* );
-        let ___deferred_code =$crate::Defer::new(move|| {
-            ::defer_rs_impl::call_indexed!($func($($arg),*));
-        });
-    };
+        impl $name {
+            /// Acquires the resource, returning a guard releasing it when it goes out of scope.
+            $vis fn new($($arg: $arg_ty),*) -> Self {
+                Self::from_resource($acquire)
+            }
 
-    // The following two patterns are only here to surround the input in a block statement and to filter the `move` keyword
-    // and pass it back (if it exists) recursively to the the first case to handle the actual code generation
-    (move $($body:tt)+ ) => {
-        defer!(@move@ {$($body)*})
-    };
+            /// Wraps an already acquired resource, returning a guard releasing it when it goes out of scope.
+            $vis fn from_resource(resource: $resource) -> Self {
+                Self {
+                    resource: ::std::option::Option::Some(resource),
+                    armed: true,
+                }
+            }
 
-    ($($body:tt)+ ) => {
-        defer!({$($body)*})
+            /// Disarms the guard, the resource is dropped as usual (without being released) when the guard goes out of scope.
+            $vis fn disarm(&mut self) {
+                self.armed = false;
+            }
+
+            /// Consumes the guard, returning the resource without releasing it.
+            $vis fn into_inner(mut self) -> $resource {
+                // `resource` can only be `None` once the guard is consumed
+                self.resource.take().unwrap()
+            }
+        }
+
+        impl ::std::ops::Deref for $name {
+            type Target = $resource;
+
+            fn deref(&self) -> &$resource {
+                self.resource.as_ref().unwrap()
+            }
+        }
+
+        impl ::std::ops::DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $resource {
+                self.resource.as_mut().unwrap()
+            }
+        }
+
+        impl ::std::ops::Drop for $name {
+            fn drop(&mut self) {
+                let ::std::option::Option::Some(resource) = self.resource.take() else {
+                    return;
+                };
+                if !self.armed
+                    || $crate::debug::skipped(|| ::std::format!("releasing a `{}`", ::std::stringify!($name)))
+                {
+                    return;
+                }
+                let _span = $crate::hooks::executing::<Self>(::std::stringify!($name));
+                ($release)(resource);
+            }
+        }
     };
 }
 
@@ -341,30 +2377,185 @@ macro_rules! defer{
 /// })));
 /// ```
 ///
+/// ## Targeting an enclosing group:
+/// When groups are nested (using `defer_scope_init!(nested)` in the nested scopes), an enclosing group can be targeted by its depth,
+/// `1` being the innermost group (the default), `2` its parent, and so on.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// for i in 0..3 {
+///     defer_scope_init!(nested);
+///     defer_scope!(println!("Executed at the end of iteration #{i}."));
+///     defer_scope!(2: move println!("Executed once the loop is done, for iteration #{i}."));
+/// }
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// for i in 0..3 {
+///     let mut ___deferred_code_group = ::defer_rs::NestedDeferGroup::new(&mut ___deferred_code_group);
+///     ___deferred_code_group.add(Box::new(|| {
+///         println!("Executed at the end of iteration #{i}.");
+///     }));
+///     ___deferred_code_group.parent().add(Box::new(move || {
+///         println!("Executed once the loop is done, for iteration #{i}.");
+///     }));
+/// }
+/// ```
+///
+/// ## Appending to the group:
+/// By default, the deferred code is added to the start of the group's queue (using `add`), so it's executed before
+/// the code deferred earlier. Prefixing it with `push:` appends it to the end of the queue (using `push`) instead,
+/// so it's executed after the code deferred earlier. A depth can precede it, e.g. `defer_scope!(2: push: ...)`.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer_scope!(push: println!("This will be printed 1st."));
+/// defer_scope!(push: println!("This will be printed 2nd."));
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// ___deferred_code_group.push(Box::new(|| {
+///     println!("This will be printed 1st.");
+/// }));
+/// ___deferred_code_group.push(Box::new(|| {
+///     println!("This will be printed 2nd.");
+/// }));
+/// ```
+///
+/// ## Priorities:
+/// Prefixing the deferred code with `priority = N;` (`N` being an `i32` expression) queues it with that priority
+/// (using `add_with_priority`, or `push_with_priority` when followed by `push:`): code with a higher priority is executed first,
+/// regardless of the order of registration, and code deferred without a priority has a priority of `0`.
+/// This lets code deferred independently be ordered relative to each other. A depth can precede it, e.g. `defer_scope!(2: priority = 10; ...)`.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer_scope!(priority = -1; println!("This will be printed 3rd."));
+/// defer_scope!(println!("This will be printed 2nd."));
+/// defer_scope!(priority = 10; println!("This will be printed 1st."));
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// ___deferred_code_group.add_with_priority(-1, Box::new(|| {
+///     println!("This will be printed 3rd.");
+/// }));
+/// ___deferred_code_group.add(Box::new(|| {
+///     println!("This will be printed 2nd.");
+/// }));
+/// ___deferred_code_group.add_with_priority(10, Box::new(|| {
+///     println!("This will be printed 1st.");
+/// }));
+/// ```
+///
+/// ## Binding a handle to the deferred code:
+/// Prefixing the deferred code with `as name;` binds a [`RunOnce`](RunOnce) handle to the queued code to `name`,
+/// so it can be executed early (using `name.run()`), or cancelled (using `name.cancel()`), while it's still queued on the group.
+/// The prefix must come first, e.g. `defer_scope!(as name; 2: push: ...)`.
+///
+/// ```rust
+/// use defer_rs::{defer_scope, defer_scope_init};
+///
+/// defer_scope_init!();
+/// defer_scope!(as drop_table; println!("Dropping the temporary table..."));
+/// defer_scope!(as unlock; println!("Releasing the lock..."));
+/// // The lock is released now, instead of when the scope exits
+/// unlock.run();
+/// // The table was kept, it must not be dropped
+/// drop_table.cancel();
+/// ```
+/// ### Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// let drop_table = ::defer_rs::RunOnce::new(|| {
+///     println!("Dropping the temporary table...");
+/// });
+/// ___deferred_code_group.add(Box::new(drop_table.callback()));
+/// let unlock = ::defer_rs::RunOnce::new(|| {
+///     println!("Releasing the lock...");
+/// });
+/// ___deferred_code_group.add(Box::new(unlock.callback()));
+/// unlock.run();
+/// drop_table.cancel();
+/// ```
+///
 /// Ignoring the ability to specify the scope and the need for invoking `defer_scope_init!` beforehand,
 /// `defer_scope!` is otherwise identical to [`defer!`].
 ///
 /// For more usage examples, refer to the documentation for the [`defer!`] macro,
 /// simply replace `defer!` with `defer_scope!` and add an invocation of [`defer_scope_init!`] beforehand.
 ///
-/// See also: [`DeferGroup`], [`defer_scope_init!`], and [`defer!`].
-#[cfg(doc)]
-#[macro_export]
-macro_rules! defer_scope { ($($tt:tt)*) => { ... } }
-
-/// Initializes a [DeferGroup], which is an empty collection of closures to run at the end of the scope containing the invocation.
-/// It provides no functionality by itself and should be called before any [defer_scope!] invocation(s).
+/// See also: [`DeferGroup`], [`defer_scope_init!`], and [`defer!`].
+#[cfg(doc)]
+#[macro_export]
+macro_rules! defer_scope { ($($tt:tt)*) => { ... } }
+
+/// Initializes a [DeferGroup], which is an empty collection of closures to run at the end of the scope containing the invocation.
+/// It provides no functionality by itself and should be called before any [defer_scope!] invocation(s).
+///
+/// No arguments should be passed to the macro invocation, except for `nested` (see below).
+///
+/// # Usage
+///
+/// ```rust
+/// defer_rs::defer_scope_init!();
+/// ```
+/// ## Expands to:
+/// ```rust
+/// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// ```
+///
+/// ## Invoking it more than once in a scope:
+/// Only one group can be initialized per scope, invoking `defer_scope_init!` again in the same scope is a compile error
+/// (rather than silently shadowing the first group, changing which group the following [defer_scope!] invocations target).
+/// To use another group, initialize it in a nested scope.
 ///
-/// No arguments should be passed to the macro invocation.
+/// ```rust,compile_fail
+/// defer_rs::defer_scope_init!();
+/// defer_rs::defer_scope_init!(); // error[E0428]: the name `___DEFER_SCOPE_INIT_ALREADY_INVOKED_IN_THIS_SCOPE` is defined multiple times
+/// ```
 ///
-/// # Usage
+/// ## Nested groups:
+/// A group initialized using `defer_scope_init!(nested)` (in a scope nested in the scope of another group) is a [NestedDeferGroup],
+/// borrowing the enclosing group, which can then be targeted by [defer_scope!] using its depth (e.g. `defer_scope!(2: ...)`).
 ///
 /// ```rust
 /// defer_rs::defer_scope_init!();
+/// {
+///     defer_rs::defer_scope_init!(nested);
+/// }
 /// ```
 /// ## Expands to:
 /// ```rust
 /// let mut ___deferred_code_group = ::defer_rs::DeferGroup::new();
+/// {
+///     let mut ___deferred_code_group = ::defer_rs::NestedDeferGroup::new(&mut ___deferred_code_group);
+/// }
+/// ```
+///
+/// ## Binding a handle:
+/// `defer_scope_init!(let name)` also binds a [DeferScope] handle to `name`, sharing the group with [defer_scope!],
+/// so closures can be queued (or the group managed, e.g. rolled back to a savepoint) through direct method calls as well.
+///
+/// ```rust
+/// defer_rs::defer_scope_init!(let cleanup);
+/// defer_rs::defer_scope!(println!("Queued using the macro"));
+/// cleanup.add(Box::new(|| println!("Queued using the handle")));
+/// assert_eq!(cleanup.len(), 2);
+/// ```
+/// ## Expands to:
+/// ```rust
+/// let cleanup = ::defer_rs::DeferScope::new();
+/// #[allow(unused_mut)]
+/// let mut ___deferred_code_group = cleanup.clone();
 /// ```
 ///
 /// For more detailed examples, refer to the documentation for [defer_scope!].
@@ -372,28 +2563,30 @@ macro_rules! defer_scope { ($($tt:tt)*) => { ... } }
 /// See also: [`DeferGroup`], [`defer_scope!`], and [`defer!`].
 #[cfg(doc)]
 #[macro_export]
-macro_rules! defer_scope_init { () => { ... } }
+macro_rules! defer_scope_init { () => { ... }; (nested) => { ... }; (let $name:ident) => { ... } }
 
 #[cfg(test)]
 #[allow(unused)]
 mod tests {
     // use super::*;
-    use super::{defer, defer_scope, defer_scope_init, Defer, DeferGroup};
+    use super::testing::ExecutionRecorder;
+    use super::{
+        defer, defer_fn, defer_guard, defer_scope, defer_scope_init, BoxDefer, Defer, DeferGroup,
+        DynSendDefer, Order,
+    };
     use std::cell::{Cell, RefCell};
 
-    use std::io::Write;
-
     fn print(to_print: String) {
         println!("{to_print}");
     }
 
-    fn add_to_buffer(to_add: String, buff: &RefCell<Vec<u8>>) {
-        writeln!(buff.borrow_mut(), "{to_add}");
+    fn add_to_recorder(to_add: String, rec: &ExecutionRecorder) {
+        rec.record(to_add);
     }
 
     #[test]
     fn test_execution_order() {
-        let buff = RefCell::new(Vec::new());
+        let rec = ExecutionRecorder::new();
         let val = Cell::new(0);
 
         {
@@ -401,16 +2594,29 @@ mod tests {
             {
                 // This to ensure that the deferred statments are executed in the correct order
                 defer_scope!({
-                    let res = b"This will be printed 1st, x is: 1\nThis will be printed 2nd\nThis will be printed 3rd\nThis will be printed 4th\nThis will be printed 5th\nThis will be printed 6th\nThis will be printed 7th\nThis will be printed 8th, x is: 3\nThis will be printed 9th\nThis will be printed 10th, x is: 0\nThis will be printed 11th\nThis will be printed 12th\nThis will be printed 13th/last\n";
-                    assert_eq!(*buff.borrow(), res.to_vec());
+                    rec.assert_order(&[
+                        "This will be printed 1st, x is: 1",
+                        "This will be printed 2nd",
+                        "This will be printed 3rd",
+                        "This will be printed 4th",
+                        "This will be printed 5th",
+                        "This will be printed 6th",
+                        "This will be printed 7th",
+                        "This will be printed 8th, x is: 3",
+                        "This will be printed 9th",
+                        "This will be printed 10th, x is: 0",
+                        "This will be printed 11th",
+                        "This will be printed 12th",
+                        "This will be printed 13th/last",
+                    ]);
                 });
                 // The macro deferrs execution of the code passed to it to the end of the scope of the nearest DeferGroup going up
                 defer_scope!(
-                    writeln!(buff.borrow_mut(), "This will be printed 13th/last");
+                    rec.record("This will be printed 13th/last");
                 );
 
                 defer_scope!(
-                writeln!(buff.borrow_mut(), "This will be printed 12th");
+                rec.record("This will be printed 12th");
                 );
 
                 {
@@ -420,54 +2626,55 @@ mod tests {
 
                     // This will evaluate the arguments passed to the invoked at function at time of macro invocation (now), results in `0`
                     // Using `defer!` here instead of `defer_scope!` will result in identical behavior!
-                    defer_scope!(add_to_buffer(
+                    defer_scope!(add_to_recorder(
                         format!("This will be printed 1st, x is: {}", val.get()),
-                        &buff
+                        &rec
                     ));
                     val.set(3);
                 }
 
                 // `defer!` will delay the execution of code passed to it until the end of it's containg scope!
                 defer! {
-                    writeln!(buff.borrow_mut(), "This will be printed 3rd");
-                    writeln!(buff.borrow_mut(), "This will be printed 4th");
+                    rec.record("This will be printed 3rd");
+                    rec.record("This will be printed 4th");
                 };
 
                 defer_scope! {
-                    writeln!(buff.borrow_mut(), "This will be printed 11th");
+                    rec.record("This will be printed 11th");
                 };
 
                 defer! {
-                    writeln!(buff.borrow_mut(), "This will be printed 2nd");
+                    rec.record("This will be printed 2nd");
                 };
             }
-            writeln!(buff.borrow_mut(), "This will be printed 5th");
+            rec.record("This will be printed 5th");
 
             // This will evaluate the arguments passed to the invoked at function at time of macro invocation (now), results in `0`
-            defer_scope!(add_to_buffer(
+            defer_scope!(add_to_recorder(
                 format!("This will be printed 10th, x is: {}", val.get()),
-                &buff
+                &rec
             ));
 
             // This will evaluate the arguments passed to the invoked at function at call time (deferred-to time, later), results in `3`
             defer!({
-                add_to_buffer(
+                add_to_recorder(
                     format!("This will be printed 8th, x is: {}", val.get()),
-                    &buff,
+                    &rec,
                 )
             });
             val.set(3);
 
             defer_scope! {
-                writeln!(buff.borrow_mut(), "This will be printed 9th");
+                rec.record("This will be printed 9th");
             };
 
-            writeln!(buff.borrow_mut(), "This will be printed 6th");
+            rec.record("This will be printed 6th");
 
             defer! {
-                writeln!(buff.borrow_mut(), "This will be printed 7th");
+                rec.record("This will be printed 7th");
             };
         }
+        assert_eq!(rec.len(), 13);
     }
 
     #[test]
@@ -490,6 +2697,548 @@ mod tests {
         assert_eq!(val.get(), 1);
     }
 
+    #[test]
+    fn test_defer_group_insert_at() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push(Box::new(rec.callback("1st")));
+            group.push(Box::new(rec.callback("3rd")));
+            group.insert_at(1, Box::new(rec.callback("2nd")));
+            group.insert_at(3, Box::new(rec.callback("4th")));
+        }
+        rec.assert_order(&["1st", "2nd", "3rd", "4th"]);
+
+        // The inserted closures take the priority of their neighbour, keeping the queue sorted by priority
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push_with_priority(10, Box::new(rec.callback("1st")));
+            group.push_with_priority(-10, Box::new(rec.callback("5th")));
+            group.insert_at(1, Box::new(rec.callback("3rd")));
+            group.insert_at(2, Box::new(rec.callback("4th")));
+            group.add_with_priority(-5, Box::new(rec.callback("2nd")));
+            group.push_with_priority(-10, Box::new(rec.callback("6th")));
+        }
+        rec.assert_order(&["1st", "2nd", "3rd", "4th", "5th", "6th"]);
+    }
+
+    #[test]
+    fn test_defer_group_add_once() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            assert!(group.push_once("key", Box::new(rec.callback("1st"))));
+            assert!(!group.add_once("key", Box::new(rec.callback("duplicate"))));
+            assert!(group.add_once(String::from("other key"), Box::new(rec.callback("0th"))));
+            assert_eq!(group.len(), 2);
+
+            group.run_first(2);
+            assert!(group.add_once("key", Box::new(rec.callback("2nd"))));
+        }
+        rec.assert_order(&["0th", "1st", "2nd"]);
+    }
+
+    #[test]
+    fn test_defer_group_retain() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push(Box::new(rec.callback("unkeyed")));
+            group.push_once("a/1", Box::new(rec.callback("a/1")));
+            group.push_once("b/1", Box::new(rec.callback("b/1")));
+            group.push_once("a/2", Box::new(rec.callback("a/2")));
+            group.retain(|key| !key.is_some_and(|key| key.starts_with("a/")));
+            assert_eq!(group.len(), 2);
+        }
+        rec.assert_order(&["unkeyed", "b/1"]);
+    }
+
+    #[test]
+    fn test_defer_group_disarm_all() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.add(Box::new(rec.callback("disarmed")));
+            group.add_reentrant(Box::new(|_| rec.record("disarmed")));
+            assert_eq!(group.disarm_all(), 2);
+            group.add(Box::new(rec.callback("queued afterwards")));
+        }
+        rec.assert_order(&["queued afterwards"]);
+    }
+
+    #[test]
+    fn test_defer_group_into_inner() {
+        let rec = ExecutionRecorder::new();
+        let mut group = DeferGroup::new();
+        group.push(Box::new(rec.callback("1st")));
+        group.push_with_priority(1, Box::new(rec.callback("0th")));
+        let deferred = group.into_inner();
+        assert!(rec.is_empty());
+        deferred.into_iter().for_each(|f| f());
+        rec.assert_order(&["0th", "1st"]);
+    }
+
+    #[test]
+    fn test_defer_group_guards() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push(Box::new(rec.callback("1st")));
+            group.push_guard(Defer::new(rec.callback("2nd")));
+            group.add_guard(Defer::new(rec.callback("0th")));
+            assert!(rec.is_empty());
+        }
+        rec.assert_order(&["0th", "1st", "2nd"]);
+    }
+
+    #[test]
+    fn test_defer_group_take() {
+        let rec = ExecutionRecorder::new();
+        let mut other = DeferGroup::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push_once("moved", Box::new(rec.callback("moved")));
+            group.push(Box::new(rec.callback("group")));
+            assert!(group.take("missing").is_none());
+            other.push(group.take("moved").unwrap());
+            assert!(group.add_once("moved", Box::new(|| {})));
+            assert!(group.take("moved").is_some());
+        }
+        drop(other);
+        rec.assert_order(&["group", "moved"]);
+    }
+
+    #[test]
+    fn test_defer_group_from_tuple() {
+        let rec = ExecutionRecorder::new();
+        {
+            let _group = DeferGroup::from((
+                rec.callback("1st"),
+                || rec.record("2nd"),
+                rec.callback("3rd"),
+            ));
+        }
+        rec.assert_order(&["1st", "2nd", "3rd"]);
+    }
+
+    #[test]
+    fn test_defer_group_add_group() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push(Box::new(rec.callback("3rd")));
+
+            let mut child = DeferGroup::new();
+            child.push(Box::new(rec.callback("1st")));
+            child.push(Box::new(rec.callback("2nd")));
+            group.add_group(child);
+
+            let mut child = DeferGroup::on_unwind();
+            child.push(Box::new(rec.callback("rolled back")));
+            group.push_group(child);
+            assert_eq!(group.len(), 3);
+        }
+        rec.assert_order(&["1st", "2nd", "3rd"]);
+    }
+
+    #[test]
+    fn test_defer_group_reentrant() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push(Box::new(rec.callback("3rd")));
+            group.add_reentrant(Box::new(|group| {
+                rec.record("1st");
+                group.add(Box::new(rec.callback("2nd")));
+                group.push_reentrant(Box::new(|group| {
+                    rec.record("4th");
+                    group.push(Box::new(rec.callback("5th")));
+                }));
+            }));
+        }
+        rec.assert_order(&["1st", "2nd", "3rd", "4th", "5th"]);
+
+        rec.clear();
+        let mut group = DeferGroup::new();
+        group.push(Box::new(rec.callback("last")));
+        group.add_reentrant(Box::new(|group| {
+            group.push(Box::new(rec.callback("right after")))
+        }));
+        group.run_first(1);
+        rec.assert_order(&["right after"]);
+    }
+
+    #[test]
+    fn test_make_guard_macro() {
+        crate::make_guard! {
+            struct RecordGuard(ExecutionRecorder) {
+                acquire(rec: &ExecutionRecorder) => rec.clone(),
+                release => |rec: ExecutionRecorder| rec.record("released"),
+            }
+        }
+
+        let rec = ExecutionRecorder::new();
+        {
+            let guard = RecordGuard::new(&rec);
+            guard.record("used");
+        }
+        {
+            let mut guard = RecordGuard::new(&rec);
+            guard.disarm();
+        }
+        let _rec = RecordGuard::from_resource(rec.clone()).into_inner();
+        rec.assert_order(&["used", "released"]);
+    }
+
+    #[test]
+    fn test_defer_fn_macro() {
+        let rec = ExecutionRecorder::new();
+        {
+            let first = rec.callback("1st");
+            defer_fn!(first);
+            defer_fn!(rec.callback("2nd"));
+        }
+        rec.assert_order(&["2nd", "1st"]);
+    }
+
+    #[test]
+    fn test_defer_guard_macro() {
+        let rec = ExecutionRecorder::new();
+        let val = Cell::new(0);
+        {
+            let _guards = (
+                defer_guard!(add_to_recorder(format!("x is: {}", val.get()), &rec)),
+                defer_guard!(rec.record("1st")),
+            );
+            val.set(3);
+        }
+        rec.assert_order(&["x is: 0", "1st"]);
+    }
+
+    #[test]
+    fn test_defer_call_arities() {
+        fn record_len(rec: &ExecutionRecorder, s: &str) {
+            rec.record(format!("len: {}", s.len()));
+        }
+        #[allow(clippy::too_many_arguments)]
+        fn sum13(
+            rec: &ExecutionRecorder,
+            a: i32,
+            b: i32,
+            c: i32,
+            d: i32,
+            e: i32,
+            f: i32,
+            g: i32,
+            h: i32,
+            i: i32,
+            j: i32,
+            k: i32,
+            l: i32,
+        ) {
+            let sum = a + b + c + d + e + f + g + h + i + j + k + l;
+            rec.record(format!("sum: {sum}"));
+        }
+        let rec = ExecutionRecorder::new();
+        let nothing = || rec.record("no args");
+        let text = String::from("four");
+        {
+            defer!(nothing());
+            // `&String` is still coerced to `&str`, as with a direct call
+            defer!(record_len(&rec, &text));
+            defer!(sum13(&rec, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12));
+        }
+        rec.assert_order(&["sum: 78", "len: 4", "no args"]);
+    }
+
+    #[test]
+    fn test_unified_defers() {
+        use super::unified_defers;
+
+        #[unified_defers]
+        fn run(rec: &ExecutionRecorder, val: &Cell<i32>) {
+            defer!(add_to_recorder(format!("x is: {}", val.get()), rec));
+            defer_scope!(push: rec.record("pushed"));
+            defer!(priority = 10; rec.record("prioritized"));
+            for i in 0..2 {
+                defer!(move rec.record(format!("iteration #{i}")));
+            }
+            {
+                defer_scope_init!();
+                defer_scope!(rec.record("init"));
+                defer!(rec.record("shared"));
+            }
+            val.set(3);
+        }
+
+        let rec = ExecutionRecorder::new();
+        run(&rec, &Cell::new(0));
+        rec.assert_order(&[
+            "iteration #0",
+            "iteration #1",
+            "shared",
+            "init",
+            "prioritized",
+            "x is: 0",
+            "pushed",
+        ]);
+    }
+
+    #[test]
+    fn test_defer_group_run_in() {
+        let rec = ExecutionRecorder::new();
+        let mut group = DeferGroup::new();
+        let mut queue = |group: &mut DeferGroup<'_>| {
+            group.push(Box::new(rec.callback("1st")));
+            group.add_with_priority(-1, Box::new(rec.callback("2nd")));
+            group.add(Box::new(rec.callback("3rd")));
+            group.push_with_priority(5, Box::new(rec.callback("4th")));
+        };
+        queue(&mut group);
+        group.run_in(Order::Registration);
+        queue(&mut group);
+        group.run_in(Order::Reverse);
+        queue(&mut group);
+        group.run_in(Order::Priority);
+        assert!(group.is_empty());
+        rec.assert_order(&[
+            "1st", "2nd", "3rd", "4th", // Registration
+            "4th", "3rd", "2nd", "1st", // Reverse
+            "4th", "3rd", "1st", "2nd", // Priority
+        ]);
+    }
+
+    #[test]
+    fn test_defer_cancel() {
+        let rec = ExecutionRecorder::new();
+        let captured = std::rc::Rc::new(());
+        {
+            let (captured, rec) = (captured.clone(), rec.clone());
+            let guard = Defer::new(move || {
+                let _captured = captured;
+                rec.record("executed");
+            });
+            guard.cancel();
+        }
+        // The closure was dropped (along with its captures), but not executed
+        assert_eq!(std::rc::Rc::strong_count(&captured), 1);
+        assert!(rec.is_empty());
+    }
+
+    #[test]
+    fn test_defer_run_now() {
+        let rec = ExecutionRecorder::new();
+        {
+            let guard = Defer::new(rec.callback("executed"));
+            rec.record("before");
+            guard.run_now();
+            rec.record("after");
+        }
+        rec.assert_order(&["before", "executed", "after"]);
+    }
+
+    #[test]
+    fn test_defer_replace() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut guard = Defer::boxed(rec.callback("1st"));
+            let previous = guard.replace(Box::new(rec.callback("2nd")));
+            rec.record("replaced");
+            previous();
+        }
+        rec.assert_order(&["replaced", "1st", "2nd"]);
+    }
+
+    // Fails to compile (when instantiated) if `Defer<F>`'s layout differs from `F`'s
+    struct SameLayout<F>(std::marker::PhantomData<F>);
+
+    impl<F: FnOnce()> SameLayout<F> {
+        const ASSERT: () = {
+            assert!(std::mem::size_of::<Defer<F>>() == std::mem::size_of::<F>());
+            assert!(std::mem::align_of::<Defer<F>>() == std::mem::align_of::<F>());
+        };
+    }
+
+    fn assert_same_layout<F: FnOnce()>(guard: Defer<F>) -> Defer<F> {
+        #[allow(clippy::let_unit_value)]
+        let () = SameLayout::<F>::ASSERT;
+        guard
+    }
+
+    #[test]
+    fn test_defer_layout() {
+        let guard = assert_same_layout(Defer::new(|| {}));
+        assert_eq!(std::mem::size_of_val(&guard), 0);
+
+        let (big, aligned) = ([0u8; 100], 0u128);
+        let _guard = assert_same_layout(Defer::new(move || {
+            std::hint::black_box((big, aligned));
+        }));
+        let _guard = assert_same_layout(Defer::new(|| {
+            std::hint::black_box(&big);
+        }));
+        let _guard = assert_same_layout(Defer::with_args(big, |big| {
+            std::hint::black_box(big);
+        }));
+    }
+
+    #[test]
+    fn test_defer_if_alive() {
+        let rec = ExecutionRecorder::new();
+        let owner = std::sync::Arc::new("owner");
+        {
+            let _guard = Defer::if_alive(std::sync::Arc::downgrade(&owner), |owner| {
+                rec.record(*owner);
+            });
+            let dropped = std::sync::Arc::new("dropped");
+            let _guard = Defer::if_alive(std::sync::Arc::downgrade(&dropped), |dropped| {
+                rec.record(*dropped);
+            });
+            drop(dropped);
+        }
+        rec.assert_order(&["owner"]);
+        // The guard doesn't keep the value alive
+        assert_eq!(std::sync::Arc::strong_count(&owner), 1);
+    }
+
+    #[test]
+    fn test_defer_and_then() {
+        let rec = ExecutionRecorder::new();
+        {
+            let _guard = Defer::new(rec.callback("1st"))
+                .and_then(rec.callback("2nd"))
+                .and_then(rec.callback("3rd"));
+        }
+        rec.assert_order(&["1st", "2nd", "3rd"]);
+    }
+
+    #[test]
+    fn test_defer_zip() {
+        let rec = ExecutionRecorder::new();
+        {
+            let second = Defer::new(rec.callback("2nd"));
+            let _guard = Defer::new(rec.callback("1st")).zip(second);
+        }
+        rec.assert_order(&["1st", "2nd"]);
+    }
+
+    #[test]
+    fn test_defer_group_into_fn() {
+        let rec = ExecutionRecorder::new();
+        let mut group = DeferGroup::new();
+        group.push(Box::new(rec.callback("1st")));
+        group.push(Box::new(rec.callback("2nd")));
+
+        let f = group.into_fn();
+        assert!(rec.is_empty());
+        f();
+        rec.assert_order(&["1st", "2nd"]);
+    }
+
+    #[test]
+    fn test_defer_group_priorities() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            group.push(Box::new(rec.callback("0, pushed")));
+            group.add_with_priority(-5, Box::new(rec.callback("-5")));
+            group.add(Box::new(rec.callback("0, added")));
+            group.push_with_priority(10, Box::new(rec.callback("10, pushed")));
+            group.add_with_priority(10, Box::new(rec.callback("10, added")));
+        }
+        rec.assert_order(&["10, added", "10, pushed", "0, added", "0, pushed", "-5"]);
+    }
+
+    #[test]
+    fn test_defer_priorities() {
+        let rec = ExecutionRecorder::new();
+        {
+            defer_scope_init!();
+            defer!(priority = 1; rec.record("1"));
+            defer_scope!(rec.record("0"));
+            let rec_ref = &rec;
+            defer!(priority = 10; move rec_ref.record("10"));
+            defer_scope!(priority = 5; push: rec.record("5"));
+        }
+        rec.assert_order(&["10", "5", "1", "0"]);
+    }
+
+    #[test]
+    fn test_handle_binding_macros() {
+        let rec = ExecutionRecorder::new();
+        let val = Cell::new(0);
+        {
+            defer_scope_init!();
+            defer_scope!(as cancelled; rec.record("cancelled"));
+            defer_scope!(as early; 1: push: add_to_recorder(format!("x is: {}", val.get()), &rec));
+            defer!(as guard; rec.record("guard"));
+            defer!(as eager; add_to_recorder(format!("eager x is: {}", val.get()), &rec));
+            val.set(1);
+            early.run();
+            guard.run_now();
+            cancelled.cancel();
+            eager.cancel();
+            rec.record("end of scope");
+        }
+        rec.assert_order(&["x is: 0", "guard", "end of scope"]);
+    }
+
+    #[test]
+    fn test_defer_with_args() {
+        let rec = ExecutionRecorder::new();
+        let label = Cell::new("now");
+        {
+            let _guard = Defer::with_args(label.get(), |label| rec.record(label));
+            label.set("later");
+        }
+        rec.assert_order(&["now"]);
+    }
+
+    #[test]
+    fn test_defer_boxed() {
+        let rec = ExecutionRecorder::new();
+        {
+            let label = String::from("2nd");
+            let _guards: [BoxDefer; 2] = [
+                Defer::boxed(rec.callback("1st")),
+                Defer::boxed(|| rec.record(label)),
+            ];
+        }
+        rec.assert_order(&["1st", "2nd"]);
+    }
+
+    #[test]
+    fn test_defer_dyn_send() {
+        let rec = ExecutionRecorder::new();
+        let guards: Vec<DynSendDefer> = vec![
+            Defer::boxed_send(rec.callback("1st")),
+            Defer::boxed_send(rec.callback("2nd")),
+        ];
+        std::thread::spawn(move || drop(guards)).join().unwrap();
+        rec.assert_order(&["1st", "2nd"]);
+    }
+
+    #[test]
+    fn test_defer_const_construction() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static RAN: AtomicUsize = AtomicUsize::new(0);
+        fn bump() {
+            RAN.fetch_add(1, Ordering::SeqCst);
+        }
+        const BUMP: Defer<fn()> = Defer::new(bump);
+        const GROUP: DeferGroup<'static> = DeferGroup::new();
+
+        {
+            let _deferred = BUMP;
+            let mut group = GROUP;
+            group.add(Box::new(bump));
+            let _deferred = BUMP;
+        }
+        assert_eq!(RAN.load(Ordering::SeqCst), 3);
+    }
+
     #[test]
     fn test_defer_scoped_macro_execution() {
         let val = Cell::new(0);
@@ -519,57 +3268,164 @@ mod tests {
     }
 
     #[test]
-    fn test_defer_macro_immediate_args_eval() {
+    fn test_defer_group_capacity() {
+        let val = Cell::new(0);
+        {
+            let mut deferred = DeferGroup::with_capacity(4);
+            let capacity = deferred.capacity();
+            assert!(capacity >= 4);
+            for _ in 0..4 {
+                deferred.add(Box::new(|| val.set(val.get() + 1)));
+            }
+            assert_eq!(deferred.capacity(), capacity);
+
+            deferred.reserve(8);
+            assert!(deferred.capacity() >= 12);
+        }
+        assert_eq!(val.get(), 4)
+    }
+
+    #[test]
+    fn test_defer_group_partial_execution() {
+        let buff = RefCell::new(Vec::new());
+        {
+            let mut deferred = DeferGroup::new();
+            for i in 0..6 {
+                let buff = &buff;
+                deferred.push(Box::new(move || buff.borrow_mut().push(i)));
+            }
+
+            deferred.run_first(2);
+            assert_eq!(*buff.borrow(), [0, 1]);
+            deferred.run_range(1..3);
+            assert_eq!(*buff.borrow(), [0, 1, 3, 4]);
+            assert_eq!(deferred.len(), 2);
+            deferred.run_first(10);
+            assert!(deferred.is_empty());
+        }
+        assert_eq!(*buff.borrow(), [0, 1, 3, 4, 2, 5]);
+    }
+
+    #[test]
+    fn test_defer_group_into_iter() {
+        let val = Cell::new(0);
+        let mut deferred = DeferGroup::new();
+        deferred.push(Box::new(|| val.set(1)));
+        deferred.push(Box::new(|| val.set(2)));
+
+        let mut closures = deferred.into_iter();
+        assert_eq!(val.get(), 0);
+        closures.next_back().unwrap()();
+        assert_eq!(val.get(), 2);
+        drop(closures);
+        assert_eq!(val.get(), 2);
+    }
+
+    #[test]
+    fn test_defer_group_strategies() {
+        let buff = RefCell::new(Vec::new());
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut always = DeferGroup::new();
+            always.add(Box::new(|| buff.borrow_mut().push("always, unwinding")));
+            let mut on_success = DeferGroup::on_success();
+            on_success.add(Box::new(|| buff.borrow_mut().push("on_success, unwinding")));
+            let mut on_unwind = DeferGroup::on_unwind();
+            on_unwind.add(Box::new(|| buff.borrow_mut().push("on_unwind, unwinding")));
+            panic!();
+        }));
+        assert!(res.is_err());
+
+        {
+            let mut always = DeferGroup::new();
+            always.add(Box::new(|| buff.borrow_mut().push("always")));
+            let mut on_success = DeferGroup::on_success();
+            on_success.add(Box::new(|| buff.borrow_mut().push("on_success")));
+            let mut on_unwind = DeferGroup::on_unwind();
+            on_unwind.add(Box::new(|| buff.borrow_mut().push("on_unwind")));
+        }
+
+        assert_eq!(
+            *buff.borrow(),
+            [
+                "on_unwind, unwinding",
+                "always, unwinding",
+                "on_success",
+                "always"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_defer_group_savepoints() {
         let buff = RefCell::new(Vec::new());
-        let buff2 = RefCell::new(Vec::new());
+        {
+            let mut deferred = DeferGroup::new();
+            let buff = &buff;
+            let push = |i| Box::new(move || buff.borrow_mut().push(i));
+
+            deferred.push(push(0));
+            let first = deferred.savepoint();
+            deferred.add(push(1));
+            let second = deferred.savepoint();
+            deferred.push(push(2));
+            deferred.add(push(3));
+
+            deferred.run_since(second);
+            assert_eq!(*buff.borrow(), [3, 2]);
+
+            deferred.push(push(4));
+            deferred.rollback_to(first);
+            assert_eq!(deferred.len(), 1);
+        }
+        assert_eq!(*buff.borrow(), [3, 2, 0]);
+    }
+
+    #[test]
+    fn test_defer_macro_immediate_args_eval() {
+        let rec = ExecutionRecorder::new();
+        let rec2 = ExecutionRecorder::new();
         let val = Cell::new(0);
 
         defer! {
-            let res = b"x is: 3\n";
-            assert_eq!(*buff.borrow(), res.to_vec());
-
-            let res = b"x is: 0\n";
-            assert_eq!(*buff2.borrow(), res.to_vec());
+            rec.assert_order(&["x is: 3"]);
+            rec2.assert_order(&["x is: 0"]);
         };
 
         // This will evaluate the arguments passed to the invoked at function at call time (deferred-to time, later), results in `3`
         defer!(
-            add_to_buffer(
+            add_to_recorder(
                 format!("x is: {}", val.get()),
-                &buff
+                &rec
             );
         );
 
         // This will evaluate the arguments passed to the invoked at function at time of macro invocation (now), results in `0`
-        defer!(add_to_buffer(format!("x is: {}", val.get()), &buff2));
+        defer!(add_to_recorder(format!("x is: {}", val.get()), &rec2));
         val.set(3);
     }
 
     #[test]
     fn test_defer_scope_macro_immediate_args_eval() {
-        let buff = RefCell::new(Vec::new());
-        let buff2 = RefCell::new(Vec::new());
+        let rec = ExecutionRecorder::new();
+        let rec2 = ExecutionRecorder::new();
         let val = Cell::new(0);
         defer_scope_init!();
         defer_scope! {
-            let res = b"x is: 3\n";
-            assert_eq!(*buff.borrow(), res.to_vec());
-
-            let res = b"x is: 0\n";
-            assert_eq!(*buff2.borrow(), res.to_vec());
+            rec.assert_order(&["x is: 3"]);
+            rec2.assert_order(&["x is: 0"]);
         };
 
         // This will evaluate the arguments passed to the invoked at function at call time (deferred-to time, later), results in `3`
         defer_scope!(
-            add_to_buffer(
+            add_to_recorder(
                 format!("x is: {}", val.get()),
-                &buff
+                &rec
             );
         );
 
         // This will evaluate the arguments passed to the invoked at function at time of macro invocation (now), results in `0`
-        defer_scope!(add_to_buffer(format!("x is: {}", val.get()), &buff2));
+        defer_scope!(add_to_recorder(format!("x is: {}", val.get()), &rec2));
         val.set(3);
     }
 }
-    
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::ops::{Deref, DerefMut};
+
+/// A lock guard (e.g. a [`MutexGuard`](std::sync::MutexGuard)) paired with a closure that is executed strictly after the lock is released.
+///
+/// Deferred closures registered while a lock is held (e.g. using [`defer!`](crate::defer)) are usually dropped in reverse order
+/// of declaration, which makes it easy to accidentally do slow work while still holding the lock.
+/// `UnlockThen` releases the lock (drops the guard) first, then executes the closure, when it goes out of scope.
+///
+/// `UnlockThen` dereferences to the guarded value, so it can be used in place of the lock guard.
+///
+/// **Note: `UnlockThen` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, releasing the lock and executing the enclosed closure!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::unlock_then;
+/// use std::sync::Mutex;
+///
+/// let queue = Mutex::new(vec![1, 2, 3]);
+/// {
+///     let mut queue = unlock_then(queue.lock().unwrap(), || {
+///         println!("Notifying the consumers (without holding the lock)...");
+///     });
+///     queue.push(4);
+/// }
+/// assert_eq!(queue.lock().unwrap().len(), 4);
+/// ```
+///
+/// See also: [`unlock_then`], and [`Defer`](crate::Defer).
+#[must_use = "UnlockThen MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, releasing the lock and executing the enclosed closure!"]
+pub struct UnlockThen<G, F: FnOnce()> {
+    guard: Option<G>,
+    deferred: Option<F>,
+}
+
+/// Pairs a lock guard with a closure executed strictly after the lock is released, see [`UnlockThen`].
+///
+/// **Note: `UnlockThen` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, releasing the lock and executing the enclosed closure!**
+pub fn unlock_then<G, F: FnOnce()>(guard: G, deferred: F) -> UnlockThen<G, F> {
+    UnlockThen {
+        guard: Some(guard),
+        deferred: Some(deferred),
+    }
+}
+
+impl<G: Deref, F: FnOnce()> Deref for UnlockThen<G, F> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        // `self.guard` is only `None` once the `UnlockThen` is dropped
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<G: DerefMut, F: FnOnce()> DerefMut for UnlockThen<G, F> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<G, F: FnOnce()> Drop for UnlockThen<G, F> {
+    fn drop(&mut self) {
+        // Release the lock first (even when cleanup is skipped, which only applies to the user's closure), then run the closure
+        drop(self.guard.take());
+        if let Some(deferred) = self.deferred.take() {
+            if crate::debug::skipped(|| {
+                format!("deferred closure `{}`", std::any::type_name::<F>())
+            }) {
+                return;
+            }
+            let _span = crate::hooks::executing::<F>("UnlockThen");
+            deferred();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_unlock_then_runs_after_unlock() {
+        let lock = Mutex::new(0);
+        let was_locked = std::cell::Cell::new(true);
+        {
+            let mut guard = unlock_then(lock.lock().unwrap(), || {
+                was_locked.set(lock.try_lock().is_err())
+            });
+            *guard += 1;
+        }
+        assert!(!was_locked.get());
+        assert_eq!(*lock.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,109 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::DeferGroup;
+
+/// A [`DeferGroup`] nested in an enclosing group (its parent), allowing closures to be queued on the parent while it's in scope.
+///
+/// This is what [`defer_scope_init!(nested)`](crate::defer_scope_init) creates, it borrows the enclosing group (mutably) for
+/// as long as it's in scope, and is what lets [`defer_scope!`](crate::defer_scope) target an enclosing group by depth
+/// (e.g. `defer_scope!(2: ...)`), the parent of a `NestedDeferGroup` is reached using [`NestedDeferGroup::parent`].
+///
+/// The `NestedDeferGroup` dereferences to its own [`DeferGroup`], whose closures are executed when the `NestedDeferGroup` goes out of scope.
+///
+/// **Note: `NestedDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{DeferGroup, NestedDeferGroup};
+///
+/// let mut outer = DeferGroup::new();
+/// {
+///     let mut inner = NestedDeferGroup::new(&mut outer);
+///     inner.add(Box::new(|| println!("Executed when `inner` goes out of scope.")));
+///     inner.parent().add(Box::new(|| println!("Executed when `outer` goes out of scope.")));
+/// }
+/// ```
+///
+/// See also: [`defer_scope!`](crate::defer_scope), and [`DeferGroup`].
+#[must_use = "NestedDeferGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct NestedDeferGroup<'p, 'a, P> {
+    group: DeferGroup<'a>,
+    parent: &'p mut P,
+}
+
+impl<'p, 'a, P> NestedDeferGroup<'p, 'a, P> {
+    /// Creates a new, empty `NestedDeferGroup`, nested in `parent` (usually a [`DeferGroup`], or another `NestedDeferGroup`).
+    ///
+    /// **Note: `NestedDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn new(parent: &'p mut P) -> Self {
+        Self {
+            group: DeferGroup::new(),
+            parent,
+        }
+    }
+
+    /// Returns the enclosing group.
+    pub fn parent(&mut self) -> &mut P {
+        self.parent
+    }
+}
+
+impl<'p, 'a, P> Deref for NestedDeferGroup<'p, 'a, P> {
+    type Target = DeferGroup<'a>;
+
+    fn deref(&self) -> &DeferGroup<'a> {
+        &self.group
+    }
+}
+
+impl<'p, 'a, P> DerefMut for NestedDeferGroup<'p, 'a, P> {
+    fn deref_mut(&mut self) -> &mut DeferGroup<'a> {
+        &mut self.group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::ExecutionRecorder;
+    use crate::{defer_scope, defer_scope_init};
+
+    #[test]
+    fn test_defer_scope_depth() {
+        let rec = ExecutionRecorder::new();
+        {
+            defer_scope_init!();
+            {
+                defer_scope_init!(nested);
+                {
+                    defer_scope_init!(nested);
+                    defer_scope!(3: rec.record("outermost"));
+                    defer_scope!(2: rec.record("middle"));
+                    defer_scope!(1: rec.record("innermost"));
+                    defer_scope!(rec.record("innermost, implicitly"));
+                }
+                rec.assert_order(&["innermost, implicitly", "innermost"]);
+            }
+            rec.assert_contains("middle");
+            rec.assert_not_contains("outermost");
+        }
+        rec.assert_order(&["innermost, implicitly", "innermost", "middle", "outermost"]);
+    }
+
+    #[test]
+    fn test_defer_scope_push() {
+        let rec = ExecutionRecorder::new();
+        {
+            defer_scope_init!();
+            defer_scope!(push: rec.record("1st"));
+            let rec_ref = &rec;
+            defer_scope!(push: move rec_ref.record("2nd"));
+            defer_scope!(rec.record("0th"));
+            {
+                defer_scope_init!(nested);
+                defer_scope!(2: push: rec.record("3rd"));
+            }
+        }
+        rec.assert_order(&["0th", "1st", "2nd", "3rd"]);
+    }
+}
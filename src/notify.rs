@@ -0,0 +1,133 @@
+use std::sync::mpsc::{Sender, SyncSender};
+
+use crate::future::Trigger;
+
+/// A channel (or any other sink) a [`NotifyOnDrop`] guard sends its value on.
+///
+/// It's implemented by the std channels' senders (send errors are ignored, as a disconnected receiver has nobody to notify),
+/// by [`Trigger`], and by closures taking the value, e.g. `|phase| { let _ = tx.send(phase); }` for a tokio `oneshot`, `watch`, or `mpsc` sender.
+pub trait Notifier<T> {
+    /// Sends `value`, consuming the notifier.
+    fn notify(self, value: T);
+}
+
+impl<T> Notifier<T> for Sender<T> {
+    fn notify(self, value: T) {
+        let _ = self.send(value);
+    }
+}
+
+// Blocks until there's room in the channel, like any other send on a bounded channel
+impl<T> Notifier<T> for SyncSender<T> {
+    fn notify(self, value: T) {
+        let _ = self.send(value);
+    }
+}
+
+impl Notifier<()> for Trigger {
+    fn notify(self, (): ()) {
+        self.fire();
+    }
+}
+
+impl<T, F: FnOnce(T)> Notifier<T> for F {
+    fn notify(self, value: T) {
+        self(value);
+    }
+}
+
+/// A guard sending a value on a channel when it goes out of scope (including when unwinding), so other threads (or tasks)
+/// learn that a phase is over without polling.
+///
+/// The value can be updated while the guard is held (e.g. to report how far the phase got), using [`NotifyOnDrop::set`].
+///
+/// **Note: `NotifyOnDrop` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, sending the value!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::NotifyOnDrop;
+/// use std::sync::mpsc;
+///
+/// #[derive(Debug, PartialEq)]
+/// enum Phase {
+///     Started,
+///     Migrated,
+/// }
+///
+/// let (tx, rx) = mpsc::channel();
+/// std::thread::spawn(move || {
+///     let mut done = NotifyOnDrop::new(tx, Phase::Started);
+///     // ... run the migrations, panicking on failure ...
+///     done.set(Phase::Migrated);
+/// });
+/// assert_eq!(rx.recv(), Ok(Phase::Migrated));
+/// ```
+///
+/// See also: [`Trigger`], and [`Defer`](crate::Defer).
+#[must_use = "NotifyOnDrop MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, sending the value!"]
+pub struct NotifyOnDrop<T, N: Notifier<T>> {
+    notifier: Option<N>,
+    value: Option<T>,
+}
+
+impl<T, N: Notifier<T>> NotifyOnDrop<T, N> {
+    /// Creates a new `NotifyOnDrop` instance, sending `value` using `notifier` when it goes out of scope.
+    ///
+    /// **Note: `NotifyOnDrop` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, sending the value!**
+    pub fn new(notifier: N, value: T) -> Self {
+        Self {
+            notifier: Some(notifier),
+            value: Some(value),
+        }
+    }
+
+    /// Replaces the value sent when the guard goes out of scope.
+    pub fn set(&mut self, value: T) {
+        self.value = Some(value);
+    }
+
+    /// Consumes the guard, dropping the notifier without sending the value.
+    pub fn cancel(mut self) {
+        self.notifier = None;
+    }
+}
+
+impl<T, N: Notifier<T>> Drop for NotifyOnDrop<T, N> {
+    fn drop(&mut self) {
+        let (Some(notifier), Some(value)) = (self.notifier.take(), self.value.take()) else {
+            return;
+        };
+        // The receiver waits for the notification, so it's sent even while cleanup is skipped
+        let _span = crate::hooks::executing::<N>("NotifyOnDrop");
+        notifier.notify(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_notify_on_drop() {
+        let (tx, rx) = mpsc::channel();
+        let result = std::thread::spawn({
+            let tx = tx.clone();
+            move || {
+                let _done = NotifyOnDrop::new(tx, "panicked");
+                panic!("phase failed");
+            }
+        })
+        .join();
+        assert!(result.is_err());
+        assert_eq!(rx.try_recv(), Ok("panicked"));
+
+        NotifyOnDrop::new(tx, "cancelled").cancel();
+        assert!(rx.try_recv().is_err());
+
+        let fired = Trigger::new();
+        drop(NotifyOnDrop::new(fired.clone(), ()));
+        assert!(fired.is_fired());
+    }
+}
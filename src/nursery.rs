@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, Scope, ScopedJoinHandle};
+
+use crate::SyncDeferGroup;
+
+/// A structured-concurrency scope, owning the threads spawned in it and a group of deferred cleanups, see [`nursery`].
+///
+/// Threads can't be aborted, so cancellation is cooperative: once [`Nursery::cancel`] is called (or a thread panics),
+/// [`Nursery::is_cancelled`] returns `true`, and the remaining threads are expected to wind down.
+///
+/// `Nursery` is a (cloneable) handle, a clone can be moved into the threads spawned in it.
+#[derive(Clone)]
+pub struct Nursery<'scope, 'env: 'scope> {
+    scope: &'scope Scope<'scope, 'env>,
+    state: Arc<State<'env>>,
+}
+
+// The cleanups are executed when the last handle is dropped, i.e. once all the threads are joined
+struct State<'env> {
+    group: SyncDeferGroup<'env>,
+    cancelled: AtomicBool,
+}
+
+impl<'scope, 'env> Nursery<'scope, 'env> {
+    /// Spawns a thread owned by the nursery, joined before the nursery's cleanups are executed.
+    ///
+    /// If the thread panics, the nursery is cancelled, and the panic is propagated once all the threads are joined
+    /// (unless it's observed by joining the returned handle explicitly), see [`std::thread::scope`].
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let state = self.state.clone();
+        self.scope.spawn(move || {
+            let _cancel_on_panic = CancelOnPanic(state);
+            f()
+        })
+    }
+
+    /// Adds a cleanup to the start (0-index) of the nursery's queue, executed once all the threads are joined.
+    pub fn add(&self, f: Box<dyn FnOnce() + Send + 'env>) {
+        self.state.group.add(f);
+    }
+
+    /// Pushes a cleanup to the end of the nursery's queue, executed once all the threads are joined.
+    pub fn push(&self, f: Box<dyn FnOnce() + Send + 'env>) {
+        self.state.group.push(f);
+    }
+
+    /// Cancels the nursery, letting its threads know they should wind down.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if the nursery was cancelled, or one of its threads panicked.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+}
+
+// Cancels the nursery if the thread panics, this is the nursery's own bookkeeping (not a user cleanup), so it's never skipped
+struct CancelOnPanic<'env>(Arc<State<'env>>);
+
+impl Drop for CancelOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.cancelled.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// Runs `f` with a [`Nursery`], in which threads can be spawned and cleanups deferred, guaranteeing that all the threads are joined
+/// before the cleanups are executed (first to last).
+///
+/// This is the usual "spawn workers, then clean up the resources they share" pattern, without the possibility of a cleanup
+/// racing a worker that's still running. The cleanups are executed even if a thread (or `f`) panics, the panic is then propagated.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::nursery;
+/// use std::sync::Mutex;
+///
+/// let results = Mutex::new(Vec::new());
+/// nursery(|n| {
+///     n.add(Box::new(|| println!("Removing the scratch directory...")));
+///     for shard in 0..4 {
+///         let (worker, results) = (n.clone(), &results);
+///         n.spawn(move || {
+///             if worker.is_cancelled() {
+///                 return;
+///             }
+///             results.lock().unwrap().push(shard);
+///         });
+///     }
+/// });
+/// assert_eq!(results.lock().unwrap().len(), 4);
+/// ```
+///
+/// See also: [`sync_scope`](crate::sync_scope), and [`SyncDeferGroup`].
+pub fn nursery<'env, T>(f: impl for<'scope> FnOnce(&Nursery<'scope, 'env>) -> T) -> T {
+    // Dropped after the scope returns (or unwinds), i.e. after all the threads are joined
+    let state = Arc::new(State {
+        group: SyncDeferGroup::new(),
+        cancelled: AtomicBool::new(false),
+    });
+    thread::scope(|scope| {
+        f(&Nursery {
+            scope,
+            state: state.clone(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::time::Duration;
+
+    #[test]
+    fn test_nursery_joins_before_cleanup() {
+        let rec = ExecutionRecorder::new();
+        let rec = &rec;
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            nursery(|n| {
+                n.add(Box::new(rec.callback("cleanup")));
+                let worker = n.clone();
+                n.spawn(move || {
+                    while !worker.is_cancelled() {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    rec.record("cancelled worker");
+                });
+                n.spawn(|| panic!("worker failed"));
+            })
+        }));
+        assert!(res.is_err());
+        rec.assert_order(&["cancelled worker", "cleanup"]);
+    }
+}
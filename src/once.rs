@@ -0,0 +1,193 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::Defer;
+
+/// A cleanup executed at most once, no matter how many of its (cloned) handles reach it.
+///
+/// When the same logical cleanup may be registered from several places (e.g. in a [`DeferGroup`](crate::DeferGroup), and in the
+/// [registry](crate::registry)), or triggered manually and also deferred, each path gets its own clone (or [callback](RunOnce::callback)),
+/// and only the first one to reach the cleanup executes it, the others do nothing.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{registry, RunOnce};
+///
+/// let close = RunOnce::new(|| println!("Closing the connection..."));
+/// // Executed at shutdown, unless it's executed before
+/// registry::register(close.callback());
+/// {
+///     let _close = close.guard();
+///     // ... use the connection ...
+/// }
+/// // "Closing the connection..." was printed once, when `_close` was dropped
+/// assert!(close.has_run());
+/// registry::run_all();
+/// ```
+pub struct RunOnce<F: FnOnce()>(Arc<Mutex<Option<F>>>);
+
+impl<F: FnOnce()> RunOnce<F> {
+    /// Creates a new `RunOnce`, executing `f` the first time it's reached.
+    pub fn new(f: F) -> Self {
+        Self(Arc::new(Mutex::new(Some(f))))
+    }
+
+    /// Executes the cleanup, unless it was already executed (or is executing), returning `true` if this call executed it.
+    pub fn run(&self) -> bool {
+        take_and_run(&self.0, "RunOnce")
+    }
+
+    /// Drops the cleanup without executing it, unless it was already executed (or is executing), returning `true` if this call dropped it.
+    ///
+    /// Once cancelled, the cleanup is considered run, none of the handles execute it.
+    pub fn cancel(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .is_some()
+    }
+
+    /// Returns `true` if the cleanup was already executed (or is executing).
+    pub fn has_run(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none()
+    }
+
+    /// Returns a closure executing the cleanup (unless it was already executed), to be registered wherever a closure is expected.
+    pub fn callback(&self) -> impl FnOnce() {
+        let once = self.clone();
+        move || {
+            once.run();
+        }
+    }
+
+    /// Returns a [`Defer`] executing the cleanup (unless it was already executed) when it goes out of scope.
+    ///
+    /// **Note: `Defer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub fn guard(&self) -> Defer<impl FnOnce()> {
+        Defer::new(self.callback())
+    }
+}
+
+// The lock isn't held while executing the cleanup, a concurrent call returns immediately
+fn take_and_run<F: FnOnce()>(f: &Mutex<Option<F>>, name: &'static str) -> bool {
+    let f = f.lock().unwrap_or_else(PoisonError::into_inner).take();
+    match f {
+        Some(f) => {
+            if !crate::debug::skipped(|| {
+                format!("run-once cleanup `{}`", std::any::type_name::<F>())
+            }) {
+                let _span = crate::hooks::executing::<F>(name);
+                f();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+impl<F: FnOnce()> Clone for RunOnce<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// A guard whose cleanup can be [fired](OnceDefer::fire) early, and is otherwise executed when it goes out of scope, at most once either way.
+///
+/// This suits cleanups that should happen as early as possible, but must happen eventually: the cleanup is fired as soon
+/// as it's possible (e.g. once a lock is no longer needed), and if an early return or a panic skips that, it's executed on drop instead.
+/// Firing only needs a shared reference, so it can be done from any thread holding one.
+///
+/// **Note: `OnceDefer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::OnceDefer;
+///
+/// # fn flush() -> Result<(), ()> { Ok(()) }
+/// let unlock = OnceDefer::new(|| println!("Releasing the lock..."));
+/// flush()?;
+/// // "Releasing the lock..." is printed here, rather than at the end of the scope (or on the early return above)
+/// unlock.fire();
+/// assert!(unlock.has_fired());
+/// // ... more work not needing the lock ...
+/// # Ok::<(), ()>(())
+/// ```
+#[must_use = "OnceDefer MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct OnceDefer<F: FnOnce()>(Mutex<Option<F>>);
+
+impl<F: FnOnce()> OnceDefer<F> {
+    /// Creates a new `OnceDefer`, executing `f` when it's fired, or goes out of scope, whichever comes first.
+    ///
+    /// **Note: `OnceDefer` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!**
+    pub const fn new(f: F) -> Self {
+        Self(Mutex::new(Some(f)))
+    }
+
+    /// Executes the cleanup now, unless it was already executed (or is executing), returning `true` if this call executed it.
+    pub fn fire(&self) -> bool {
+        take_and_run(&self.0, "OnceDefer")
+    }
+
+    /// Returns `true` if the cleanup was already fired (or is executing).
+    pub fn has_fired(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none()
+    }
+}
+
+impl<F: FnOnce()> Drop for OnceDefer<F> {
+    fn drop(&mut self) {
+        take_and_run(&self.0, "OnceDefer");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+    use crate::DeferGroup;
+
+    #[test]
+    fn test_run_once() {
+        let rec = ExecutionRecorder::new();
+        let once = RunOnce::new(rec.callback("cleanup"));
+        {
+            let mut group = DeferGroup::new();
+            group.add(Box::new(once.callback()));
+            let _guard = once.guard();
+            assert!(!once.has_run());
+        }
+        assert!(once.has_run());
+        assert!(!once.run());
+        assert!(!once.cancel());
+
+        let cancelled = RunOnce::new(rec.callback("cancelled"));
+        let _guard = cancelled.guard();
+        assert!(cancelled.cancel());
+        assert!(cancelled.has_run());
+        rec.assert_order(&["cleanup"]);
+    }
+
+    #[test]
+    fn test_once_defer() {
+        let rec = ExecutionRecorder::new();
+        {
+            let fired = OnceDefer::new(rec.callback("fired"));
+            let _dropped = OnceDefer::new(rec.callback("dropped"));
+            std::thread::scope(|s| {
+                s.spawn(|| assert!(fired.fire()));
+            });
+            assert!(fired.has_fired());
+            assert!(!fired.fire());
+            rec.record("end of scope");
+        }
+        rec.assert_order(&["fired", "end of scope", "dropped"]);
+    }
+}
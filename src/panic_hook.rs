@@ -0,0 +1,116 @@
+use std::panic::{self, PanicHookInfo};
+use std::sync::Arc;
+
+type Hook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// A guard installing a panic hook, and restoring the previous one when it goes out of scope.
+///
+/// The panic hook is process-wide, `PanicHookGuard` lets a test (or a subsystem) customize panic handling temporarily,
+/// without leaking its hook to the rest of the process. As hooks are restored when the guards go out of scope,
+/// guards must be dropped in reverse order of creation (i.e. be scoped), installing a hook through another mechanism in the
+/// meantime overrides it, and it's replaced by the previous hook once the guard is dropped.
+///
+/// **Note: `PanicHookGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the previous hook!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::PanicHookGuard;
+///
+/// {
+///     // Expected panics are silenced
+///     let _quiet = PanicHookGuard::set(|_| {});
+///     assert!(std::panic::catch_unwind(|| panic!("expected")).is_err());
+/// }
+/// // The previous hook (the default one, here) is restored
+/// ```
+#[must_use = "PanicHookGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the previous hook!"]
+pub struct PanicHookGuard {
+    previous: Option<Arc<Hook>>,
+}
+
+impl PanicHookGuard {
+    /// Installs `hook` as the panic hook, until the guard goes out of scope.
+    ///
+    /// **Note: `PanicHookGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the previous hook!**
+    pub fn set(hook: impl Fn(&PanicHookInfo<'_>) + Sync + Send + 'static) -> Self {
+        let previous = Arc::new(panic::take_hook());
+        panic::set_hook(Box::new(hook));
+        Self {
+            previous: Some(previous),
+        }
+    }
+
+    /// Installs `hook` as the panic hook, followed by the previous hook (e.g. restoring the terminal before the panic message is printed),
+    /// until the guard goes out of scope.
+    ///
+    /// **Note: `PanicHookGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the previous hook!**
+    pub fn chain(hook: impl Fn(&PanicHookInfo<'_>) + Sync + Send + 'static) -> Self {
+        let previous = Arc::new(panic::take_hook());
+        let chained = previous.clone();
+        panic::set_hook(Box::new(move |info| {
+            hook(info);
+            chained(info);
+        }));
+        Self {
+            previous: Some(previous),
+        }
+    }
+}
+
+impl Drop for PanicHookGuard {
+    fn drop(&mut self) {
+        let Some(previous) = self.previous.take() else {
+            return;
+        };
+        // Restoring the previous hook is never skipped, the temporary hook would otherwise stay installed for the whole process.
+        // `set_hook` panics if called while panicking, the current hook is left installed in that case
+        if !std::thread::panicking() {
+            panic::set_hook(Box::new(move |info| previous(info)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+    use std::thread;
+
+    fn panic_in_thread(name: &str) {
+        let panicked = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(|| panic!("expected"))
+            .unwrap()
+            .join();
+        assert!(panicked.is_err());
+    }
+
+    #[test]
+    fn test_panic_hook_guard() {
+        let rec = ExecutionRecorder::new();
+        // Other tests may panic concurrently, only this test's threads are recorded
+        let hook = |rec: ExecutionRecorder, label: &'static str| {
+            move |_: &PanicHookInfo<'_>| {
+                if let Some(name) = thread::current().name() {
+                    if name.starts_with("panic-hook-test") {
+                        rec.record(format!("{label} {name}"));
+                    }
+                }
+            }
+        };
+        {
+            let _outer = PanicHookGuard::set(hook(rec.clone(), "outer"));
+            {
+                let _inner = PanicHookGuard::chain(hook(rec.clone(), "inner"));
+                panic_in_thread("panic-hook-test 1");
+            }
+            panic_in_thread("panic-hook-test 2");
+        }
+        rec.assert_order(&[
+            "inner panic-hook-test 1",
+            "outer panic-hook-test 1",
+            "outer panic-hook-test 2",
+        ]);
+    }
+}
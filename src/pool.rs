@@ -0,0 +1,119 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, PoisonError};
+
+/// A pool items can be checked back into, see [`PoolGuard`].
+///
+/// It's implemented by closures taking the item (e.g. `|conn| pool.release(conn)`), and by `&Mutex<Vec<T>>`, the simplest object pool.
+pub trait Pool<T> {
+    /// Checks `item` back into the pool.
+    fn check_in(&mut self, item: T);
+}
+
+impl<T, F: FnMut(T)> Pool<T> for F {
+    fn check_in(&mut self, item: T) {
+        self(item);
+    }
+}
+
+impl<T> Pool<T> for &Mutex<Vec<T>> {
+    fn check_in(&mut self, item: T) {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(item);
+    }
+}
+
+/// A guard holding an item checked out of a pool, which checks it back into the pool when it goes out of scope.
+///
+/// `PoolGuard` dereferences to the item, so it can be used in place of the item while it's held,
+/// and [`PoolGuard::take`] takes the item out of the guard, so it's never returned to the pool (e.g. a broken connection).
+///
+/// **Note: `PoolGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, returning the item to the pool!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::PoolGuard;
+/// use std::sync::Mutex;
+///
+/// let buffers = Mutex::new(vec![Vec::<u8>::with_capacity(4096)]);
+/// {
+///     let buffer = buffers.lock().unwrap().pop().unwrap_or_default();
+///     let mut buffer = PoolGuard::new(buffer, &buffers);
+///     buffer.extend_from_slice(b"response");
+///     // ... write the response ...
+///     buffer.clear();
+/// }
+/// assert_eq!(buffers.lock().unwrap().len(), 1);
+/// ```
+///
+/// See also: [`Defer`](crate::Defer), and [`UnlockThen`](crate::UnlockThen).
+#[must_use = "PoolGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, returning the item to the pool!"]
+pub struct PoolGuard<T, P: Pool<T>> {
+    item: Option<T>,
+    pool: P,
+}
+
+impl<T, P: Pool<T>> PoolGuard<T, P> {
+    /// Creates a new `PoolGuard`, checking `item` back into `pool` when it goes out of scope.
+    ///
+    /// **Note: `PoolGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, returning the item to the pool!**
+    pub fn new(item: T, pool: P) -> Self {
+        Self {
+            item: Some(item),
+            pool,
+        }
+    }
+
+    /// Takes the item out of the guard, it won't be checked back into the pool.
+    pub fn take(mut self) -> T {
+        // `self.item` is only `None` once the `PoolGuard` is taken or dropped
+        self.item.take().unwrap()
+    }
+}
+
+impl<T, P: Pool<T>> Deref for PoolGuard<T, P> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item.as_ref().unwrap()
+    }
+}
+
+impl<T, P: Pool<T>> DerefMut for PoolGuard<T, P> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().unwrap()
+    }
+}
+
+impl<T, P: Pool<T>> Drop for PoolGuard<T, P> {
+    fn drop(&mut self) {
+        let Some(item) = self.item.take() else {
+            return;
+        };
+        // Skipping the check-in would leak the item, slowly exhausting the pool, so it's never skipped
+        let _span = crate::hooks::executing::<P>("PoolGuard");
+        self.pool.check_in(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_guard() {
+        let pool = Mutex::new(vec![1, 2]);
+        {
+            let mut first = PoolGuard::new(pool.lock().unwrap().pop().unwrap(), &pool);
+            *first += 10;
+            let second = PoolGuard::new(pool.lock().unwrap().pop().unwrap(), &pool);
+            assert_eq!(second.take(), 1);
+        }
+        assert_eq!(*pool.lock().unwrap(), [12]);
+
+        let mut returned = Vec::new();
+        drop(PoolGuard::new("conn", |conn| returned.push(conn)));
+        assert_eq!(returned, ["conn"]);
+    }
+}
@@ -0,0 +1,172 @@
+#[cfg(feature = "arena")]
+use crate::ArenaDeferGroup;
+use crate::{
+    testing::TeardownGroup, DeferContext, DeferGroup, DeferScope, NestedDeferGroup, SendDeferGroup,
+    SyncDeferGroup,
+};
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+/// A sink deferred cleanups can be registered to, so APIs can stay agnostic about where their cleanups end up.
+///
+/// It's implemented by the crate's groups (e.g. [`DeferGroup`], [`SyncDeferGroup`], or [`DeferScope`]), and by [`Registry`](crate::registry::Registry)
+/// (the process-wide [`registry`](crate::registry), which only accepts `'static` closures). Registered closures are executed before
+/// the closures registered earlier (like [`DeferGroup::add`]), and must be `Send`, so they can be registered to any sink.
+///
+/// A thread-local group (a `thread_local!` `RefCell<DeferGroup<'static>>`) is a sink as well, its closures being executed when the
+/// thread exits (or never, for the main thread, whose thread-locals aren't guaranteed to be destroyed). There is no async group or
+/// task-local sink: task-locals belong to the async runtime (which this crate doesn't depend on), and a cleanup that has to be awaited
+/// is better spawned using [`AsyncDefer`](crate::future::AsyncDefer).
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{registry::Registry, DeferGroup, DeferRegistrar};
+///
+/// fn create_temp_file(cleanup: &mut impl DeferRegistrar<'static>) -> std::path::PathBuf {
+///     let path = std::env::temp_dir().join("defer-rs-registrar");
+///     std::fs::write(&path, "").unwrap();
+///
+///     let removed = path.clone();
+///     cleanup.register(Box::new(move || std::fs::remove_file(removed).unwrap()));
+///     path
+/// }
+///
+/// {
+///     let mut group = DeferGroup::new();
+///     let path = create_temp_file(&mut group);
+///     assert!(path.exists());
+/// }
+///
+/// // Removed once `registry::run_all` is invoked instead
+/// let path = create_temp_file(&mut Registry);
+/// # defer_rs::registry::run_all();
+/// ```
+pub trait DeferRegistrar<'a> {
+    /// Registers a deferred closure, executed before the closures registered earlier.
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>);
+}
+
+impl<'a> DeferRegistrar<'a> for DeferGroup<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'p, 'a, P> DeferRegistrar<'a> for NestedDeferGroup<'p, 'a, P> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for SendDeferGroup<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for SyncDeferGroup<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+// Shared handles can register through a shared reference as well
+impl<'a> DeferRegistrar<'a> for &SyncDeferGroup<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for DeferScope<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for DeferContext<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for &DeferScope<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for &DeferContext<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+impl<'a> DeferRegistrar<'a> for TeardownGroup<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+#[cfg(feature = "arena")]
+impl<'a> DeferRegistrar<'a> for ArenaDeferGroup<'a> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        self.add(f);
+    }
+}
+
+// Panics (like `LocalKey::with`) when the thread-local was already destroyed, e.g. when registering from a thread-local's destructor
+impl DeferRegistrar<'static> for &'static LocalKey<RefCell<DeferGroup<'static>>> {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'static>) {
+        self.with(|group| group.borrow_mut().add(f));
+    }
+}
+
+impl<'a, R: DeferRegistrar<'a> + ?Sized> DeferRegistrar<'a> for &mut R {
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        (**self).register(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+
+    fn register_both(cleanup: &mut impl DeferRegistrar<'static>, rec: &ExecutionRecorder) {
+        cleanup.register(Box::new(rec.callback("registered 1st")));
+        cleanup.register(Box::new(rec.callback("registered 2nd")));
+    }
+
+    #[test]
+    fn test_defer_registrar() {
+        let rec = ExecutionRecorder::new();
+        {
+            let mut group = DeferGroup::new();
+            register_both(&mut group, &rec);
+            let group = SyncDeferGroup::new();
+            register_both(&mut &group, &rec);
+        }
+        rec.assert_order(&[
+            "registered 2nd",
+            "registered 1st",
+            "registered 2nd",
+            "registered 1st",
+        ]);
+    }
+    #[test]
+    fn test_thread_local_registrar() {
+        thread_local! {
+            static CLEANUP: RefCell<DeferGroup<'static>> = const { RefCell::new(DeferGroup::new()) };
+        }
+        let rec = ExecutionRecorder::new();
+        let thread_rec = rec.clone();
+        std::thread::spawn(move || {
+            register_both(&mut &CLEANUP, &thread_rec);
+            thread_rec.record("thread exiting");
+        })
+        .join()
+        .unwrap();
+        rec.assert_order(&["thread exiting", "registered 2nd", "registered 1st"]);
+    }
+}
@@ -0,0 +1,922 @@
+//! A process-wide registry of deferred cleanups.
+//!
+//! Unlike [`Defer`](crate::Defer) and [`DeferGroup`](crate::DeferGroup), which run their closures when they go out of scope,
+//! closures registered here are only executed when [`run_all`] is invoked, usually right before the process
+//! (or the module, for `wasm32` targets) is torn down. This makes the registry suitable for cleanup of
+//! global resources, which are never dropped.
+//!
+//! # Example
+//!
+//! ```rust
+//! use defer_rs::registry;
+//!
+//! registry::register(|| println!("Flushing metrics..."));
+//! registry::register(|| println!("Closing the database pool..."));
+//!
+//! // ... the rest of `main` ...
+//!
+//! // Prints "Closing the database pool...", then "Flushing metrics..."
+//! registry::run_all();
+//! ```
+//!
+//! # WebAssembly
+//!
+//! In the browser, `Drop` never runs when the tab is closed. To drain the registry on page unload,
+//! call [`run_all`] from a `beforeunload` or `visibilitychange` (when `document.visibilityState` becomes
+//! `"hidden"`) event listener. The crate doesn't register such a listener itself (there's no `wasm-bindgen` feature),
+//! wiring it is left to the application, e.g. (using `wasm-bindgen` and `web-sys`):
+//!
+//! ```rust,ignore
+//! let on_unload = Closure::<dyn FnMut()>::new(|| defer_rs::registry::run_all());
+//! web_sys::window()
+//!     .unwrap()
+//!     .add_event_listener_with_callback("beforeunload", on_unload.as_ref().unchecked_ref())
+//!     .unwrap();
+//! on_unload.forget();
+//! ```
+//!
+//! As [`run_all`] drains the registry, it's fine for it to be invoked by several events, each registered closure is only executed once.
+//!
+//! # Bare-metal targets
+//!
+//! The registry isn't interrupt-safe: it's a heap-allocated queue behind a `std` [`Mutex`], so registering a closure from
+//! an interrupt handler can deadlock (if the interrupted code holds the lock) and allocates. There's no `critical-section`
+//! feature (nor interrupt-safe guard types), the crate depends on `std` and can't depend on the `critical-section` crate,
+//! bare-metal firmware needs its own (e.g. fixed-capacity, `critical_section::Mutex`-protected) queue for this.
+
+use std::borrow::{Borrow, Cow};
+use std::fmt;
+use std::panic::Location;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use crate::{budget, Budget, RunReport};
+
+type Deferred = Box<dyn FnOnce() + Send + 'static>;
+
+struct Registered {
+    priority: i32,
+    // Set for closures registered using `register_in`
+    namespace: Option<Cow<'static, str>>,
+    // Set for closures registered using `register_named`/`register_after`
+    name: Option<Cow<'static, str>>,
+    // The names of the closures that must be executed before this one
+    after: Vec<Cow<'static, str>>,
+    // Where the closure was registered, for `list`
+    location: &'static Location<'static>,
+    deferred: Deferred,
+}
+
+impl Registered {
+    #[track_caller]
+    fn new(priority: i32, deferred: Deferred) -> Self {
+        Self {
+            priority,
+            namespace: None,
+            name: None,
+            after: Vec::new(),
+            location: Location::caller(),
+            deferred,
+        }
+    }
+
+    // Returns `true` if a closure this one must be executed after is still waiting in `pending`
+    fn is_blocked<'r>(&self, mut pending: impl Iterator<Item = &'r Registered>) -> bool {
+        !self.after.is_empty()
+            && pending.any(|other| {
+                other
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| self.after.contains(name))
+            })
+    }
+}
+
+// Kept sorted by priority (lowest first), closures are popped from the end
+static REGISTRY: Mutex<Vec<Registered>> = Mutex::new(Vec::new());
+
+/// Registers a closure to be executed by the next [`run_all`] invocation.
+///
+/// See also: [`static_defer!`](crate::static_defer).
+#[track_caller]
+pub fn register(f: impl FnOnce() + Send + 'static) {
+    register_with_priority(0, f);
+}
+
+/// Registers a closure with the given priority, to be executed by the next [`run_all`] invocation.
+///
+/// Closures with a higher priority are executed first, regardless of the order of registration, closures registered
+/// using [`register`] have a priority of `0`. Closures with the same priority are executed last to first.
+/// This lets global teardown be ordered in phases (e.g. stopping the servers before closing the database pool),
+/// even when the closures are registered by independent modules.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry;
+///
+/// registry::register_with_priority(-10, || println!("Closing the database pool..."));
+/// registry::register_with_priority(10, || println!("Stopping the HTTP server..."));
+///
+/// // Prints "Stopping the HTTP server...", then "Closing the database pool..."
+/// registry::run_all();
+/// ```
+#[track_caller]
+pub fn register_with_priority(priority: i32, f: impl FnOnce() + Send + 'static) {
+    insert(Registered::new(priority, Box::new(f)));
+}
+
+/// Registers a closure under the given namespace (e.g. `"db"`, or `"telemetry"`), to be executed by the next [`run_all`]
+/// (or [`run_namespace`]) invocation.
+///
+/// Namespaces let a single subsystem be torn down (or have its teardown discarded, using [`clear_namespace`]) on demand,
+/// e.g. when it's restarted in a long-lived daemon, while the rest of the registry is kept until shutdown.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry;
+///
+/// registry::register_in("cache", || println!("Flushing the cache..."));
+/// registry::register(|| println!("Closing the log file..."));
+///
+/// // Restarting the cache subsystem, only prints "Flushing the cache..."
+/// registry::run_namespace("cache");
+/// assert_eq!(registry::namespace_len("cache"), 0);
+/// ```
+#[track_caller]
+pub fn register_in(namespace: impl Into<Cow<'static, str>>, f: impl FnOnce() + Send + 'static) {
+    insert(Registered {
+        namespace: Some(namespace.into()),
+        ..Registered::new(0, Box::new(f))
+    });
+}
+
+/// Registers a named closure, to be executed by the next [`run_all`] invocation, see [`register_after`].
+#[track_caller]
+pub fn register_named(name: impl Into<Cow<'static, str>>, f: impl FnOnce() + Send + 'static) {
+    insert(Registered {
+        name: Some(name.into()),
+        ..Registered::new(0, Box::new(f))
+    });
+}
+
+/// Registers a named closure, to be executed by the next [`run_all`] invocation after the closures named in `after` (registered using
+/// [`register_named`], or `register_after`).
+///
+/// Dependencies take precedence over priorities, and the order of registration: the closures are executed in a topological order,
+/// each closure being executed after all the (still registered) closures it depends on, otherwise, in the usual order. This replaces
+/// manual phase numbering for complex services, each subsystem only declaring what it must be torn down after.
+/// Depending on a name that isn't registered isn't an error, there's nothing to wait for. Cyclic dependencies are reported by [`run_ordered`].
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry;
+///
+/// registry::register_after("close network", ["flush metrics"], || println!("Closing the network..."));
+/// registry::register_named("flush metrics", || println!("Flushing the metrics..."));
+///
+/// // Prints "Flushing the metrics...", then "Closing the network..."
+/// registry::run_ordered().unwrap();
+/// ```
+#[track_caller]
+pub fn register_after<D: Into<Cow<'static, str>>>(
+    name: impl Into<Cow<'static, str>>,
+    after: impl IntoIterator<Item = D>,
+    f: impl FnOnce() + Send + 'static,
+) {
+    insert(Registered {
+        name: Some(name.into()),
+        after: after.into_iter().map(Into::into).collect(),
+        ..Registered::new(0, Box::new(f))
+    });
+}
+
+fn insert(registered: Registered) {
+    crate::hooks::registered("registry");
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let index = registry.partition_point(|entry| entry.priority <= registered.priority);
+    registry.insert(index, registered);
+}
+
+/// The process-wide registry as a [`DeferRegistrar`](crate::DeferRegistrar), see [`register`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Registry;
+
+impl crate::DeferRegistrar<'static> for Registry {
+    #[track_caller]
+    fn register(&mut self, f: Box<dyn FnOnce() + Send + 'static>) {
+        register(f);
+    }
+}
+
+/// Drains the registry, executing the registered closures last to first (in reverse order of registration).
+///
+/// Closures registered while `run_all` is executing (e.g. by one of the registered closures) are executed as well.
+/// Closures with [dependencies](register_after) are executed after the closures they depend on, unless the dependencies are cyclic,
+/// the closures blocked by a cycle are then executed in the usual order (use [`run_ordered`] to detect cycles beforehand).
+pub fn run_all() {
+    if len() > 0 && crate::debug::skipped(|| format!("{} registered closure(s)", len())) {
+        return;
+    }
+    loop {
+        // The lock must not be held while executing the closure, as it may register more closures
+        let deferred = next_entry(&mut REGISTRY.lock().unwrap_or_else(PoisonError::into_inner));
+        match deferred {
+            Some(f) => {
+                let _span = crate::hooks::executing_queued("registry");
+                f()
+            }
+            None => break,
+        }
+    }
+}
+
+/// Same as [`run_all`], but a panicking closure doesn't stop the run, and a [`RunReport`] of the run is returned.
+///
+/// The panics are caught and recorded in the report, along with the time each closure took, so operators can check
+/// (or log) whether the shutdown was actually clean.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry;
+///
+/// registry::register(|| panic!("failed to deregister from service discovery"));
+/// registry::register(|| println!("Flushing metrics..."));
+///
+/// let report = registry::run_all_reported();
+/// assert_eq!(report.executed(), 2);
+/// assert!(!report.is_clean());
+/// eprintln!("Shutdown: {report}");
+/// ```
+pub fn run_all_reported() -> RunReport {
+    let count = len();
+    if count > 0 && crate::debug::skipped(|| format!("{count} registered closure(s)")) {
+        return RunReport::skipped_all(count);
+    }
+    budget::run_reported(
+        std::iter::from_fn(|| {
+            next_entry(&mut REGISTRY.lock().unwrap_or_else(PoisonError::into_inner))
+                .map(|f| f as Box<dyn FnOnce()>)
+        }),
+        "registry",
+    )
+}
+
+/// Same as [`run_all`], but the registry is checked for cyclic [dependencies](register_after) first, nothing is executed
+/// (and the registry is left untouched) if a cycle is found.
+pub fn run_ordered() -> Result<(), DependencyCycle> {
+    {
+        let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cycle) = find_cycle(&registry) {
+            return Err(cycle);
+        }
+    }
+    run_all();
+    Ok(())
+}
+
+/// Same as [`run_all`], but closures with no ordering constraints between them are executed concurrently (each on its own scoped thread).
+///
+/// The registry is drained in rounds: each round executes (concurrently) every closure whose [dependencies](register_after) were all executed,
+/// and whose [priority](register_with_priority) is the highest among these, returning once all of them are done. The order of registration
+/// isn't an ordering constraint, so this is an opt-in, meant for services with many slow, independent teardowns.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry;
+/// use std::time::{Duration, Instant};
+///
+/// for _ in 0..4 {
+///     registry::register(|| std::thread::sleep(Duration::from_millis(100)));
+/// }
+/// registry::register_with_priority(10, || println!("Stopping the HTTP server first..."));
+///
+/// let start = Instant::now();
+/// registry::run_parallel();
+/// assert!(start.elapsed() < Duration::from_millis(400));
+/// ```
+pub fn run_parallel() {
+    if len() > 0 && crate::debug::skipped(|| format!("{} registered closure(s)", len())) {
+        return;
+    }
+    loop {
+        let round = next_round(&mut REGISTRY.lock().unwrap_or_else(PoisonError::into_inner));
+        if round.is_empty() {
+            break;
+        }
+        crate::sync::run_concurrently(round, "registry");
+    }
+}
+
+// Removes the highest priority closures whose dependencies were all executed (or the last closure, if every closure is blocked by a cycle)
+fn next_round(registry: &mut Vec<Registered>) -> Vec<Deferred> {
+    let ready: Vec<usize> = (0..registry.len())
+        .filter(|&i| !registry[i].is_blocked(registry.iter()))
+        .collect();
+    let Some(priority) = ready.iter().map(|&i| registry[i].priority).max() else {
+        return next_entry(registry).into_iter().collect();
+    };
+    let round: Vec<usize> = ready
+        .into_iter()
+        .filter(|&i| registry[i].priority == priority)
+        .collect();
+    // Removing the last indices first leaves the others valid
+    round
+        .into_iter()
+        .rev()
+        .map(|i| registry.remove(i).deferred)
+        .collect()
+}
+
+// Removes the last closure whose dependencies were all executed (or the last closure, if every closure is blocked by a cycle)
+fn next_entry(registry: &mut Vec<Registered>) -> Option<Deferred> {
+    let index = next_index(registry)?;
+    Some(registry.remove(index).deferred)
+}
+
+fn next_index<R: Borrow<Registered>>(pending: &[R]) -> Option<usize> {
+    pending
+        .iter()
+        .rposition(|entry| {
+            !entry
+                .borrow()
+                .is_blocked(pending.iter().map(Borrow::borrow))
+        })
+        .or(pending.len().checked_sub(1))
+}
+
+/// Lists the closures waiting in the registry, in the order [`run_all`] would execute them, without executing them.
+///
+/// Each closure is listed along with its name, namespace, priority, dependencies, and where it was registered,
+/// for debugging "what exactly will run when this service stops". The listing is a snapshot, closures registered
+/// by other closures as they're executed aren't listed.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry;
+///
+/// registry::register_named("flush metrics", || println!("Flushing metrics..."));
+/// registry::register_in("db", || println!("Closing the database pool..."));
+///
+/// for cleanup in registry::list() {
+///     // e.g. "`flush metrics` (priority 0) registered at src/main.rs:3:1"
+///     println!("{cleanup}");
+/// }
+/// assert_eq!(registry::list()[0].namespace(), Some("db"));
+/// ```
+pub fn list() -> Vec<ListedCleanup> {
+    let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut pending: Vec<&Registered> = registry.iter().collect();
+    let mut listed = Vec::with_capacity(pending.len());
+    while let Some(index) = next_index(&pending) {
+        let entry = pending.remove(index);
+        listed.push(ListedCleanup {
+            name: entry.name.clone(),
+            namespace: entry.namespace.clone(),
+            priority: entry.priority,
+            after: entry.after.clone(),
+            location: entry.location,
+        });
+    }
+    listed
+}
+
+/// A closure waiting in the registry, as listed by [`list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedCleanup {
+    name: Option<Cow<'static, str>>,
+    namespace: Option<Cow<'static, str>>,
+    priority: i32,
+    after: Vec<Cow<'static, str>>,
+    location: &'static Location<'static>,
+}
+
+impl ListedCleanup {
+    /// Returns the name of the closure, if it was registered using [`register_named`] (or [`register_after`]).
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the namespace of the closure, if it was registered using [`register_in`].
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Returns the priority (i.e. the phase) of the closure.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the names of the closures this one must be executed after.
+    pub fn after(&self) -> impl Iterator<Item = &str> {
+        self.after.iter().map(|name| name.as_ref())
+    }
+
+    /// Returns where the closure was registered.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl fmt::Display for ListedCleanup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "`{name}`")?,
+            None => write!(f, "unnamed closure")?,
+        }
+        write!(f, " (priority {}", self.priority)?;
+        if let Some(namespace) = &self.namespace {
+            write!(f, ", namespace `{namespace}`")?;
+        }
+        if !self.after.is_empty() {
+            write!(f, ", after {}", self.after.join(", "))?;
+        }
+        write!(f, ") registered at {}", self.location)
+    }
+}
+
+// Repeatedly discards the closures that aren't blocked, the closures left once none can be discarded are blocked by a cycle
+fn find_cycle(registry: &[Registered]) -> Option<DependencyCycle> {
+    let mut pending: Vec<&Registered> = registry.iter().collect();
+    loop {
+        let blocked: Vec<&Registered> = pending
+            .iter()
+            .copied()
+            .filter(|entry| entry.is_blocked(pending.iter().copied()))
+            .collect();
+        if blocked.is_empty() {
+            return None;
+        }
+        if blocked.len() == pending.len() {
+            return Some(DependencyCycle {
+                names: blocked
+                    .iter()
+                    .filter_map(|entry| entry.name.clone())
+                    .collect(),
+            });
+        }
+        pending = blocked;
+    }
+}
+
+/// The error returned by [`run_ordered`] when the dependencies between the registered closures are cyclic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyCycle {
+    names: Vec<Cow<'static, str>>,
+}
+
+impl DependencyCycle {
+    /// Returns the names of the closures in (or blocked by) the cycle, in order of execution priority.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|name| name.as_ref())
+    }
+}
+
+impl fmt::Display for DependencyCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cyclic dependencies between the registered closures: ")?;
+        for (i, name) in self.names.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "`{name}`")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DependencyCycle {}
+
+/// Drains the closures registered under the given namespace (using [`register_in`]), executing them in the same order as [`run_all`] does.
+///
+/// Closures registered under the namespace while `run_namespace` is executing are executed as well. The rest of the registry is left untouched.
+pub fn run_namespace(namespace: &str) {
+    let count = namespace_len(namespace);
+    if count > 0
+        && crate::debug::skipped(|| format!("{count} closure(s) registered under `{namespace}`"))
+    {
+        return;
+    }
+    loop {
+        // The lock must not be held while executing the closure, as it may register more closures
+        let deferred = {
+            let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+            registry
+                .iter()
+                .rposition(|entry| entry.namespace.as_deref() == Some(namespace))
+                .map(|index| registry.remove(index).deferred)
+        };
+        match deferred {
+            Some(f) => {
+                let _span = crate::hooks::executing_queued("registry");
+                f()
+            }
+            None => break,
+        }
+    }
+}
+
+/// Removes the closures registered under the given namespace without executing them, returning how many were removed.
+pub fn clear_namespace(namespace: &str) -> usize {
+    let removed: Vec<_> = {
+        let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        let (removed, kept) = std::mem::take(&mut *registry)
+            .into_iter()
+            .partition(|entry| entry.namespace.as_deref() == Some(namespace));
+        *registry = kept;
+        removed
+    };
+    // The closures (and their captures) are dropped after the lock is released
+    removed.len()
+}
+
+/// Returns the number of closures currently waiting in the registry under the given namespace.
+pub fn namespace_len(namespace: &str) -> usize {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .filter(|entry| entry.namespace.as_deref() == Some(namespace))
+        .count()
+}
+
+/// Same as [`run_all`], but within the given time [`Budget`].
+///
+/// When a limit applies, each closure is executed on its own thread, so a cleanup that exceeds its budget
+/// can be left running in the background (when the budget's policy is [`Overrun::Continue`](crate::Overrun::Continue)
+/// or [`Overrun::SkipRest`](crate::Overrun::SkipRest)) instead of wedging the shutdown forever.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{registry, Budget};
+/// use std::time::Duration;
+///
+/// registry::register(|| std::thread::sleep(Duration::from_secs(3600)));
+///
+/// let report = registry::run_all_with_budget(Budget::total(Duration::from_millis(50)));
+/// assert_eq!(report.overran(), [0]);
+/// ```
+pub fn run_all_with_budget(budget: Budget) -> RunReport {
+    let count = len();
+    if count > 0 && crate::debug::skipped(|| format!("{count} registered closure(s)")) {
+        return RunReport::skipped_all(count);
+    }
+    budget::run_detachable(
+        || next_entry(&mut REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)),
+        &budget,
+        "registry",
+    )
+}
+
+/// Same as [`run_all_with_budget`], but the registry is drained in phases, each with its own time [`Budget`].
+///
+/// A phase is a [priority](register_with_priority) level: the phases are executed from the highest priority to the lowest, and `budget`
+/// is called with each phase's priority to get its budget. An [`Overrun::SkipRest`](crate::Overrun::SkipRest) policy only skips the rest
+/// of the phase that overran, so one misbehaving subsystem can't block the teardown of the others (or the process exit) indefinitely.
+/// [Dependencies](register_after) are only honored between closures of the same phase.
+///
+/// Returns each phase's priority, along with its [`RunReport`].
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{registry, Budget, Overrun};
+/// use std::time::Duration;
+///
+/// registry::register_with_priority(10, || println!("Stopping the HTTP server..."));
+/// // Executed first, and hangs
+/// registry::register_with_priority(10, || std::thread::sleep(Duration::from_secs(3600)));
+/// registry::register(|| println!("Closing the database pool..."));
+///
+/// let reports = registry::run_phased(|_phase| {
+///     Budget::total(Duration::from_millis(50)).on_overrun(Overrun::SkipRest)
+/// });
+/// assert_eq!(reports[0].0, 10);
+/// assert_eq!(reports[0].1.skipped(), 1);
+/// assert!(reports[1].1.is_clean());
+/// ```
+pub fn run_phased(mut budget: impl FnMut(i32) -> Budget) -> Vec<(i32, RunReport)> {
+    let count = len();
+    if count > 0 && crate::debug::skipped(|| format!("{count} registered closure(s)")) {
+        let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut phases: Vec<_> = registry.iter().map(|entry| entry.priority).collect();
+        phases.sort_unstable_by(|a, b| b.cmp(a));
+        phases.dedup();
+        return phases
+            .into_iter()
+            .map(|phase| {
+                let in_phase = registry.iter().filter(|entry| entry.priority == phase);
+                (phase, RunReport::skipped_all(in_phase.count()))
+            })
+            .collect();
+    }
+    let mut reports = Vec::new();
+    loop {
+        // Closures registered with a higher priority while a phase is executing are executed in the next one
+        let phase = {
+            let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+            match registry.iter().map(|entry| entry.priority).max() {
+                Some(phase) => phase,
+                None => break,
+            }
+        };
+        let report = budget::run_detachable(
+            || {
+                let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+                let in_phase = |entry: &Registered| entry.priority == phase;
+                let index = registry
+                    .iter()
+                    .rposition(|entry| {
+                        in_phase(entry)
+                            && !entry.is_blocked(registry.iter().filter(|other| in_phase(other)))
+                    })
+                    .or_else(|| registry.iter().rposition(in_phase))?;
+                Some(registry.remove(index).deferred)
+            },
+            &budget(phase),
+            "registry",
+        );
+        reports.push((phase, report));
+    }
+    reports
+}
+
+/// Returns the number of closures currently waiting in the registry.
+pub fn len() -> usize {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .len()
+}
+
+/// A lazily initialized global, whose teardown is registered in the registry once it's initialized.
+///
+/// Like a [`LazyLock`](std::sync::LazyLock), the value is initialized on first access, and unlike it, a teardown closure is
+/// [registered](register) right after the initialization, so global resources get symmetric setup and cleanup (when [`run_all`] is invoked).
+/// The teardown is only registered if the value was initialized, and receives a reference to it (the value itself is never dropped).
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::registry::{self, DeferredStatic};
+/// use std::sync::Mutex;
+///
+/// static CONNECTIONS: DeferredStatic<Mutex<Vec<&str>>> = DeferredStatic::new(
+///     || Mutex::new(vec!["db", "cache"]),
+///     |connections| connections.lock().unwrap().clear(),
+/// );
+///
+/// assert_eq!(CONNECTIONS.get().lock().unwrap().len(), 2);
+///
+/// // ... the rest of `main` ...
+///
+/// registry::run_all();
+/// assert!(CONNECTIONS.get().lock().unwrap().is_empty());
+/// ```
+pub struct DeferredStatic<T> {
+    value: OnceLock<T>,
+    init: fn() -> T,
+    teardown: fn(&T),
+}
+
+impl<T: Send + Sync + 'static> DeferredStatic<T> {
+    /// Creates a new, uninitialized `DeferredStatic`, initialized using `init`, and torn down using `teardown`.
+    pub const fn new(init: fn() -> T, teardown: fn(&T)) -> Self {
+        Self {
+            value: OnceLock::new(),
+            init,
+            teardown,
+        }
+    }
+
+    /// Returns the value, initializing it (and registering its teardown) if it isn't initialized yet.
+    #[track_caller]
+    pub fn get(&'static self) -> &'static T {
+        if let Some(value) = self.value.get() {
+            return value;
+        }
+        let mut initialized = false;
+        let value = self.value.get_or_init(|| {
+            initialized = true;
+            (self.init)()
+        });
+        // Only the thread that initialized the value registers the teardown
+        if initialized {
+            let teardown = self.teardown;
+            register(move || teardown(value));
+        }
+        value
+    }
+
+    /// Returns the value if it's already initialized, without initializing it.
+    pub fn get_if_initialized(&self) -> Option<&T> {
+        self.value.get()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Overrun;
+    use std::sync::{Arc, MutexGuard};
+    use std::thread;
+    use std::time::Duration;
+
+    // The registry is process-wide, tests using it must not run concurrently
+    pub(crate) fn serial() -> MutexGuard<'static, ()> {
+        static SERIAL: Mutex<()> = Mutex::new(());
+        SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[test]
+    fn test_run_all_drains_in_reverse_order() {
+        let _serial = serial();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        for i in 0..3 {
+            let log = log.clone();
+            register(move || log.lock().unwrap().push(i));
+        }
+        assert_eq!(len(), 3);
+
+        run_all();
+        run_all();
+        assert_eq!(len(), 0);
+        assert_eq!(*log.lock().unwrap(), [2, 1, 0]);
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let _serial = serial();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        for (namespace, i) in [("db", 1), ("cache", 2), ("db", 3)] {
+            let log = log.clone();
+            register_in(namespace, move || log.lock().unwrap().push(i));
+        }
+        let global = log.clone();
+        register(move || global.lock().unwrap().push(0));
+        assert_eq!(namespace_len("db"), 2);
+
+        run_namespace("db");
+        assert_eq!(*log.lock().unwrap(), [3, 1]);
+        assert_eq!(clear_namespace("cache"), 1);
+        assert_eq!(len(), 1);
+
+        run_all();
+        assert_eq!(*log.lock().unwrap(), [3, 1, 0]);
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let _serial = serial();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let record = |name: &'static str| {
+            let log = log.clone();
+            move || log.lock().unwrap().push(name)
+        };
+        register_after(
+            "close network",
+            ["flush metrics", "missing"],
+            record("close network"),
+        );
+        register_named("flush metrics", record("flush metrics"));
+        register_with_priority(10, record("stop server"));
+        run_ordered().unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["stop server", "flush metrics", "close network"]
+        );
+
+        log.lock().unwrap().clear();
+        register_after("a", ["b"], record("a"));
+        register_after("b", ["a"], record("b"));
+        register_after("c", ["a"], record("c"));
+        let cycle = run_ordered().unwrap_err();
+        assert_eq!(cycle.names().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(len(), 3);
+
+        // The cycle is broken in the usual order
+        run_all();
+        assert_eq!(*log.lock().unwrap(), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        let _serial = serial();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+
+        for name in ["a", "b"] {
+            let (log, barrier) = (log.clone(), barrier.clone());
+            register(move || {
+                barrier.wait();
+                log.lock().unwrap().push(name);
+            });
+        }
+        let first = log.clone();
+        register_after("close", ["flush"], move || {
+            first.lock().unwrap().push("close")
+        });
+        let flush = log.clone();
+        register_named("flush", move || flush.lock().unwrap().push("flush"));
+
+        run_parallel();
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 4);
+        let position = |name| log.iter().position(|n| *n == name).unwrap();
+        assert!(position("flush") < position("close"));
+    }
+
+    #[test]
+    fn test_run_phased() {
+        let _serial = serial();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        register_with_priority(5, || thread::sleep(Duration::from_secs(60)));
+        for (priority, i) in [(5, 1), (5, 2), (0, 3)] {
+            let log = log.clone();
+            register_with_priority(priority, move || log.lock().unwrap().push(i));
+        }
+
+        let reports = run_phased(|phase| {
+            let budget = Budget::per_entry(Duration::from_millis(20));
+            if phase == 5 {
+                budget.on_overrun(Overrun::SkipRest)
+            } else {
+                budget
+            }
+        });
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].0, 5);
+        assert_eq!(reports[0].1.executed(), 3);
+        assert_eq!(reports[0].1.overran(), [2]);
+        assert!(reports[1].1.is_clean());
+        assert_eq!(*log.lock().unwrap(), [2, 1, 3]);
+    }
+
+    #[test]
+    fn test_list() {
+        let _serial = serial();
+        register_after("b", ["a"], || {});
+        register_named("a", || {});
+        let line = line!() + 1;
+        register_with_priority(1, || {});
+
+        let listed = list();
+        assert_eq!(len(), 3);
+        assert_eq!(listed[0].location().line(), line);
+        assert_eq!(listed[0].location().file(), file!());
+        assert_eq!(
+            listed.iter().map(ListedCleanup::name).collect::<Vec<_>>(),
+            [None, Some("a"), Some("b")]
+        );
+        assert!(listed[2]
+            .to_string()
+            .starts_with("`b` (priority 0, after a) registered at "));
+        run_all();
+    }
+
+    #[test]
+    fn test_static_defer_priorities() {
+        let _serial = serial();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let (a, b, c, d) = (log.clone(), log.clone(), log.clone(), log.clone());
+        crate::static_defer!(c.lock().unwrap().push(3));
+        crate::static_defer!(priority = -1; d.lock().unwrap().push(4));
+        crate::static_defer!(priority = 5; move a.lock().unwrap().push(1));
+        register(move || b.lock().unwrap().push(2));
+
+        run_all();
+        assert_eq!(*log.lock().unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_deferred_static() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static TORN_DOWN: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: DeferredStatic<u8> = DeferredStatic::new(
+            || 42,
+            |_| {
+                TORN_DOWN.fetch_add(1, Ordering::Relaxed);
+            },
+        );
+
+        let _serial = serial();
+        assert!(VALUE.get_if_initialized().is_none());
+        assert_eq!(*VALUE.get(), 42);
+        assert_eq!(*VALUE.get(), 42);
+        assert_eq!(len(), 1);
+
+        run_all();
+        assert_eq!(TORN_DOWN.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,166 @@
+use std::future::Future;
+use std::thread;
+use std::time::Duration;
+
+/// A retry policy for fallible cleanups (e.g. releasing a lease, or deregistering from service discovery), retrying a failed
+/// cleanup a number of times, with a backoff between attempts, before giving up.
+///
+/// The backoff is constant by default, or doubled after each attempt with [`Retry::exponential`] (capped by [`Retry::max_backoff`]).
+/// Blocking cleanups are retried using [`Retry::run`] (sleeping the current thread between attempts), async ones using [`Retry::run_async`]
+/// (awaiting a timer future passed in, e.g. `tokio::time::sleep`), and [`Retry::wrap`] turns a fallible cleanup into a closure
+/// that can be deferred as usual, recording the failure once it gives up.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{Defer, Retry};
+/// use std::time::Duration;
+///
+/// # fn release_lease() -> Result<(), String> { Ok(()) }
+/// let retry = Retry::new(3).backoff(Duration::from_millis(100)).exponential();
+/// let _release = Defer::new(retry.wrap(release_lease, |err| {
+///     eprintln!("Giving up on releasing the lease: {err}");
+/// }));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    retries: u32,
+    backoff: Duration,
+    exponential: bool,
+    max_backoff: Option<Duration>,
+}
+
+impl Retry {
+    /// Creates a policy retrying a failed cleanup up to `retries` times (after the first attempt), without any backoff.
+    pub const fn new(retries: u32) -> Self {
+        Self {
+            retries,
+            backoff: Duration::ZERO,
+            exponential: false,
+            max_backoff: None,
+        }
+    }
+
+    /// Sets the delay between attempts (or before the first retry, if the backoff is exponential).
+    pub const fn backoff(mut self, delay: Duration) -> Self {
+        self.backoff = delay;
+        self
+    }
+
+    /// Doubles the delay after each attempt.
+    pub const fn exponential(mut self) -> Self {
+        self.exponential = true;
+        self
+    }
+
+    /// Sets the maximum delay between attempts.
+    pub const fn max_backoff(mut self, limit: Duration) -> Self {
+        self.max_backoff = Some(limit);
+        self
+    }
+
+    // The delay before the given retry (0-indexed)
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = if self.exponential {
+            self.backoff
+                .saturating_mul(2u32.saturating_pow(retry.min(31)))
+        } else {
+            self.backoff
+        };
+        self.max_backoff.map_or(delay, |limit| delay.min(limit))
+    }
+
+    /// Calls `f` until it succeeds, or the retries are exhausted, sleeping the current thread between attempts, returning the last result.
+    pub fn run<T, E>(&self, mut f: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut res = f();
+        for retry in 0..self.retries {
+            if res.is_ok() {
+                break;
+            }
+            thread::sleep(self.delay(retry));
+            res = f();
+        }
+        res
+    }
+
+    /// Same as [`Retry::run`], but for async cleanups, awaiting the future returned by `sleep` (e.g. `tokio::time::sleep`) between attempts.
+    pub async fn run_async<T, E, Fut, S>(
+        &self,
+        mut f: impl FnMut() -> Fut,
+        mut sleep: impl FnMut(Duration) -> S,
+    ) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+        S: Future,
+    {
+        let mut res = f().await;
+        for retry in 0..self.retries {
+            if res.is_ok() {
+                break;
+            }
+            sleep(self.delay(retry)).await;
+            res = f().await;
+        }
+        res
+    }
+
+    /// Turns a fallible cleanup into a closure retrying it (see [`Retry::run`]), and passing the last error to `on_failure` if it gives up.
+    pub fn wrap<E>(
+        self,
+        f: impl FnMut() -> Result<(), E>,
+        on_failure: impl FnOnce(E),
+    ) -> impl FnOnce() {
+        move || {
+            if let Err(err) = self.run(f) {
+                on_failure(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::tests::block_on;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_retry() {
+        let retry = Retry::new(3)
+            .backoff(Duration::from_millis(1))
+            .exponential()
+            .max_backoff(Duration::from_millis(3));
+        assert_eq!(
+            (0..4).map(|i| retry.delay(i)).collect::<Vec<_>>(),
+            [1, 2, 3, 3].map(Duration::from_millis)
+        );
+
+        // Succeeds on the 3rd attempt
+        let attempts = Cell::new(0);
+        let flaky = || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                Err(attempts.get())
+            } else {
+                Ok(())
+            }
+        };
+        assert_eq!(retry.run(flaky), Ok(()));
+        assert_eq!(attempts.get(), 3);
+
+        let failed = Cell::new(None);
+        Retry::new(2).wrap(|| Err::<(), _>("unreachable"), |err| failed.set(Some(err)))();
+        assert_eq!(failed.get(), Some("unreachable"));
+
+        let attempts = Cell::new(0);
+        let res: Result<(), ()> = block_on(Retry::new(1).run_async(
+            || {
+                attempts.set(attempts.get() + 1);
+                async { Err(()) }
+            },
+            |_| async {},
+        ));
+        assert!(res.is_err());
+        assert_eq!(attempts.get(), 2);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::DeferGroup;
+
+/// A guard executing a stack of rollback steps when it goes out of scope, unless [`RollbackGuard::commit`] is called first.
+///
+/// This formalizes the "undo everything, unless we reach the end" pattern: each successful step of an operation
+/// registers the action undoing it, and once the whole operation succeeds, the guard is committed,
+/// discarding the rollback steps. Rollback steps are executed last to first, undoing the most recent step first.
+///
+/// **Note: `RollbackGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::RollbackGuard;
+/// use std::cell::RefCell;
+///
+/// fn provision(log: &RefCell<Vec<&str>>, fail: bool) -> Result<(), &'static str> {
+///     let mut rollback = RollbackGuard::new();
+///
+///     log.borrow_mut().push("create user");
+///     rollback.add_step(|| log.borrow_mut().push("delete user"));
+///
+///     log.borrow_mut().push("create home dir");
+///     rollback.add_step(|| log.borrow_mut().push("delete home dir"));
+///
+///     if fail {
+///         return Err("quota service is down");
+///     }
+///
+///     rollback.commit();
+///     Ok(())
+/// }
+///
+/// let log = RefCell::new(Vec::new());
+/// assert!(provision(&log, true).is_err());
+/// assert_eq!(
+///     *log.borrow(),
+///     ["create user", "create home dir", "delete home dir", "delete user"]
+/// );
+/// ```
+///
+/// See also: [`DeferGroup::on_unwind`].
+#[must_use = "RollbackGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!"]
+#[derive(Default)]
+pub struct RollbackGuard<'a> {
+    steps: DeferGroup<'a>,
+}
+
+impl<'a> RollbackGuard<'a> {
+    /// Creates a new `RollbackGuard`, with no rollback steps.
+    ///
+    /// **Note: `RollbackGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub const fn new() -> Self {
+        Self {
+            steps: DeferGroup::new(),
+        }
+    }
+
+    /// Adds a rollback step, which will be executed before the previously added ones.
+    pub fn add_step(&mut self, step: impl FnOnce() + 'a) {
+        self.steps.add(Box::new(step));
+    }
+
+    /// Returns the number of rollback steps.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Returns `true` if no rollback steps were added.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Consumes the guard, discarding all the rollback steps without executing them.
+    pub fn commit(self) {
+        drop(self.steps.into_iter());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_rollback_guard() {
+        let log = RefCell::new(Vec::new());
+        {
+            let mut rollback = RollbackGuard::new();
+            rollback.add_step(|| log.borrow_mut().push(1));
+            rollback.add_step(|| log.borrow_mut().push(2));
+            assert_eq!(rollback.len(), 2);
+        }
+        assert_eq!(*log.borrow(), [2, 1]);
+
+        {
+            let mut rollback = RollbackGuard::new();
+            rollback.add_step(|| log.borrow_mut().push(3));
+            rollback.commit();
+        }
+        assert_eq!(*log.borrow(), [2, 1]);
+    }
+}
@@ -0,0 +1,265 @@
+use std::any::Any;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::DeferGroup;
+
+/// Runs a fallible closure with a fresh [`DeferGroup`] in scope.
+///
+/// The group is handed to the closure by mutable reference, so cleanups can be queued on it
+/// as the work progresses. Once the closure returns, the group is dropped, executing the queued
+/// closures, whether the result is `Ok` or `Err` (or the closure panics).
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::run_scope;
+/// use std::cell::Cell;
+///
+/// let cleaned_up = Cell::new(false);
+/// let res: Result<(), &str> = run_scope(|group| {
+///     group.add(Box::new(|| cleaned_up.set(true)));
+///     Err("Something went wrong!")
+/// });
+///
+/// assert_eq!(res, Err("Something went wrong!"));
+/// assert!(cleaned_up.get());
+/// ```
+///
+/// See also: [`run_scope_with`], and [`DeferGroup`].
+pub fn run_scope<'a, T, E, F>(f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut DeferGroup<'a>) -> Result<T, E>,
+{
+    let mut group = DeferGroup::new();
+    let res = f(&mut group);
+    drop(group);
+    res
+}
+
+/// Acquires a resource, runs `body` with it, and releases it on every exit path (including panics), returning the body's value.
+///
+/// This is the "bracket" pattern: `release` is guaranteed to receive the resource once `body` returns (or unwinds),
+/// which is tighter than acquiring the resource and creating a [`Defer`](crate::Defer) for its release separately.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::with_resource;
+/// use std::cell::RefCell;
+///
+/// let log = RefCell::new(Vec::new());
+/// let len = with_resource(
+///     || {
+///         log.borrow_mut().push("acquired");
+///         String::from("connection")
+///     },
+///     |_conn| log.borrow_mut().push("released"),
+///     |conn| {
+///         log.borrow_mut().push("used");
+///         conn.len()
+///     },
+/// );
+///
+/// assert_eq!(len, 10);
+/// assert_eq!(*log.borrow(), ["acquired", "used", "released"]);
+/// ```
+pub fn with_resource<R, T, A, F, B>(acquire: A, release: F, body: B) -> T
+where
+    A: FnOnce() -> R,
+    F: FnOnce(R),
+    B: FnOnce(&mut R) -> T,
+{
+    struct Release<R, F: FnOnce(R)> {
+        resource: Option<R>,
+        release: Option<F>,
+    }
+
+    impl<R, F: FnOnce(R)> Drop for Release<R, F> {
+        fn drop(&mut self) {
+            let (Some(resource), Some(release)) = (self.resource.take(), self.release.take())
+            else {
+                return;
+            };
+            if crate::debug::skipped(|| format!("releasing `{}`", std::any::type_name::<R>())) {
+                return;
+            }
+            let _span = crate::hooks::executing::<F>("with_resource");
+            release(resource);
+        }
+    }
+
+    let mut guard = Release {
+        resource: Some(acquire()),
+        release: Some(release),
+    };
+    // `resource` is only taken once the guard is dropped
+    body(guard.resource.as_mut().unwrap())
+}
+
+/// Same as [`run_scope`], but panics raised by the queued cleanups are caught and handed,
+/// along with the closure's result, to `on_cleanup_error`.
+///
+/// Every queued cleanup is executed, even if one before it panics. `on_cleanup_error` is only
+/// invoked if at least one cleanup panicked, and can be used to attach the failure as context to
+/// the returned error (or to turn an `Ok` into an `Err`).
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::run_scope_with;
+///
+/// let res: Result<u32, String> = run_scope_with(
+///     |group| {
+///         group.add(Box::new(|| panic!("failed to remove temp dir")));
+///         Ok(42)
+///     },
+///     |res, err| match res {
+///         Ok(_) => Err(format!("cleanup failed: {err}")),
+///         Err(e) => Err(format!("{e} (cleanup also failed: {err})")),
+///     },
+/// );
+///
+/// assert_eq!(res.unwrap_err(), "cleanup failed: 1 deferred cleanup(s) panicked: failed to remove temp dir");
+/// ```
+pub fn run_scope_with<'a, T, E, F, C>(f: F, on_cleanup_error: C) -> Result<T, E>
+where
+    F: FnOnce(&mut DeferGroup<'a>) -> Result<T, E>,
+    C: FnOnce(Result<T, E>, CleanupError) -> Result<T, E>,
+{
+    let mut group = DeferGroup::new();
+    let res = f(&mut group);
+
+    if !group.is_empty()
+        && crate::debug::skipped(|| format!("{} deferred cleanup(s) of `run_scope`", group.len()))
+    {
+        drop(group.take_all());
+        return res;
+    }
+
+    let mut panics = Vec::new();
+    for deferred in group.take_all() {
+        let _span = crate::hooks::executing_queued("DeferGroup");
+        if let Err(payload) = catch_unwind(AssertUnwindSafe(deferred)) {
+            panics.push(payload);
+        }
+    }
+
+    if panics.is_empty() {
+        res
+    } else {
+        on_cleanup_error(res, CleanupError { panics })
+    }
+}
+
+/// The error handed to [`run_scope_with`]'s callback, holding the payloads of the cleanups that panicked.
+pub struct CleanupError {
+    pub(crate) panics: Vec<Box<dyn Any + Send + 'static>>,
+}
+
+impl CleanupError {
+    /// Returns the panic payloads, in the order the failing cleanups were executed.
+    pub fn payloads(&self) -> &[Box<dyn Any + Send + 'static>] {
+        &self.panics
+    }
+
+    /// Returns the panic messages (when the payload is a string), in the order the failing cleanups were executed.
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.panics.iter().filter_map(|payload| {
+            payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        })
+    }
+}
+
+impl fmt::Debug for CleanupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CleanupError")
+            .field("panics", &self.panics.len())
+            .field("messages", &self.messages().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl fmt::Display for CleanupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} deferred cleanup(s) panicked", self.panics.len())?;
+        let mut messages = self.messages();
+        if let Some(first) = messages.next() {
+            write!(f, ": {first}")?;
+            for message in messages {
+                write!(f, "; {message}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CleanupError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_run_scope_runs_on_both_paths() {
+        let val = Cell::new(0);
+
+        let res: Result<(), ()> = run_scope(|group| {
+            group.add(Box::new(|| val.set(val.get() + 1)));
+            Ok(())
+        });
+        assert!(res.is_ok());
+        assert_eq!(val.get(), 1);
+
+        let res: Result<(), ()> = run_scope(|group| {
+            group.add(Box::new(|| val.set(val.get() + 1)));
+            Err(())
+        });
+        assert!(res.is_err());
+        assert_eq!(val.get(), 2);
+    }
+
+    #[test]
+    fn test_with_resource_releases_on_panic() {
+        let released = Cell::new(None);
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            with_resource(
+                || 1,
+                |resource| released.set(Some(resource)),
+                |resource| {
+                    *resource += 1;
+                    panic!("body failed");
+                },
+            )
+        }));
+
+        assert!(res.is_err());
+        assert_eq!(released.get(), Some(2));
+    }
+
+    #[test]
+    fn test_run_scope_with_catches_cleanup_panics() {
+        let val = Cell::new(0);
+
+        let res: Result<(), String> = run_scope_with(
+            |group| {
+                group.push(Box::new(|| panic!("1st")));
+                group.push(Box::new(|| val.set(1)));
+                group.push(Box::new(|| panic!("2nd")));
+                Err("body".to_string())
+            },
+            |res, err| Err(format!("{}, {}", res.unwrap_err(), err)),
+        );
+
+        // The cleanup queued between the two panicking ones still ran
+        assert_eq!(val.get(), 1);
+        assert_eq!(
+            res.unwrap_err(),
+            "body, 2 deferred cleanup(s) panicked: 1st; 2nd"
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use std::io::{self, Read};
+use std::net::{Shutdown, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+/// What a guard does with the error of the teardown it performs when it goes out of scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Ignore the error.
+    #[default]
+    Ignore,
+    /// Print the error to `stderr`.
+    Log,
+    /// Panic with the error, unless the thread is already panicking (in which case it's printed to `stderr` instead).
+    Panic,
+}
+
+impl ErrorPolicy {
+    fn handle(self, what: &str, err: io::Error) {
+        match self {
+            ErrorPolicy::Ignore => {}
+            ErrorPolicy::Panic if !std::thread::panicking() => panic!("failed to {what}: {err}"),
+            ErrorPolicy::Log | ErrorPolicy::Panic => eprintln!("defer_rs: failed to {what}: {err}"),
+        }
+    }
+}
+
+/// A guard shutting down the write half of a [`TcpStream`] when it goes out of scope, optionally draining the read half afterwards.
+///
+/// Shutting down the write half lets the peer know no more data is coming (it reads an EOF), draining the read half
+/// (until the peer closes its end, or the drain timeout elapses) before the stream is closed avoids resetting the connection
+/// while the peer is still sending data, which could make it lose the data it didn't read yet.
+///
+/// The guard dereferences to the stream, so it can be used as usual while the guard is held.
+///
+/// **Note: `TcpShutdownGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, shutting down the stream!**
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use defer_rs::{ErrorPolicy, TcpShutdownGuard};
+/// use std::io::Write;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+/// let mut stream = TcpShutdownGuard::new(stream)
+///     .drain(Duration::from_secs(1))
+///     .on_error(ErrorPolicy::Log);
+///
+/// stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+/// // The write half is shut down, and the response drained, when `stream` goes out of scope
+/// ```
+#[must_use = "TcpShutdownGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, shutting down the stream!"]
+pub struct TcpShutdownGuard {
+    stream: Option<TcpStream>,
+    drain: Option<Duration>,
+    policy: ErrorPolicy,
+}
+
+impl TcpShutdownGuard {
+    /// Wraps a stream in a guard, which only shuts down its write half (without draining), ignoring errors.
+    ///
+    /// **Note: `TcpShutdownGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, shutting down the stream!**
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream: Some(stream),
+            drain: None,
+            policy: ErrorPolicy::Ignore,
+        }
+    }
+
+    /// Drains the read half of the stream (for at most `timeout`) after shutting down its write half.
+    pub fn drain(mut self, timeout: Duration) -> Self {
+        self.drain = Some(timeout);
+        self
+    }
+
+    /// Sets the [`ErrorPolicy`] for errors shutting down (or draining) the stream.
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Shuts down (and drains) the stream now, returning the error instead of applying the [`ErrorPolicy`].
+    pub fn shutdown(mut self) -> io::Result<()> {
+        // `self.stream` can only be `None` once the guard is consumed
+        let stream = self.stream.take().unwrap();
+        shutdown(&stream, self.drain)
+    }
+
+    /// Consumes the guard, returning the stream without shutting it down.
+    pub fn into_inner(mut self) -> TcpStream {
+        self.stream.take().unwrap()
+    }
+}
+
+fn shutdown(mut stream: &TcpStream, drain: Option<Duration>) -> io::Result<()> {
+    stream.shutdown(Shutdown::Write)?;
+    if let Some(timeout) = drain {
+        stream.set_read_timeout(Some(timeout))?;
+        let mut buf = [0; 1024];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // The peer didn't close its end in time
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Deref for TcpShutdownGuard {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        self.stream.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for TcpShutdownGuard {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+impl Drop for TcpShutdownGuard {
+    fn drop(&mut self) {
+        let Some(stream) = self.stream.take() else {
+            return;
+        };
+        if let Err(err) = shutdown(&stream, self.drain) {
+            self.policy.handle("shut down a TCP stream", err);
+        }
+    }
+}
+
+type Flush = Box<dyn FnOnce() -> io::Result<()>>;
+
+/// A guard flushing buffered output (e.g. the global logger, or a tracing subscriber's writer) when it goes out of scope.
+///
+/// Placed at the top of `main`, it makes sure buffered logs survive early returns (e.g. using `?`) and panics.
+/// The crate doesn't depend on any logging crate, the flushing itself is done by closures, e.g. `|| { log::logger().flush(); Ok(()) }`.
+/// Several sinks can be flushed by the same guard (in order of registration), using [`FlushGuard::and`].
+///
+/// Nothing is flushed if the process exits without unwinding (e.g. [`std::process::exit`], or `panic = "abort"`).
+///
+/// **Note: `FlushGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, flushing the output!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{ErrorPolicy, FlushGuard};
+/// use std::io::Write;
+///
+/// fn main() -> std::io::Result<()> {
+///     let _flush = FlushGuard::stdio()
+///         .and(|| {
+///             // e.g. `log::logger().flush()`
+///             Ok(())
+///         })
+///         .on_error(ErrorPolicy::Log);
+///
+///     let mut out = std::io::BufWriter::new(std::io::stdout());
+///     writeln!(out, "Starting...")?;
+///     // ... the rest of `main` ...
+///     Ok(())
+/// }
+/// ```
+#[must_use = "FlushGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, flushing the output!"]
+pub struct FlushGuard {
+    flushes: Vec<Flush>,
+    policy: ErrorPolicy,
+}
+
+impl FlushGuard {
+    /// Creates a new `FlushGuard`, calling `flush` when it goes out of scope, ignoring errors.
+    ///
+    /// **Note: `FlushGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, flushing the output!**
+    pub fn new(flush: impl FnOnce() -> io::Result<()> + 'static) -> Self {
+        Self {
+            flushes: vec![Box::new(flush)],
+            policy: ErrorPolicy::Ignore,
+        }
+    }
+
+    /// Creates a new `FlushGuard`, flushing `stdout`, then `stderr`, when it goes out of scope, ignoring errors.
+    ///
+    /// **Note: `FlushGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, flushing the output!**
+    pub fn stdio() -> Self {
+        Self::new(|| io::Write::flush(&mut io::stdout()))
+            .and(|| io::Write::flush(&mut io::stderr()))
+    }
+
+    /// Adds another flush, called after the ones added before it.
+    pub fn and(mut self, flush: impl FnOnce() -> io::Result<()> + 'static) -> Self {
+        self.flushes.push(Box::new(flush));
+        self
+    }
+
+    /// Sets the [`ErrorPolicy`] for errors flushing the output.
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Flushes the output now, returning the first error instead of applying the [`ErrorPolicy`] (every flush is called regardless).
+    pub fn flush(mut self) -> io::Result<()> {
+        let mut res = Ok(());
+        for flush in std::mem::take(&mut self.flushes) {
+            res = res.and(flush());
+        }
+        res
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        let flushes = std::mem::take(&mut self.flushes);
+        if flushes.is_empty() || crate::debug::skipped(|| "flushing the output".into()) {
+            return;
+        }
+        let _span = crate::hooks::executing::<Self>("FlushGuard");
+        for flush in flushes {
+            if let Err(err) = flush() {
+                self.policy.handle("flush the output", err);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use fd::FdCloseGuard;
+
+#[cfg(unix)]
+mod fd {
+    use super::ErrorPolicy;
+    use std::io;
+    use std::os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd, RawFd};
+
+    extern "C" {
+        fn close(fd: RawFd) -> i32;
+    }
+
+    /// A guard closing a file descriptor when it goes out of scope, applying an [`ErrorPolicy`] to the error of closing it.
+    ///
+    /// Dropping an [`OwnedFd`] (or a [`File`](std::fs::File)) closes it, ignoring any error, but some errors (e.g. of
+    /// a delayed write on a network filesystem) are only reported when the file descriptor is closed.
+    ///
+    /// **Note: `FdCloseGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, closing the file descriptor!**
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::{ErrorPolicy, FdCloseGuard};
+    ///
+    /// let path = std::env::temp_dir().join("defer-rs-fd-close-guard");
+    /// let file = std::fs::File::create(&path).unwrap();
+    /// let fd = FdCloseGuard::new(file).on_error(ErrorPolicy::Panic);
+    ///
+    /// // ... use the file descriptor (e.g. through `AsFd`) ...
+    ///
+    /// // Closing it explicitly returns the error, instead of applying the policy
+    /// fd.close().unwrap();
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    #[must_use = "FdCloseGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, closing the file descriptor!"]
+    pub struct FdCloseGuard {
+        fd: Option<OwnedFd>,
+        policy: ErrorPolicy,
+    }
+
+    impl FdCloseGuard {
+        /// Wraps a file descriptor in a guard, ignoring errors closing it.
+        ///
+        /// **Note: `FdCloseGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, closing the file descriptor!**
+        pub fn new(fd: impl Into<OwnedFd>) -> Self {
+            Self {
+                fd: Some(fd.into()),
+                policy: ErrorPolicy::Ignore,
+            }
+        }
+
+        /// Sets the [`ErrorPolicy`] for errors closing the file descriptor.
+        pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+            self.policy = policy;
+            self
+        }
+
+        /// Closes the file descriptor now, returning the error instead of applying the [`ErrorPolicy`].
+        pub fn close(mut self) -> io::Result<()> {
+            // `self.fd` can only be `None` once the guard is consumed
+            close_fd(self.fd.take().unwrap())
+        }
+
+        /// Consumes the guard, returning the file descriptor without closing it.
+        pub fn into_inner(mut self) -> OwnedFd {
+            self.fd.take().unwrap()
+        }
+    }
+
+    fn close_fd(fd: OwnedFd) -> io::Result<()> {
+        // SAFETY: the file descriptor is owned, and is never used again
+        if unsafe { close(fd.into_raw_fd()) } == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    impl AsFd for FdCloseGuard {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.fd.as_ref().unwrap().as_fd()
+        }
+    }
+
+    impl AsRawFd for FdCloseGuard {
+        fn as_raw_fd(&self) -> RawFd {
+            self.fd.as_ref().unwrap().as_raw_fd()
+        }
+    }
+
+    impl Drop for FdCloseGuard {
+        fn drop(&mut self) {
+            let Some(fd) = self.fd.take() else {
+                return;
+            };
+            if let Err(err) = close_fd(fd) {
+                self.policy.handle("close a file descriptor", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_tcp_shutdown_guard() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let server_thread = std::thread::spawn(move || {
+            // Blocks until the client shuts down its write half
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        {
+            let mut client = TcpShutdownGuard::new(client)
+                .drain(Duration::from_secs(5))
+                .on_error(ErrorPolicy::Panic);
+            std::io::Write::write_all(&mut *client, b"bye").unwrap();
+        }
+        assert_eq!(server_thread.join().unwrap(), b"bye");
+    }
+
+    #[test]
+    fn test_flush_guard() {
+        let rec = crate::testing::ExecutionRecorder::new();
+        {
+            let failed = rec.clone();
+            let _flush = FlushGuard::new(move || {
+                failed.record("failed");
+                Err(io::Error::other("disk full"))
+            })
+            .and({
+                let logger = rec.callback("logger");
+                || {
+                    logger();
+                    Ok(())
+                }
+            });
+        }
+        rec.assert_order(&["failed", "logger"]);
+
+        let res = FlushGuard::new(|| Err(io::Error::other("disk full")))
+            .and(|| Ok(()))
+            .flush();
+        assert_eq!(res.unwrap_err().to_string(), "disk full");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fd_close_guard() {
+        let path = std::env::temp_dir().join(format!(
+            "defer-rs-test-fd-close-guard-{}",
+            std::process::id()
+        ));
+        {
+            let _fd = FdCloseGuard::new(std::fs::File::create(&path).unwrap())
+                .on_error(ErrorPolicy::Panic);
+        }
+        FdCloseGuard::new(std::fs::File::open(&path).unwrap())
+            .close()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+use std::sync::{Mutex, PoisonError};
+
+/// A thread-safe counterpart to [`DeferGroup`](crate::DeferGroup).
+///
+/// `SyncDeferGroup` can be shared by reference between threads (or parallel tasks), all of which can queue
+/// closures on it through a shared `&SyncDeferGroup`. The queued closures must be `Send`, and are
+/// executed exactly once, first to last, when the `SyncDeferGroup` instance goes out of scope.
+///
+/// **Note: `SyncDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::SyncDeferGroup;
+///
+/// let defer_group = SyncDeferGroup::new();
+///
+/// std::thread::scope(|s| {
+///     for i in 0..4 {
+///         let defer_group = &defer_group;
+///         s.spawn(move || {
+///             defer_group.add(Box::new(move || {
+///                 println!("Cleaning up after worker #{i}");
+///             }));
+///         });
+///     }
+/// });
+///
+/// // The deferred (queued) actions will be executed here, when the `defer_group` is dropped.
+/// ```
+///
+/// See also: [`sync_scope`], and [`DeferGroup`](crate::DeferGroup).
+#[must_use = "SyncDeferGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct SyncDeferGroup<'a>(Mutex<Vec<Box<dyn FnOnce() + Send + 'a>>>);
+
+impl<'a> SyncDeferGroup<'a> {
+    /// Creates a new `SyncDeferGroup`.
+    ///
+    /// **Note: `SyncDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub const fn new() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the `SyncDeferGroup` queue.
+    ///
+    /// The closures queued in `SyncDeferGroup` will be executed first to last
+    /// when the the `SyncDeferGroup` instance goes out of scope.
+    pub fn add(&self, f: Box<dyn FnOnce() + Send + 'a>) {
+        crate::hooks::registered("SyncDeferGroup");
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(0, f);
+    }
+
+    /// Pushes a deferred closure to the end of the `SyncDeferGroup` queue.
+    ///
+    /// The closures queued in `SyncDeferGroup` will be executed first to last
+    /// when the the `SyncDeferGroup` instance goes out of scope.
+    pub fn push(&self, f: Box<dyn FnOnce() + Send + 'a>) {
+        crate::hooks::registered("SyncDeferGroup");
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(f);
+    }
+
+    /// Executes the queued closures immediately (first to last), removing them from the `SyncDeferGroup` queue.
+    ///
+    /// Unlike dropping the group, this only needs a shared reference, so a group that's never dropped (e.g. a `static`, or one shared
+    /// through an `Arc`) can still be executed, e.g. on shutdown (see [`SyncDeferGroup::on_shutdown`]). Closures queued afterwards are
+    /// executed by the next call, or when the group goes out of scope.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::SyncDeferGroup;
+    ///
+    /// static CLEANUP: SyncDeferGroup<'static> = SyncDeferGroup::new();
+    ///
+    /// CLEANUP.add(Box::new(|| println!("Removing the pid file...")));
+    /// // Statics are never dropped
+    /// CLEANUP.run_pending();
+    /// ```
+    pub fn run_pending(&self) {
+        let deferred = std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner));
+        run_sequentially(deferred);
+    }
+
+    /// Consumes the group, executing the queued closures concurrently (each on its own scoped thread), instead of first to last.
+    ///
+    /// This is an opt-in for closures with no ordering constraints between them (e.g. closing independent connections),
+    /// returning once all of them are done. If any closure panics, the panic is propagated once all of them are done.
+    pub fn run_parallel(mut self) {
+        let deferred = std::mem::take(self.0.get_mut().unwrap_or_else(PoisonError::into_inner));
+        run_concurrently(deferred, "SyncDeferGroup");
+    }
+}
+
+impl<'a> Default for SyncDeferGroup<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Drop for SyncDeferGroup<'a> {
+    fn drop(&mut self) {
+        let deferred = std::mem::take(self.0.get_mut().unwrap_or_else(PoisonError::into_inner));
+        run_sequentially(deferred);
+    }
+}
+
+fn run_sequentially(deferred: Vec<Box<dyn FnOnce() + Send + '_>>) {
+    if deferred.is_empty()
+        || crate::debug::skipped(|| {
+            format!(
+                "{} deferred closure(s) of a `SyncDeferGroup`",
+                deferred.len()
+            )
+        })
+    {
+        return;
+    }
+    for f in deferred {
+        let _span = crate::hooks::executing_queued("SyncDeferGroup");
+        f();
+    }
+}
+
+/// A [`DeferGroup`](crate::DeferGroup) whose closures must be `Send`, so the whole group can be moved between threads (or tasks).
+///
+/// Unlike [`SyncDeferGroup`], a `SendDeferGroup` isn't meant to be shared, closures are queued through a `&mut SendDeferGroup`
+/// (so no locking is needed), but it can be moved to a spawned thread, e.g. along with the resources it cleans up.
+/// The queued closures are executed first to last when the `SendDeferGroup` instance goes out of scope.
+///
+/// **Note: `SendDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::SendDeferGroup;
+///
+/// let mut defer_group = SendDeferGroup::new();
+/// defer_group.add(Box::new(|| println!("Worker cleaned up!")));
+///
+/// std::thread::spawn(move || {
+///     let _defer_group = defer_group;
+///     // ... do work ...
+///     // The deferred (queued) actions will be executed here, on the worker thread.
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+///
+/// See also: [`SyncDeferGroup`], and [`DeferGroup`](crate::DeferGroup).
+#[must_use = "SendDeferGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closure!"]
+pub struct SendDeferGroup<'a>(Vec<Box<dyn FnOnce() + Send + 'a>>);
+
+impl<'a> SendDeferGroup<'a> {
+    /// Creates a new `SendDeferGroup`.
+    ///
+    /// **Note: `SendDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the `SendDeferGroup` queue.
+    ///
+    /// The closures queued in `SendDeferGroup` will be executed first to last
+    /// when the the `SendDeferGroup` instance goes out of scope.
+    pub fn add(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        crate::hooks::registered("SendDeferGroup");
+        self.0.insert(0, f);
+    }
+
+    /// Pushes a deferred closure to the end of the `SendDeferGroup` queue.
+    ///
+    /// The closures queued in `SendDeferGroup` will be executed first to last
+    /// when the the `SendDeferGroup` instance goes out of scope.
+    pub fn push(&mut self, f: Box<dyn FnOnce() + Send + 'a>) {
+        crate::hooks::registered("SendDeferGroup");
+        self.0.push(f);
+    }
+
+    /// Returns the number of queued closures.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if no closures are queued.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Consumes the group, executing the queued closures concurrently (each on its own scoped thread), instead of first to last,
+    /// see [`SyncDeferGroup::run_parallel`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use defer_rs::SendDeferGroup;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mut defer_group = SendDeferGroup::new();
+    /// for _ in 0..4 {
+    ///     // A slow, independent teardown (e.g. a graceful disconnect)
+    ///     defer_group.push(Box::new(|| std::thread::sleep(Duration::from_millis(100))));
+    /// }
+    ///
+    /// let start = Instant::now();
+    /// defer_group.run_parallel();
+    /// assert!(start.elapsed() < Duration::from_millis(400));
+    /// ```
+    pub fn run_parallel(mut self) {
+        run_concurrently(std::mem::take(&mut self.0), "SendDeferGroup");
+    }
+}
+
+impl<'a> Default for SendDeferGroup<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Drop for SendDeferGroup<'a> {
+    fn drop(&mut self) {
+        if self.0.is_empty()
+            || crate::debug::skipped(|| {
+                format!("{} deferred closure(s) of a `SendDeferGroup`", self.0.len())
+            })
+        {
+            return;
+        }
+        for f in std::mem::take(&mut self.0) {
+            let _span = crate::hooks::executing_queued("SendDeferGroup");
+            f();
+        }
+    }
+}
+
+// Executes the closures on scoped threads (the first one on the current thread), returning once all of them are done
+pub(crate) fn run_concurrently<'a>(
+    deferred: Vec<Box<dyn FnOnce() + Send + 'a>>,
+    source: &'static str,
+) {
+    if deferred.is_empty()
+        || crate::debug::skipped(|| {
+            format!("{} deferred closure(s) of a `{source}`", deferred.len())
+        })
+    {
+        return;
+    }
+    let mut deferred = deferred.into_iter();
+    let first = deferred.next();
+    std::thread::scope(|s| {
+        for f in deferred {
+            s.spawn(move || {
+                let _span = crate::hooks::executing_queued(source);
+                f();
+            });
+        }
+        if let Some(f) = first {
+            let _span = crate::hooks::executing_queued(source);
+            f();
+        }
+    });
+}
+
+/// Runs `f` with a fresh [`SyncDeferGroup`] in scope, executing the closures queued on it once `f` returns.
+///
+/// This is meant to wrap a scoped parallel section (e.g. [`std::thread::scope`]), as it only returns once all the
+/// threads spawned within it complete, cleanups registered by the threads are guaranteed to run exactly once,
+/// after all of them are done.
+///
+/// There's no integration with thread pools (e.g. `rayon`), the guarantee only holds for scopes joining every task
+/// before returning: the group is passed by reference, so a task outliving `f` (e.g. one spawned with `rayon::spawn`)
+/// can't queue closures on it anyway.
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::sync_scope;
+/// use std::sync::Mutex;
+///
+/// let log = Mutex::new(Vec::new());
+///
+/// sync_scope(|group| {
+///     std::thread::scope(|s| {
+///         for i in 0..4 {
+///             let log = &log;
+///             s.spawn(move || {
+///                 log.lock().unwrap().push(format!("task #{i}"));
+///                 group.push(Box::new(move || log.lock().unwrap().push(format!("cleanup #{i}"))));
+///             });
+///         }
+///     });
+///     assert_eq!(log.lock().unwrap().len(), 4);
+/// });
+///
+/// let log = log.into_inner().unwrap();
+/// assert_eq!(log.len(), 8);
+/// assert!(log[4..].iter().all(|entry| entry.starts_with("cleanup")));
+/// ```
+///
+/// See also: [`SyncDeferGroup`], and [`run_scope`](crate::run_scope).
+pub fn sync_scope<'a, R, F>(f: F) -> R
+where
+    F: FnOnce(&SyncDeferGroup<'a>) -> R,
+{
+    let group = SyncDeferGroup::new();
+    let res = f(&group);
+    drop(group);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_sync_defer_group_runs_once_after_all_tasks() {
+        let ran = AtomicUsize::new(0);
+
+        sync_scope(|group| {
+            std::thread::scope(|s| {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        group.add(Box::new(|| {
+                            ran.fetch_add(1, Ordering::SeqCst);
+                        }))
+                    });
+                }
+            });
+            assert_eq!(ran.load(Ordering::SeqCst), 0);
+        });
+
+        assert_eq!(ran.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        // Each closure waits for all of them to start, which only completes if they're executed concurrently
+        let barrier = std::sync::Barrier::new(4);
+        let ran = AtomicUsize::new(0);
+        let group = SyncDeferGroup::new();
+        for _ in 0..4 {
+            group.push(Box::new(|| {
+                barrier.wait();
+                ran.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        group.run_parallel();
+        assert_eq!(ran.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_send_defer_group_moves_between_threads() {
+        let rec = crate::testing::ExecutionRecorder::new();
+        let mut group = SendDeferGroup::new();
+        group.push(Box::new(rec.callback("2nd")));
+        group.add(Box::new(rec.callback("1st")));
+        assert_eq!(group.len(), 2);
+
+        std::thread::spawn(move || drop(group)).join().unwrap();
+        rec.assert_order(&["1st", "2nd"]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::io::{self, Stdout, Write};
+
+const ENTER_ALTERNATE_SCREEN: &[u8] = b"\x1b[?1049h";
+const LEAVE_ALTERNATE_SCREEN: &[u8] = b"\x1b[?1049l";
+const HIDE_CURSOR: &[u8] = b"\x1b[?25l";
+const SHOW_CURSOR: &[u8] = b"\x1b[?25h";
+
+/// A guard restoring the terminal's state (raw mode, alternate screen, and cursor) when it goes out of scope, behind the `terminal` feature.
+///
+/// Each setting changed through the guard (e.g. [`TerminalGuard::enter_alternate_screen`]) is undone when the guard goes out of scope,
+/// in reverse order, including when unwinding, so a crashing TUI app doesn't leave the user's terminal garbled.
+/// The alternate screen and the cursor are controlled using ANSI escape sequences, and raw mode using `stty` (on Unix).
+/// State set by a terminal library (e.g. crossterm's raw mode, or mouse capture) can be restored as well, using [`TerminalGuard::on_restore`].
+///
+/// As the panic message is printed before unwinding starts (i.e. on the alternate screen, which is then left), apps should also restore
+/// the terminal from a panic hook (e.g. using [`PanicHookGuard::chain`](crate::PanicHookGuard::chain)), [`TerminalGuard::restore`] is idempotent.
+/// Nothing is restored if the process aborts on panic (`panic = "abort"`), or exits without unwinding (e.g. [`std::process::exit`]).
+///
+/// **Note: `TerminalGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the terminal!**
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use defer_rs::TerminalGuard;
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut terminal = TerminalGuard::new();
+///     terminal.enable_raw_mode()?;
+///     terminal.enter_alternate_screen()?;
+///     terminal.hide_cursor()?;
+///
+///     // ... run the TUI, the terminal is restored even if it panics ...
+///
+///     terminal.restore()
+/// }
+/// ```
+#[must_use = "TerminalGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the terminal!"]
+pub struct TerminalGuard<W: Write = Stdout> {
+    out: W,
+    alternate_screen: bool,
+    cursor_hidden: bool,
+    #[cfg(unix)]
+    saved_mode: Option<String>,
+    on_restore: Vec<Box<dyn FnOnce()>>,
+}
+
+impl TerminalGuard {
+    /// Creates a new `TerminalGuard` controlling the terminal through stdout, without changing any setting yet.
+    ///
+    /// **Note: `TerminalGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the terminal!**
+    pub fn new() -> Self {
+        Self::with_writer(io::stdout())
+    }
+}
+
+impl Default for TerminalGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> TerminalGuard<W> {
+    /// Creates a new `TerminalGuard` writing the escape sequences to `out`, without changing any setting yet.
+    ///
+    /// **Note: `TerminalGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, restoring the terminal!**
+    pub fn with_writer(out: W) -> Self {
+        Self {
+            out,
+            alternate_screen: false,
+            cursor_hidden: false,
+            #[cfg(unix)]
+            saved_mode: None,
+            on_restore: Vec::new(),
+        }
+    }
+
+    /// Switches to the alternate screen, left when the terminal is restored.
+    pub fn enter_alternate_screen(&mut self) -> io::Result<()> {
+        self.write(ENTER_ALTERNATE_SCREEN)?;
+        self.alternate_screen = true;
+        Ok(())
+    }
+
+    /// Hides the cursor, shown again when the terminal is restored.
+    pub fn hide_cursor(&mut self) -> io::Result<()> {
+        self.write(HIDE_CURSOR)?;
+        self.cursor_hidden = true;
+        Ok(())
+    }
+
+    /// Puts the terminal (stdin's) in raw mode (using `stty`), the previous mode is restored when the terminal is restored.
+    #[cfg(unix)]
+    pub fn enable_raw_mode(&mut self) -> io::Result<()> {
+        if self.saved_mode.is_none() {
+            let saved = stty(&["-g"])?;
+            stty(&["raw", "-echo"])?;
+            self.saved_mode = Some(saved.trim().to_string());
+        }
+        Ok(())
+    }
+
+    /// Registers a closure executed when the terminal is restored, before the guard's own settings are restored
+    /// (and before the closures registered earlier), e.g. `|| crossterm::terminal::disable_raw_mode().unwrap()`.
+    pub fn on_restore(&mut self, f: impl FnOnce() + 'static) {
+        self.on_restore.push(Box::new(f));
+    }
+
+    /// Restores the terminal immediately, undoing every setting changed through the guard (in reverse order).
+    ///
+    /// Restoring an already restored terminal does nothing. Every setting is restored even if restoring one of them fails,
+    /// the first error is returned.
+    pub fn restore(&mut self) -> io::Result<()> {
+        while let Some(f) = self.on_restore.pop() {
+            f();
+        }
+        let mut res = Ok(());
+        if self.cursor_hidden {
+            self.cursor_hidden = false;
+            res = res.and(self.write(SHOW_CURSOR));
+        }
+        if self.alternate_screen {
+            self.alternate_screen = false;
+            res = res.and(self.write(LEAVE_ALTERNATE_SCREEN));
+        }
+        #[cfg(unix)]
+        if let Some(saved) = self.saved_mode.take() {
+            res = res.and(stty(&[&saved]).map(drop));
+        }
+        res
+    }
+
+    fn write(&mut self, sequence: &[u8]) -> io::Result<()> {
+        self.out.write_all(sequence)?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Drop for TerminalGuard<W> {
+    fn drop(&mut self) {
+        let _span = crate::hooks::executing::<Self>("TerminalGuard");
+        // There's nobody to report the error to, the terminal is restored as far as possible
+        let _ = self.restore();
+    }
+}
+
+// Runs `stty` on the terminal attached to stdin, returning its output
+#[cfg(unix)]
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = std::process::Command::new("stty")
+        .args(args)
+        .stdin(std::process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ExecutionRecorder;
+
+    #[test]
+    fn test_terminal_guard_restores_in_reverse_order() {
+        let rec = ExecutionRecorder::new();
+        let mut out = Vec::new();
+        {
+            let mut terminal = TerminalGuard::with_writer(&mut out);
+            terminal.enter_alternate_screen().unwrap();
+            terminal.hide_cursor().unwrap();
+            terminal.on_restore(rec.callback("library state"));
+        }
+        rec.assert_order(&["library state"]);
+        assert_eq!(
+            out,
+            [
+                ENTER_ALTERNATE_SCREEN,
+                HIDE_CURSOR,
+                SHOW_CURSOR,
+                LEAVE_ALTERNATE_SCREEN
+            ]
+            .concat()
+        );
+    }
+}
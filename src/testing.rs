@@ -0,0 +1,254 @@
+//! Utilities for testing deferred cleanup.
+//!
+//! [`ExecutionRecorder`] is a cloneable handle deferred closures can record labels into, along with
+//! assertion helpers for checking which cleanups ran, and in what order.
+//!
+//! # Example
+//!
+//! ```rust
+//! use defer_rs::{defer, testing::ExecutionRecorder, DeferGroup};
+//!
+//! let rec = ExecutionRecorder::new();
+//! {
+//!     defer!(rec.record("defer!"));
+//!     let mut group = DeferGroup::new();
+//!     group.add(Box::new(rec.callback("group")));
+//! }
+//!
+//! rec.assert_order(&["group", "defer!"]);
+//! ```
+
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A cloneable handle recording labels in the order they're recorded, see the [module level documentation](self).
+///
+/// All clones of an `ExecutionRecorder` share the same record. It's `Send` and `Sync`, so it can be used from closures
+/// queued on [`SyncDeferGroup`](crate::SyncDeferGroup)s, or in the [`registry`](crate::registry).
+#[derive(Clone, Default)]
+pub struct ExecutionRecorder(Arc<Mutex<Vec<String>>>);
+
+impl ExecutionRecorder {
+    /// Creates a new, empty `ExecutionRecorder`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a label.
+    pub fn record(&self, label: impl Into<String>) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(label.into());
+    }
+
+    /// Returns a closure recording `label` once called, ready to be queued as a deferred closure.
+    pub fn callback(&self, label: impl Into<String>) -> impl FnOnce() + Send + 'static {
+        let (rec, label) = (self.clone(), label.into());
+        move || rec.record(label)
+    }
+
+    /// Returns a copy of the recorded labels, in the order they were recorded.
+    pub fn labels(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Returns the number of recorded labels.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Returns `true` if no labels were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all the recorded labels.
+    pub fn clear(&self) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Asserts that exactly the `expected` labels were recorded, in the same order.
+    #[track_caller]
+    pub fn assert_order(&self, expected: &[&str]) {
+        let labels = self.labels();
+        assert!(
+            labels.iter().map(String::as_str).eq(expected.iter().copied()),
+            "recorded labels don't match the expected order\n  recorded: {labels:?}\n  expected: {expected:?}",
+        );
+    }
+
+    /// Asserts that `label` was recorded (at least once).
+    #[track_caller]
+    pub fn assert_contains(&self, label: &str) {
+        let labels = self.labels();
+        assert!(
+            labels.iter().any(|l| l == label),
+            "`{label}` wasn't recorded\n  recorded: {labels:?}",
+        );
+    }
+
+    /// Asserts that `label` wasn't recorded.
+    #[track_caller]
+    pub fn assert_not_contains(&self, label: &str) {
+        let labels = self.labels();
+        assert!(
+            labels.iter().all(|l| l != label),
+            "`{label}` was recorded\n  recorded: {labels:?}",
+        );
+    }
+
+    /// Asserts that both labels were recorded, and that (the first recording of) `first` precedes (the first recording of) `second`.
+    #[track_caller]
+    pub fn assert_before(&self, first: &str, second: &str) {
+        let labels = self.labels();
+        let position = |label| labels.iter().position(|l| l == label);
+        match (position(first), position(second)) {
+            (Some(a), Some(b)) => assert!(
+                a < b,
+                "`{first}` wasn't recorded before `{second}`\n  recorded: {labels:?}",
+            ),
+            _ => panic!("`{first}` and `{second}` weren't both recorded\n  recorded: {labels:?}"),
+        }
+    }
+}
+
+impl fmt::Debug for ExecutionRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ExecutionRecorder")
+            .field(&self.labels())
+            .finish()
+    }
+}
+
+/// The group of teardowns used by the [`defer_test`](crate::defer_test) attribute, registered to using [`defer_scope!`](crate::defer_scope).
+///
+/// Like a [`DeferGroup`](crate::DeferGroup), the queued teardowns are executed (first to last) when the `TeardownGroup`
+/// goes out of scope, even if the test fails. Unlike it, every teardown is executed even if a previous one panics,
+/// and a report of the executed teardowns is printed to `stderr` (which the test harness shows for failed tests).
+/// Teardowns are identified by their registration order (`#1` being the first registered).
+///
+/// If a teardown panics while the test otherwise passed, the test is failed once all the teardowns are executed.
+#[must_use = "TeardownGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!"]
+pub struct TeardownGroup<'a> {
+    test: &'static str,
+    teardowns: Vec<(usize, Box<dyn FnOnce() + 'a>)>,
+    registered: usize,
+}
+
+impl<'a> TeardownGroup<'a> {
+    /// Creates a new `TeardownGroup` for the test named `test`.
+    pub fn new(test: &'static str) -> Self {
+        Self {
+            test,
+            teardowns: Vec::new(),
+            registered: 0,
+        }
+    }
+
+    /// Adds a teardown to the start (0-index) of the `TeardownGroup` queue.
+    pub fn add(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        self.registered += 1;
+        self.teardowns.insert(0, (self.registered, f));
+    }
+
+    /// Pushes a teardown to the end of the `TeardownGroup` queue.
+    pub fn push(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        self.registered += 1;
+        self.teardowns.push((self.registered, f));
+    }
+}
+
+impl<'a> Drop for TeardownGroup<'a> {
+    fn drop(&mut self) {
+        let test_failed = std::thread::panicking();
+        if !self.teardowns.is_empty()
+            && crate::debug::skipped(|| {
+                format!("{} teardown(s) of `{}`", self.teardowns.len(), self.test)
+            })
+        {
+            return;
+        }
+        let total = self.teardowns.len();
+        let mut executed = Vec::new();
+        let mut failed = Vec::new();
+
+        for (id, teardown) in std::mem::take(&mut self.teardowns) {
+            executed.push(format!("#{id}"));
+            if catch_unwind(AssertUnwindSafe(teardown)).is_err() {
+                failed.push(format!("#{id}"));
+            }
+        }
+
+        eprintln!(
+            "defer_test `{}`: executed {}/{total} teardown(s) [{}]{}",
+            self.test,
+            executed.len(),
+            executed.join(", "),
+            if failed.is_empty() {
+                String::new()
+            } else {
+                format!(", panicked: [{}]", failed.join(", "))
+            },
+        );
+
+        if !failed.is_empty() && !test_failed {
+            panic!(
+                "defer_test `{}`: teardown(s) [{}] panicked",
+                self.test,
+                failed.join(", ")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execution_recorder_assertions() {
+        let rec = ExecutionRecorder::new();
+        rec.record("a");
+        rec.clone().callback("b")();
+
+        rec.assert_order(&["a", "b"]);
+        rec.assert_contains("b");
+        rec.assert_not_contains("c");
+        rec.assert_before("a", "b");
+        assert!(std::panic::catch_unwind(|| rec.assert_before("b", "a")).is_err());
+        assert!(std::panic::catch_unwind(|| rec.assert_order(&["b", "a"])).is_err());
+
+        rec.clear();
+        assert!(rec.is_empty());
+    }
+
+    #[test]
+    fn test_teardown_group_runs_all_teardowns() {
+        let rec = ExecutionRecorder::new();
+        let res = catch_unwind(|| {
+            let mut group = TeardownGroup::new("test");
+            group.add(Box::new(rec.callback("1st registered")));
+            group.add(Box::new(|| panic!("teardown failed")));
+            group.add(Box::new(rec.callback("3rd registered")));
+        });
+
+        assert!(res.is_err());
+        rec.assert_order(&["3rd registered", "1st registered"]);
+    }
+
+    #[crate::defer_test]
+    fn test_defer_test_attribute() {
+        let rec = ExecutionRecorder::new();
+        let inner = rec.clone();
+        crate::defer_scope!(move inner.assert_order(&["body"]));
+        rec.record("body");
+    }
+}
@@ -0,0 +1,172 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::RollbackGuard;
+
+/// A transaction that can be committed or rolled back, e.g. a database transaction.
+///
+/// Implement this trait for your database's transaction type (or a thin wrapper around it) to use it with [`TransactionGuard`].
+pub trait Transaction {
+    /// The error returned when committing the transaction fails.
+    type Error;
+
+    /// Commits the transaction.
+    fn commit(self) -> Result<(), Self::Error>;
+
+    /// Rolls back the transaction.
+    fn rollback(self);
+}
+
+/// A guard rolling back a [`Transaction`] when it goes out of scope (including due to a panic), unless it was explicitly committed.
+///
+/// Besides the transaction itself, the guard can hold rollback steps for side effects performed outside of the
+/// transaction (e.g. files written, or messages sent), see [`TransactionGuard::add_rollback_step`]. These are executed
+/// (last to first) after the transaction is rolled back, or if committing it fails.
+///
+/// The guard dereferences to the transaction, so it can be used as usual while the guard is held.
+///
+/// **Note: `TransactionGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, rolling back the transaction!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::{Transaction, TransactionGuard};
+///
+/// struct Tx<'a>(&'a mut Vec<&'static str>);
+///
+/// impl Transaction for Tx<'_> {
+///     type Error = ();
+///     fn commit(self) -> Result<(), ()> {
+///         self.0.push("COMMIT");
+///         Ok(())
+///     }
+///     fn rollback(self) {
+///         self.0.push("ROLLBACK");
+///     }
+/// }
+///
+/// fn transfer(log: &mut Vec<&'static str>, fail: bool) -> Result<(), ()> {
+///     let mut tx = TransactionGuard::begin(Tx(log));
+///     tx.0.push("UPDATE accounts ...");
+///     if fail {
+///         return Err(());
+///     }
+///     tx.commit()
+/// }
+///
+/// let mut log = Vec::new();
+/// transfer(&mut log, false).unwrap();
+/// transfer(&mut log, true).unwrap_err();
+/// assert_eq!(log, ["UPDATE accounts ...", "COMMIT", "UPDATE accounts ...", "ROLLBACK"]);
+/// ```
+///
+/// See also: [`RollbackGuard`].
+#[must_use = "TransactionGuard MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, rolling back the transaction!"]
+pub struct TransactionGuard<'a, T: Transaction> {
+    tx: Option<T>,
+    steps: RollbackGuard<'a>,
+}
+
+impl<'a, T: Transaction> TransactionGuard<'a, T> {
+    /// Wraps an already started transaction in a guard.
+    ///
+    /// **Note: `TransactionGuard` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, rolling back the transaction!**
+    pub fn begin(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            steps: RollbackGuard::new(),
+        }
+    }
+
+    /// Adds a rollback step for a side effect performed outside of the transaction.
+    ///
+    /// Rollback steps are executed (last to first) after the transaction is rolled back, or if committing it fails.
+    pub fn add_rollback_step(&mut self, step: impl FnOnce() + 'a) {
+        self.steps.add_step(step);
+    }
+
+    /// Commits the transaction, discarding the rollback steps if it succeeds (and executing them if it fails).
+    pub fn commit(mut self) -> Result<(), T::Error> {
+        let steps = std::mem::take(&mut self.steps);
+        // `self.tx` can only be `None` once the guard is consumed
+        let res = self.tx.take().unwrap().commit();
+        if res.is_ok() {
+            steps.commit();
+        }
+        res
+    }
+
+    /// Explicitly rolls back the transaction (and executes the rollback steps).
+    ///
+    /// This is what happens when the guard goes out of scope, but makes the intent explicit.
+    pub fn rollback(self) {}
+}
+
+impl<'a, T: Transaction> Deref for TransactionGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // `self.tx` can only be `None` once the guard is consumed
+        self.tx.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: Transaction> DerefMut for TransactionGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.tx.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: Transaction> Drop for TransactionGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            tx.rollback();
+        }
+        // The rollback steps are executed after the transaction is rolled back, when `self.steps` is dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct Tx<'a>(&'a RefCell<Vec<&'static str>>, bool);
+
+    impl Transaction for Tx<'_> {
+        type Error = &'static str;
+        fn commit(self) -> Result<(), Self::Error> {
+            if self.1 {
+                self.0.borrow_mut().push("commit");
+                Ok(())
+            } else {
+                Err("commit failed")
+            }
+        }
+        fn rollback(self) {
+            self.0.borrow_mut().push("rollback");
+        }
+    }
+
+    #[test]
+    fn test_transaction_guard() {
+        let log = RefCell::new(Vec::new());
+
+        let mut tx = TransactionGuard::begin(Tx(&log, true));
+        tx.add_rollback_step(|| log.borrow_mut().push("step"));
+        tx.commit().unwrap();
+        assert_eq!(*log.borrow(), ["commit"]);
+
+        let mut tx = TransactionGuard::begin(Tx(&log, false));
+        tx.add_rollback_step(|| log.borrow_mut().push("step"));
+        assert_eq!(tx.commit(), Err("commit failed"));
+        assert_eq!(*log.borrow(), ["commit", "step"]);
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut tx = TransactionGuard::begin(Tx(&log, true));
+            tx.add_rollback_step(|| log.borrow_mut().push("step"));
+            panic!();
+        }));
+        assert!(res.is_err());
+        assert_eq!(*log.borrow(), ["commit", "step", "rollback", "step"]);
+    }
+}
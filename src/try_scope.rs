@@ -0,0 +1,137 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::DeferGroup;
+
+/// The group of a [`try_defer_scope!`](crate::try_defer_scope) block, holding both regular deferred closures, always executed,
+/// and error-only ones (registered using [`errdefer!`](crate::errdefer)), only executed if the block doesn't complete with an `Ok`.
+///
+/// All the closures share a single queue, so regular and error-only closures are executed in the usual order (last to first),
+/// interleaved with each other. Error-only closures are executed unless the group is [finished](TryDeferGroup::finish)
+/// with an `Ok`, i.e. they're executed if the block returns an `Err`, or unwinds.
+///
+/// **Note: `TryDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+///
+/// # Example
+///
+/// ```rust
+/// use defer_rs::TryDeferGroup;
+///
+/// let mut group = TryDeferGroup::new();
+/// group.add(Box::new(|| println!("Closing the connection...")));
+/// group.add_on_err(Box::new(|| println!("Removing the partially written file...")));
+///
+/// let res: Result<(), &str> = Err("disk full");
+/// // Both messages are printed
+/// let res = group.finish(res);
+/// ```
+#[must_use = "TryDeferGroup MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!"]
+pub struct TryDeferGroup<'a> {
+    group: DeferGroup<'a>,
+    failed: Rc<Cell<bool>>,
+}
+
+impl<'a> TryDeferGroup<'a> {
+    /// Creates a new, empty `TryDeferGroup`.
+    ///
+    /// **Note: `TryDeferGroup` MUST be bound to a variable to function properly; otherwise, it will be dropped immediately, executing the enclosed closures!**
+    pub fn new() -> Self {
+        Self {
+            group: DeferGroup::new(),
+            failed: Rc::new(Cell::new(true)),
+        }
+    }
+
+    /// Adds a deferred closure to the start (0-index) of the group's queue, see [`DeferGroup::add`].
+    pub fn add(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        self.group.add(f);
+    }
+
+    /// Pushes a deferred closure to the end of the group's queue, see [`DeferGroup::push`].
+    pub fn push(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        self.group.push(f);
+    }
+
+    /// Adds a deferred closure with the given priority to the group's queue, see [`DeferGroup::add_with_priority`].
+    pub fn add_with_priority(&mut self, priority: i32, f: Box<dyn FnOnce() + 'a>) {
+        self.group.add_with_priority(priority, f);
+    }
+
+    /// Pushes a deferred closure with the given priority to the group's queue, see [`DeferGroup::push_with_priority`].
+    pub fn push_with_priority(&mut self, priority: i32, f: Box<dyn FnOnce() + 'a>) {
+        self.group.push_with_priority(priority, f);
+    }
+
+    /// Adds an error-only deferred closure to the start (0-index) of the group's queue.
+    pub fn add_on_err(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        let f = self.on_err(f);
+        self.group.add(f);
+    }
+
+    /// Pushes an error-only deferred closure to the end of the group's queue.
+    pub fn push_on_err(&mut self, f: Box<dyn FnOnce() + 'a>) {
+        let f = self.on_err(f);
+        self.group.push(f);
+    }
+
+    /// Returns the number of deferred closures (regular and error-only) queued in the group.
+    pub fn len(&self) -> usize {
+        self.group.len()
+    }
+
+    /// Returns `true` if no deferred closures are queued in the group.
+    pub fn is_empty(&self) -> bool {
+        self.group.is_empty()
+    }
+
+    /// Executes the queued closures, the error-only ones included only if `res` is an `Err`, and returns `res`.
+    pub fn finish<T, E>(self, res: Result<T, E>) -> Result<T, E> {
+        self.failed.set(res.is_err());
+        drop(self);
+        res
+    }
+
+    fn on_err(&self, f: Box<dyn FnOnce() + 'a>) -> Box<dyn FnOnce() + 'a> {
+        let failed = self.failed.clone();
+        Box::new(move || {
+            if failed.get() {
+                f();
+            }
+        })
+    }
+}
+
+impl<'a> Default for TryDeferGroup<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::ExecutionRecorder;
+    use crate::{defer_scope, errdefer, try_defer_scope};
+
+    fn provision(rec: &ExecutionRecorder, fail: bool) -> Result<u8, String> {
+        try_defer_scope! {
+            defer_scope!(rec.record("close"));
+            errdefer!(rec.record("rollback"));
+            if fail {
+                Err("failed")?;
+            }
+            rec.record("commit");
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_try_defer_scope() {
+        let rec = ExecutionRecorder::new();
+        assert_eq!(provision(&rec, false), Ok(1));
+        rec.assert_order(&["commit", "close"]);
+
+        let rec = ExecutionRecorder::new();
+        assert_eq!(provision(&rec, true), Err("failed".to_string()));
+        rec.assert_order(&["rollback", "close"]);
+    }
+}
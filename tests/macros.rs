@@ -0,0 +1,36 @@
+//! Exercises the public macro syntax from outside the crate, the way a downstream crate uses it.
+
+use std::cell::RefCell;
+
+use defer_rs::{defer, defer_guard, defer_scope, defer_scope_init};
+
+fn record(log: &RefCell<Vec<String>>, entry: &str) {
+    log.borrow_mut().push(entry.to_owned());
+}
+
+#[test]
+fn test_defer_syntax() {
+    let log = RefCell::new(Vec::new());
+    {
+        defer_scope_init!();
+        defer!(priority = 1; record(&log, "priority 1"));
+        defer!(priority = 10; record(&log, "priority 10"));
+        defer_scope!(record(&log, "scope"));
+        defer!(record(&log, "call"));
+        let _guard = defer_guard!(record(&log, "guard"));
+        defer! {
+            record(&log, "block");
+        }
+    }
+    assert_eq!(
+        *log.borrow(),
+        [
+            "block",
+            "guard",
+            "call",
+            "priority 10",
+            "priority 1",
+            "scope"
+        ]
+    );
+}